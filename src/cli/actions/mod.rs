@@ -1,14 +1,15 @@
 pub mod run;
 
 use crate::cli::verbosity::Verbosity;
+use crate::killswitch::{Options, Update};
 use anyhow::Result;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Action {
     Enable {
-        ipv4: Option<String>,
-        leak: bool,
-        local: bool,
+        options: Options,
         verbose: Verbosity,
     },
     Disable {
@@ -18,14 +19,41 @@ pub enum Action {
         verbose: Verbosity,
     },
     Print {
-        ipv4: Option<String>,
-        leak: bool,
-        local: bool,
+        options: Options,
+        snapshot: Option<PathBuf>,
         verbose: Verbosity,
     },
     ShowInterfaces {
         verbose: Verbosity,
     },
+    Snapshot {
+        output: Option<PathBuf>,
+        verbose: Verbosity,
+    },
+    Refresh {
+        watch: bool,
+        verbose: Verbosity,
+    },
+    Update {
+        changes: Update,
+        verbose: Verbosity,
+    },
+    Blocked {
+        file: Option<PathBuf>,
+        time: Duration,
+        verbose: Verbosity,
+    },
+    Portal {
+        window: Duration,
+        verbose: Verbosity,
+    },
+    Lint {
+        file: PathBuf,
+        verbose: Verbosity,
+    },
+    Diff {
+        verbose: Verbosity,
+    },
 }
 
 impl Action {
@@ -46,9 +74,10 @@ mod tests {
     #[test]
     fn test_action_debug_format() {
         let action = Action::Enable {
-            ipv4: Some("10.8.0.1".to_string()),
-            leak: false,
-            local: false,
+            options: Options {
+                peers: vec!["10.8.0.1".to_string()],
+                ..Options::default()
+            },
             verbose: Verbosity::Normal,
         };
         let debug_str = format!("{action:?}");
@@ -59,9 +88,12 @@ mod tests {
     #[test]
     fn test_action_variants() {
         let enable = Action::Enable {
-            ipv4: None,
-            leak: true,
-            local: true,
+            options: Options {
+                allow_dns: Some(vec!["9.9.9.9".to_string()]),
+                allow_icmp: Some(Vec::new()),
+                local: Some(vec!["192.168.20.0/24".to_string()]),
+                ..Options::default()
+            },
             verbose: Verbosity::Verbose,
         };
         assert!(matches!(enable, Action::Enable { .. }));
@@ -77,11 +109,58 @@ mod tests {
         assert!(matches!(status, Action::Status { .. }));
 
         let print = Action::Print {
-            ipv4: Some("192.168.1.1".to_string()),
-            leak: false,
-            local: false,
+            options: Options {
+                peers: vec!["192.168.1.1".to_string()],
+                ..Options::default()
+            },
+            snapshot: None,
             verbose: Verbosity::Normal,
         };
         assert!(matches!(print, Action::Print { .. }));
+
+        let snapshot = Action::Snapshot {
+            output: None,
+            verbose: Verbosity::Normal,
+        };
+        assert!(matches!(snapshot, Action::Snapshot { .. }));
+
+        let refresh = Action::Refresh {
+            watch: true,
+            verbose: Verbosity::Normal,
+        };
+        assert!(matches!(refresh, Action::Refresh { .. }));
+
+        let update = Action::Update {
+            changes: Update {
+                add_peers: vec!["203.0.113.1".to_string()],
+                ..Update::default()
+            },
+            verbose: Verbosity::Normal,
+        };
+        assert!(matches!(update, Action::Update { .. }));
+
+        let blocked = Action::Blocked {
+            file: Some(PathBuf::from("drops.pcap")),
+            time: Duration::from_secs(30),
+            verbose: Verbosity::Normal,
+        };
+        assert!(matches!(blocked, Action::Blocked { .. }));
+
+        let lint = Action::Lint {
+            file: PathBuf::from("rules.conf"),
+            verbose: Verbosity::Normal,
+        };
+        assert!(matches!(lint, Action::Lint { .. }));
+
+        let diff = Action::Diff {
+            verbose: Verbosity::Normal,
+        };
+        assert!(matches!(diff, Action::Diff { .. }));
+
+        let portal = Action::Portal {
+            window: Duration::from_secs(120),
+            verbose: Verbosity::Normal,
+        };
+        assert!(matches!(portal, Action::Portal { .. }));
     }
 }
@@ -1,6 +1,12 @@
 use super::Action;
-use crate::killswitch;
-use anyhow::Result;
+use crate::cli::verbosity::Verbosity;
+use crate::killswitch::{self, Options, snapshot::NetworkSnapshot};
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::time::Duration;
+
+/// Delay before retrying a failed refresh in watch mode
+const RETRY_DELAY: Duration = Duration::from_secs(30);
 
 /// Execute the given action
 ///
@@ -8,25 +14,12 @@ use anyhow::Result;
 /// Returns an error if the killswitch operation fails
 pub fn execute(action: &Action) -> Result<()> {
     match action {
-        Action::Enable {
-            ipv4,
-            leak,
-            local,
-            verbose,
-        } => {
+        Action::Enable { options, verbose } => {
             if verbose.is_verbose() {
                 eprintln!("Enabling VPN kill switch...");
-                if let Some(ip) = ipv4 {
-                    eprintln!("  VPN gateway: {ip}");
-                }
-                if *leak {
-                    eprintln!("  Allowing ICMP and DNS");
-                }
-                if *local {
-                    eprintln!("  Allowing local network");
-                }
+                print_options(options);
             }
-            killswitch::enable(*leak, *local, ipv4.as_deref(), *verbose)?;
+            killswitch::enable(options, *verbose)?;
             println!("✓ VPN kill switch enabled");
         }
 
@@ -47,15 +40,15 @@ pub fn execute(action: &Action) -> Result<()> {
         }
 
         Action::Print {
-            ipv4,
-            leak,
-            local,
+            options,
+            snapshot,
             verbose,
         } => {
             if verbose.is_verbose() {
                 eprintln!("Generating pf rules...");
             }
-            let rules = killswitch::generate_rules(*leak, *local, ipv4.as_deref(), *verbose)?;
+            let snapshot = snapshot.as_deref().map(NetworkSnapshot::load).transpose()?;
+            let rules = killswitch::generate_rules(options, snapshot.as_ref(), *verbose)?;
             println!("{rules}");
         }
 
@@ -63,24 +56,258 @@ pub fn execute(action: &Action) -> Result<()> {
             let output = killswitch::show_interfaces(*verbose)?;
             print!("{output}");
         }
+
+        Action::Snapshot { output, verbose } => {
+            if verbose.is_verbose() {
+                eprintln!("Capturing network snapshot...");
+            }
+            let json = killswitch::snapshot(*verbose)?.to_json()?;
+            if let Some(path) = output {
+                std::fs::write(path, json + "\n")
+                    .with_context(|| format!("Failed to write snapshot {}", path.display()))?;
+                println!("✓ Snapshot written to {}", path.display());
+            } else {
+                println!("{json}");
+            }
+        }
+
+        Action::Refresh { watch, verbose } => {
+            if verbose.is_verbose() {
+                eprintln!("Refreshing VPN peers...");
+            }
+            if *watch {
+                watch_refresh(*verbose)?;
+            } else {
+                print_refreshed(&killswitch::refresh(*verbose)?);
+            }
+        }
+
+        Action::Update { changes, verbose } => {
+            if verbose.is_verbose() {
+                eprintln!("Updating kill switch tables...");
+            }
+            killswitch::update(changes, *verbose)?;
+            println!("✓ VPN kill switch updated");
+        }
+
+        Action::Blocked {
+            file,
+            time,
+            verbose,
+        } => {
+            if verbose.is_verbose() && file.is_none() {
+                eprintln!("Capturing blocked packets for {}s...", time.as_secs());
+            }
+            print_blocked(&killswitch::blocked(file.as_deref(), *time)?);
+        }
+
+        Action::Portal { window, verbose } => {
+            if verbose.is_verbose() {
+                eprintln!("Opening captive portal window...");
+            }
+            killswitch::portal(*window, *verbose)?;
+            println!(
+                "✓ Captive portal open for {}s, log in now (the kill switch comes back even if interrupted)",
+                window.as_secs()
+            );
+            std::thread::sleep(*window);
+            if killswitch::expire_portal(*verbose)? {
+                println!("✓ VPN kill switch restored");
+            }
+        }
+
+        Action::Lint { file, .. } => print_lint(file, &killswitch::lint(file)?)?,
+
+        Action::Diff { verbose } => print_diff(&killswitch::diff(*verbose)?),
+    }
+
+    Ok(())
+}
+
+/// Describe the rule settings (verbose mode)
+fn print_options(options: &Options) {
+    for ip in &options.peers {
+        eprintln!("  VPN gateway: {ip}");
+    }
+    if let Some(path) = &options.peer_file {
+        eprintln!("  VPN gateways from: {}", path.display());
+    }
+    if let Some(port) = options.peer_port {
+        eprintln!("  VPN peer port: {port}");
+    }
+    if let Some(proto) = options.peer_proto {
+        eprintln!("  VPN peer protocol: {proto}");
+    }
+    for dest in &options.allow {
+        eprintln!("  Allowing outside the VPN: {dest}");
+    }
+    if let Some(path) = &options.allow_file {
+        eprintln!("  Allowing outside the VPN from: {}", path.display());
+    }
+    match options.allow_dns.as_deref() {
+        Some([]) => eprintln!("  Allowing DNS"),
+        Some(resolvers) => eprintln!("  Allowing DNS to: {}", resolvers.join(", ")),
+        None => {}
+    }
+    match options.allow_icmp.as_deref() {
+        Some([]) => eprintln!("  Allowing ping"),
+        Some(types) => eprintln!("  Allowing ICMP: {}", types.join(", ")),
+        None => {}
+    }
+    if options.dns_lock {
+        eprintln!("  Locking DNS to the VPN resolvers");
+    }
+    match options.local.as_deref() {
+        Some([]) => eprintln!("  Allowing local network"),
+        Some(subnets) => eprintln!("  Allowing local networks: {}", subnets.join(", ")),
+        None => {}
+    }
+    if options.exclude_gateway {
+        eprintln!("  Excluding the default gateway");
+    }
+    if !options.inbound.is_empty() {
+        let ports: Vec<String> = options.inbound.iter().map(ToString::to_string).collect();
+        eprintln!("  Inbound ports open: {}", ports.join(", "));
+    }
+    if !options.deny.is_empty() {
+        let deny: Vec<String> = options.deny.iter().map(ToString::to_string).collect();
+        eprintln!("  Denying on every interface: {}", deny.join(", "));
+    }
+    if let Some(path) = &options.deny_file {
+        eprintln!("  Deny list from: {}", path.display());
+    }
+    if options.anchor {
+        eprintln!("  Loading the rules into a dedicated pf anchor");
+    }
+    if options.log {
+        eprintln!("  Logging blocked packets to pflog0");
+    }
+    if !options.only_ifaces.is_empty() {
+        eprintln!("  Only interfaces: {}", options.only_ifaces.join(", "));
+    }
+    if !options.ignore_ifaces.is_empty() {
+        eprintln!(
+            "  Ignoring interfaces: {}",
+            options.ignore_ifaces.join(", ")
+        );
+    }
+    eprintln!("  Bridges: {}", options.bridges);
+    eprintln!(
+        "  Broadcast: {}, multicast: {}, mDNS: {}, SSDP: {}",
+        options.broadcast, options.multicast, options.mdns, options.ssdp
+    );
+    if let Some(preset) = options.preset {
+        eprintln!("  Preset: {preset}");
+    }
+    for (title, accounts) in [
+        ("Exempt users", &options.exempt_users),
+        ("Exempt groups", &options.exempt_groups),
+        ("Enforcing only for users", &options.only_users),
+        ("Enforcing only for groups", &options.only_groups),
+    ] {
+        if !accounts.is_empty() {
+            eprintln!("  {title}: {}", accounts.join(", "));
+        }
+    }
+    if let Some(dir) = &options.fragments {
+        eprintln!("  Fragments from: {}", dir.display());
+    }
+    if options.unsafe_fragments {
+        eprintln!("  Allowing fragments to pass on the physical interfaces");
     }
+}
 
+fn print_lint(file: &Path, diagnostics: &[killswitch::lint::Diagnostic]) -> Result<()> {
+    for diagnostic in diagnostics {
+        println!("{diagnostic}");
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == killswitch::lint::Severity::Error)
+        .count();
+    if errors > 0 {
+        bail!("{errors} error(s) in {}", file.display());
+    }
+    if diagnostics.is_empty() {
+        println!("✓ No issues found");
+    }
     Ok(())
 }
 
+fn print_diff(changes: &[killswitch::diff::Change]) {
+    if changes.is_empty() {
+        println!("✓ The loaded rules are up to date");
+        return;
+    }
+    for change in changes {
+        println!("{change}");
+    }
+    if changes.iter().all(killswitch::diff::Change::is_table) {
+        println!("\nRun `killswitch refresh` to update the tables");
+    } else {
+        println!("\nEnable the kill switch again to load the fresh rules");
+    }
+}
+
+fn print_blocked(flows: &[killswitch::pflog::Flow]) {
+    if flows.is_empty() {
+        println!("No blocked packets (is the kill switch enabled with --log?)");
+        return;
+    }
+    println!("{:>8}  DESTINATION", "PACKETS");
+    for flow in flows {
+        println!("{:>8}  {flow}", flow.packets);
+    }
+}
+
+fn print_refreshed(refreshed: &killswitch::Refreshed) {
+    let peers: Vec<String> = refreshed.peers.iter().map(ToString::to_string).collect();
+    println!("✓ VPN peers refreshed: {}", peers.join(", "));
+    if !refreshed.bypass.is_empty() {
+        println!("✓ Bypassed addresses: {}", refreshed.bypass.join(", "));
+    }
+}
+
+/// Refresh until interrupted, following the DNS TTL of the bypassed domains.
+///
+/// Failures (e.g. DNS unreachable while the VPN reconnects) keep the current
+/// tables and are retried; only a missing kill switch stops the loop.
+fn watch_refresh(verbose: Verbosity) -> Result<()> {
+    let mut refreshed = killswitch::refresh(verbose)?;
+    loop {
+        print_refreshed(&refreshed);
+        if verbose.is_verbose() {
+            eprintln!("  Next refresh in {}s", refreshed.next.as_secs());
+        }
+        std::thread::sleep(refreshed.next);
+        refreshed = loop {
+            match killswitch::refresh(verbose) {
+                Ok(refreshed) => break refreshed,
+                Err(e) => {
+                    eprintln!("Refresh failed: {e:#}");
+                    std::thread::sleep(RETRY_DELAY);
+                }
+            }
+        };
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::cli::verbosity::Verbosity;
+    use crate::killswitch::Options;
 
     #[allow(clippy::unwrap_used)]
     #[test]
     fn test_action_print_execution() {
         // Print action should not require root privileges
         let action = Action::Print {
-            ipv4: Some("203.0.113.1".to_string()),
-            leak: false,
-            local: false,
+            options: Options {
+                peers: vec!["203.0.113.1".to_string()],
+                ..Options::default()
+            },
+            snapshot: None,
             verbose: Verbosity::Normal,
         };
 
@@ -93,9 +320,14 @@ mod tests {
     #[test]
     fn test_action_print_with_options() {
         let action = Action::Print {
-            ipv4: Some("198.51.100.1".to_string()),
-            leak: true,
-            local: true,
+            options: Options {
+                peers: vec!["198.51.100.1".to_string()],
+                allow_dns: Some(Vec::new()),
+                allow_icmp: Some(Vec::new()),
+                local: Some(Vec::new()),
+                ..Options::default()
+            },
+            snapshot: None,
             verbose: Verbosity::Normal,
         };
 
@@ -107,9 +339,11 @@ mod tests {
     #[test]
     fn test_action_print_rejects_private_ip() {
         let action = Action::Print {
-            ipv4: Some("10.8.0.1".to_string()),
-            leak: false,
-            local: false,
+            options: Options {
+                peers: vec!["10.8.0.1".to_string()],
+                ..Options::default()
+            },
+            snapshot: None,
             verbose: Verbosity::Normal,
         };
 
@@ -117,13 +351,58 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_action_print_from_snapshot() {
+        let path =
+            std::env::temp_dir().join(format!("killswitch-snapshot-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"interfaces":[{"name":"en0","mac":"","ip":"192.168.1.2/24","is_p2p":false}],"peers":["203.0.113.7"]}"#,
+        )
+        .unwrap();
+
+        let action = Action::Print {
+            options: Options::default(),
+            snapshot: Some(path.clone()),
+            verbose: Verbosity::Normal,
+        };
+
+        let result = execute(&action);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_action_print_server_pool() {
+        let path =
+            std::env::temp_dir().join(format!("killswitch-peers-{}.txt", std::process::id()));
+        std::fs::write(&path, "# pool\n198.51.100.7\n2001:db8::1\n").unwrap();
+
+        let action = Action::Print {
+            options: Options {
+                peers: vec!["203.0.113.1".to_string()],
+                peer_file: Some(path.clone()),
+                ..Options::default()
+            },
+            snapshot: None,
+            verbose: Verbosity::Normal,
+        };
+
+        let result = execute(&action);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+    }
+
     #[allow(clippy::unwrap_used)]
     #[test]
     fn test_action_enable_requires_root() {
         let action = Action::Enable {
-            ipv4: Some("10.8.0.1".to_string()),
-            leak: false,
-            local: false,
+            options: Options {
+                peers: vec!["10.8.0.1".to_string()],
+                ..Options::default()
+            },
             verbose: Verbosity::Normal,
         };
 
@@ -136,6 +415,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_action_refresh_requires_root() {
+        let action = Action::Refresh {
+            watch: false,
+            verbose: Verbosity::Normal,
+        };
+        // Without root it fails early, as root there is no active kill switch
+        assert!(execute(&action).is_err());
+    }
+
     #[allow(clippy::unwrap_used)]
     #[test]
     fn test_action_disable_requires_root() {
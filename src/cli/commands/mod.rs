@@ -1,8 +1,11 @@
+use crate::killswitch::{Deny, Inbound};
 use clap::{
-    Arg, ArgAction, ColorChoice, Command,
+    Arg, ArgAction, ArgGroup, ColorChoice, Command,
     builder::styling::{AnsiColor, Effects, Styles},
 };
+use std::path::PathBuf;
 use std::sync::OnceLock;
+use std::time::Duration;
 
 pub mod built_info {
     #![allow(clippy::doc_markdown)]
@@ -55,27 +58,12 @@ pub fn new() -> Command {
                 .action(ArgAction::SetTrue)
                 .conflicts_with_all(["enable", "disable"]),
         )
-        .arg(
-            Arg::new("ipv4")
-                .long("ipv4")
-                .help("VPN peer IPv4 address (auto-detected if not specified)")
-                .value_name("IP")
-                .conflicts_with_all(["disable", "status"]),
-        )
-        .arg(
-            Arg::new("leak")
-                .long("leak")
-                .help("Allow ICMP (ping) and DNS requests outside the VPN")
-                .action(ArgAction::SetTrue)
-                .conflicts_with_all(["disable", "status"]),
-        )
-        .arg(
-            Arg::new("local")
-                .long("local")
-                .help("Allow local network traffic")
-                .action(ArgAction::SetTrue)
-                .conflicts_with_all(["disable", "status"]),
-        )
+        .args(peer_args())
+        .args(rule_args())
+        .args(interface_args())
+        .args(discovery_args())
+        .args(account_args())
+        .args(port_args())
         .arg(
             Arg::new("print")
                 .short('p')
@@ -84,12 +72,415 @@ pub fn new() -> Command {
                 .action(ArgAction::SetTrue)
                 .conflicts_with_all(["disable", "status"]),
         )
+        .arg(
+            Arg::new("snapshot")
+                .long("snapshot")
+                .help("Generate rules from a saved network snapshot instead of the live network")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .requires("print"),
+        )
         .arg(
             Arg::new("verbose")
                 .short('v')
                 .long("verbose")
                 .help("Increase output verbosity (-v: verbose, -vv: debug)")
-                .action(ArgAction::Count),
+                .action(ArgAction::Count)
+                .global(true),
+        )
+        .subcommand(snapshot_command())
+        .subcommand(refresh_command())
+        .subcommand(update_command())
+        .subcommand(blocked_command())
+        .subcommand(portal_command())
+        .subcommand(lint_command())
+        .subcommand(diff_command())
+}
+
+/// VPN peer options shared by `--enable` and `--print`
+fn peer_args() -> Vec<Arg> {
+    vec![
+        Arg::new("peer")
+            .long("peer")
+            .alias("ipv4")
+            .help("VPN peer IP address or hostname, repeat for server pools (auto-detected if not specified)")
+            .value_name("PEER")
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("peer_file")
+            .long("peer-file")
+            .help("File listing VPN peer addresses, one per line")
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("peer_port")
+            .long("peer-port")
+            .help("Only allow this port on the VPN peers (auto-detected if not specified)")
+            .value_name("PORT")
+            .value_parser(clap::value_parser!(u16).range(1..))
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("peer_proto")
+            .long("peer-proto")
+            .help("Only allow this protocol to the VPN peers (auto-detected if not specified)")
+            .value_name("PROTO")
+            .value_parser(["tcp", "udp"])
+            .conflicts_with_all(["disable", "status"]),
+    ]
+}
+
+/// Interface selection options shared by `--enable` and `--print`
+fn interface_args() -> Vec<Arg> {
+    vec![
+        Arg::new("ignore_iface")
+            .long("ignore-iface")
+            .help("Leave interfaces matching this glob without rules (blocked), repeat for more")
+            .value_name("PATTERN")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("only_iface")
+            .long("only-iface")
+            .help("Only generate rules for interfaces matching this glob, VPN included (e.g. en0,utun*)")
+            .value_name("PATTERN")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("bridges")
+            .long("bridges")
+            .help("Policy for VM bridges and peer-to-peer links (bridge*, vmnet*, awdl*, llw*)")
+            .value_name("POLICY")
+            .value_parser(["block", "pass", "tunnel"])
+            .default_value("block")
+            .conflicts_with_all(["disable", "status"]),
+    ]
+}
+
+/// Broadcast and multicast switches: `all` (default), `none` or the
+/// interfaces (globs) the traffic is allowed on
+fn discovery_args() -> Vec<Arg> {
+    let scope = |id: &'static str, help: &'static str| {
+        Arg::new(id)
+            .long(id)
+            .help(help)
+            .value_name("all|none|PATTERN,...")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"])
+    };
+    vec![
+        scope(
+            "broadcast",
+            "Allow broadcasts (255.255.255.255) on every interface, none, or the matching ones",
+        ),
+        scope(
+            "multicast",
+            "Allow UDP multicast (224.0.0.0/4) on every interface, none, or the matching ones",
+        ),
+        scope(
+            "mdns",
+            "Allow mDNS (224.0.0.251:5353) on every interface, none, or the matching ones",
+        ),
+        scope(
+            "ssdp",
+            "Allow SSDP (239.255.255.250:1900) on every interface, none, or the matching ones",
+        ),
+        Arg::new("paranoid")
+            .long("paranoid")
+            .help("Block broadcast, multicast, mDNS and SSDP unless allowed explicitly")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["disable", "status"]),
+    ]
+}
+
+/// Accounts exempt from the kill switch, or the only ones it is enforced for
+fn account_args() -> Vec<Arg> {
+    vec![
+        Arg::new("exempt_user")
+            .long("exempt-user")
+            .help("Allow the TCP and UDP traffic of this account outside the VPN, repeatable")
+            .value_name("USER")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status", "only_user", "only_group"]),
+        Arg::new("exempt_group")
+            .long("exempt-group")
+            .help("Allow the TCP and UDP traffic of this group outside the VPN, repeatable")
+            .value_name("GROUP")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status", "only_user", "only_group"]),
+        Arg::new("only_user")
+            .long("only-user")
+            .help("Only enforce the kill switch for this account, others are allowed outside the VPN, repeatable")
+            .value_name("USER")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("only_group")
+            .long("only-group")
+            .help("Only enforce the kill switch for this group, others are allowed outside the VPN, repeatable")
+            .value_name("GROUP")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+    ]
+}
+
+/// Ports open to inbound connections and the egress deny list
+fn port_args() -> Vec<Arg> {
+    vec![
+        Arg::new("inbound")
+            .long("inbound")
+            .help("Open a port to inbound connections on the VPN, or on IFACE (needs --local), repeatable")
+            .value_name("PROTO/PORT[@IFACE]")
+            .value_parser(clap::value_parser!(Inbound))
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("deny")
+            .long("deny")
+            .help("Block a port or network on every interface, the VPN included (e.g. 25, tcp/465, 203.0.113.0/24), repeatable")
+            .value_name("PORT|PROTO/PORT|CIDR")
+            .value_parser(clap::value_parser!(Deny))
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("deny_file")
+            .long("deny-file")
+            .help("File listing ports and networks to block on every interface, one per line")
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with_all(["disable", "status"]),
+    ]
+}
+
+/// Options shared by `--enable` and `--print` that shape the generated rules
+fn rule_args() -> Vec<Arg> {
+    vec![
+        Arg::new("allow")
+            .long("allow")
+            .help("Allow a CIDR, address or hostname outside the VPN, repeat for more")
+            .value_name("DEST")
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("allow_file")
+            .long("allow-file")
+            .help("File listing destinations allowed outside the VPN, one per line")
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("bypass")
+            .long("bypass")
+            .help("Allow a domain outside the VPN, refreshed on its DNS TTL, repeat for more")
+            .value_name("DOMAIN")
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("bypass_file")
+            .long("bypass-file")
+            .help("File listing domains allowed outside the VPN, one per line")
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("allow_dns")
+            .long("allow-dns")
+            .help("Allow DNS outside the VPN, only to the given resolvers if any")
+            .value_name("RESOLVER,...")
+            .num_args(0..=1)
+            .value_delimiter(',')
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("allow_icmp")
+            .long("allow-icmp")
+            .help("Allow ICMP outside the VPN, only the given types if any (default: echoreq)")
+            .value_name("TYPE,...")
+            .num_args(0..=1)
+            .value_delimiter(',')
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("icmp_iface")
+            .long("icmp-iface")
+            .help("Only allow ICMP on this interface, repeat for more")
+            .value_name("IFACE")
+            .action(ArgAction::Append)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("dns_lock")
+            .long("dns-lock")
+            .help("Only allow DNS (53/853) to the resolvers pushed by the VPN, through the tunnel")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("leak")
+            .long("leak")
+            .help("Allow ICMP (ping) and DNS requests outside the VPN (alias for --allow-dns --allow-icmp)")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("local")
+            .long("local")
+            .help("Allow local network traffic, only to the given subnets if any (!ADDR excludes)")
+            .value_name("SUBNET,...")
+            .num_args(0..=1)
+            .value_delimiter(',')
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("exclude_gateway")
+            .long("exclude-gateway")
+            .help("Exclude the default gateway (router admin page) from the local networks")
+            .action(ArgAction::SetTrue)
+            .requires("local"),
+        Arg::new("log")
+            .long("log")
+            .help("Log the blocked packets to pflog0, see `killswitch blocked`")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("anchor")
+            .long("anchor")
+            .help("Load the rules into a dedicated killswitch pf anchor, keeping the system ruleset")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("preset")
+            .long("preset")
+            .help("Add the rules a VPN needs outside the tunnel (tailscale: control plane, DERP relays, direct paths and MagicDNS)")
+            .value_name("PRESET")
+            .value_parser(["tailscale"])
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("fragments")
+            .long("fragments")
+            .help("Splice the pf fragments of DIR/before-block, DIR/after-exemptions and DIR/after-tunnel into the rules")
+            .value_name("DIR")
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with_all(["disable", "status"]),
+        Arg::new("unsafe_fragments")
+            .long("unsafe-fragments")
+            .help("Allow fragments to pass traffic on the physical interfaces")
+            .action(ArgAction::SetTrue)
+            .requires("fragments"),
+    ]
+}
+
+fn refresh_command() -> Command {
+    Command::new("refresh")
+        .about("Re-resolve the VPN peers and bypassed domains of the active kill switch")
+        .arg(
+            Arg::new("watch")
+                .short('w')
+                .long("watch")
+                .help("Keep running and refresh again when the DNS records expire")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Bounds of a captive portal window
+const PORTAL_MIN: Duration = Duration::from_secs(10);
+const PORTAL_MAX: Duration = Duration::from_secs(1800);
+
+/// Parse a portal window: seconds, optionally suffixed with `s`, or minutes
+/// suffixed with `m` (`120`, `120s`, `2m`)
+fn parse_window(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        None => (value.strip_suffix('s').unwrap_or(value), 1),
+    };
+    let window = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration '{value}', use e.g. 120s or 2m"))?;
+    if !(PORTAL_MIN..=PORTAL_MAX).contains(&window) {
+        return Err(format!(
+            "the window must be between {}s and {}m",
+            PORTAL_MIN.as_secs(),
+            PORTAL_MAX.as_secs() / 60
+        ));
+    }
+    Ok(window)
+}
+
+fn portal_command() -> Command {
+    Command::new("portal")
+        .about("Allow a captive portal login outside the VPN for a while, then restore the kill switch")
+        .arg(
+            Arg::new("for")
+                .long("for")
+                .help("How long to allow DNS and web to the local network")
+                .value_name("DURATION")
+                .value_parser(parse_window)
+                .default_value("120s"),
+        )
+}
+
+fn lint_command() -> Command {
+    Command::new("lint")
+        .about("Check a pf ruleset offline: syntax, undefined macros, unreachable rules and leaks")
+        .arg(
+            Arg::new("file")
+                .help("Ruleset to check, e.g. /tmp/killswitch.pf.conf")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true),
+        )
+}
+
+fn diff_command() -> Command {
+    Command::new("diff").about(
+        "Compare the loaded rules with the rules enabling the kill switch would generate now",
+    )
+}
+
+fn blocked_command() -> Command {
+    Command::new("blocked")
+        .about("Summarize the packets dropped by the kill switch (enabled with --log)")
+        .arg(
+            Arg::new("file")
+                .help("Read a saved pflog capture (tcpdump -i pflog0 -w FILE) instead of capturing live")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("time")
+                .short('t')
+                .long("time")
+                .help("Seconds to capture on pflog0")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("30")
+                .conflicts_with("file"),
+        )
+}
+
+fn update_command() -> Command {
+    let list = |id: &'static str, long: &'static str, help: &'static str, name: &'static str| {
+        Arg::new(id)
+            .long(long)
+            .help(help)
+            .value_name(name)
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+    };
+    Command::new("update")
+        .about("Add or remove VPN peers, allowed destinations and local subnets of the active kill switch")
+        .args([
+            list("add_peer", "add-peer", "Add a VPN peer address or hostname to <vpn_peers>", "PEER"),
+            list("remove_peer", "remove-peer", "Remove a VPN peer from <vpn_peers>", "PEER"),
+            list("add_allow", "add-allow", "Add a destination to <allow>", "DEST"),
+            list("remove_allow", "remove-allow", "Remove a destination from <allow>", "DEST"),
+            list("add_local", "add-local", "Add a subnet (or !ADDR exclusion) to <local_nets>", "SUBNET"),
+            list("remove_local", "remove-local", "Remove a subnet from <local_nets>", "SUBNET"),
+        ])
+        .group(
+            ArgGroup::new("changes")
+                .args(["add_peer", "remove_peer", "add_allow", "remove_allow", "add_local", "remove_local"])
+                .required(true)
+                .multiple(true),
+        )
+}
+
+fn snapshot_command() -> Command {
+    Command::new("snapshot")
+        .about("Capture interfaces, VPN peers, routes and DNS as JSON")
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .help("Write the snapshot to FILE instead of stdout")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf)),
         )
 }
 
@@ -122,6 +513,383 @@ mod tests {
         assert!(matches.get_flag("disable"));
     }
 
+    #[allow(clippy::panic)]
+    #[test]
+    fn test_snapshot_subcommand() {
+        let matches =
+            new().get_matches_from(vec!["killswitch", "snapshot", "-o", "net.json", "-v"]);
+        let Some(("snapshot", sub)) = matches.subcommand() else {
+            panic!("Expected snapshot subcommand");
+        };
+        assert_eq!(
+            sub.get_one::<PathBuf>("output"),
+            Some(&PathBuf::from("net.json"))
+        );
+        assert_eq!(matches.get_count("verbose"), 1);
+    }
+
+    #[test]
+    fn test_refresh_subcommand() {
+        let matches = new().get_matches_from(vec!["killswitch", "refresh"]);
+        assert_eq!(matches.subcommand_name(), Some("refresh"));
+        let matches = new().get_matches_from(vec!["killswitch", "refresh", "--watch"]);
+        assert!(
+            matches
+                .subcommand_matches("refresh")
+                .is_some_and(|sub| sub.get_flag("watch"))
+        );
+    }
+
+    #[test]
+    fn test_update_subcommand() {
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "update",
+            "--add-peer",
+            "203.0.113.1,198.51.100.7",
+            "--remove-peer",
+            "192.0.2.1",
+        ]);
+        let update = matches.subcommand_matches("update");
+        let added: Vec<&String> = update
+            .and_then(|m| m.get_many("add_peer"))
+            .unwrap_or_default()
+            .collect();
+        assert_eq!(added, ["203.0.113.1", "198.51.100.7"]);
+
+        let result = new().try_get_matches_from(vec!["killswitch", "update"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_lint_subcommand() {
+        let matches = new().get_matches_from(vec!["killswitch", "lint", "rules.conf"]);
+        let lint = matches.subcommand_matches("lint");
+        assert_eq!(
+            lint.and_then(|m| m.get_one::<PathBuf>("file")),
+            Some(&PathBuf::from("rules.conf"))
+        );
+
+        let result = new().try_get_matches_from(vec!["killswitch", "lint"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_blocked_subcommand() {
+        let matches = new().get_matches_from(vec!["killswitch", "blocked", "-t", "10"]);
+        let blocked = matches.subcommand_matches("blocked");
+        assert_eq!(blocked.and_then(|m| m.get_one::<u64>("time")), Some(&10));
+        assert!(blocked.and_then(|m| m.get_one::<PathBuf>("file")).is_none());
+
+        let matches = new().get_matches_from(vec!["killswitch", "blocked", "drops.pcap"]);
+        let blocked = matches.subcommand_matches("blocked");
+        assert_eq!(
+            blocked.and_then(|m| m.get_one::<PathBuf>("file")),
+            Some(&PathBuf::from("drops.pcap"))
+        );
+
+        let result = new().try_get_matches_from(vec!["killswitch", "blocked", "-t", "0"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_portal_subcommand() {
+        let matches = new().get_matches_from(vec!["killswitch", "portal"]);
+        let portal = matches.subcommand_matches("portal");
+        assert_eq!(
+            portal.and_then(|m| m.get_one::<Duration>("for")),
+            Some(&Duration::from_secs(120))
+        );
+        for window in ["0s", "5", "31m", "soon", "-1s"] {
+            let result = new().try_get_matches_from(vec!["killswitch", "portal", "--for", window]);
+            assert!(result.is_err(), "{window}");
+        }
+    }
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("120s"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_window("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_window("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_window("2h").is_err());
+    }
+
+    #[test]
+    fn test_snapshot_requires_print() {
+        let result = new().try_get_matches_from(vec!["killswitch", "--snapshot", "net.json"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_peer_accepts_ipv4_alias() {
+        let matches = new().get_matches_from(vec!["killswitch", "-e", "--ipv4", "203.0.113.1"]);
+        assert_eq!(
+            matches.get_one::<String>("peer").map(String::as_str),
+            Some("203.0.113.1")
+        );
+        let matches = new().get_matches_from(vec!["killswitch", "-e", "--peer", "2001:db8::1"]);
+        assert_eq!(
+            matches.get_one::<String>("peer").map(String::as_str),
+            Some("2001:db8::1")
+        );
+    }
+
+    #[test]
+    fn test_peer_port_and_proto() {
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--peer-port",
+            "51820",
+            "--peer-proto",
+            "udp",
+        ]);
+        assert_eq!(matches.get_one::<u16>("peer_port"), Some(&51820));
+        assert_eq!(
+            matches.get_one::<String>("peer_proto").map(String::as_str),
+            Some("udp")
+        );
+        let result = new().try_get_matches_from(vec!["killswitch", "-e", "--peer-proto", "icmp"]);
+        assert!(result.is_err());
+        let result = new().try_get_matches_from(vec!["killswitch", "-e", "--peer-port", "0"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_allow_dns_and_icmp() {
+        let matches =
+            new().get_matches_from(vec!["killswitch", "-e", "--allow-dns", "--allow-icmp"]);
+        assert_eq!(
+            matches.get_many::<String>("allow_dns").map(Iterator::count),
+            Some(0)
+        );
+        assert_eq!(
+            matches
+                .get_many::<String>("allow_icmp")
+                .map(Iterator::count),
+            Some(0)
+        );
+
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--allow-dns",
+            "9.9.9.9,1.1.1.1",
+            "--allow-icmp",
+            "echoreq,unreach",
+            "--icmp-iface",
+            "en0",
+        ]);
+        let resolvers: Vec<&String> = matches.get_many("allow_dns").unwrap_or_default().collect();
+        assert_eq!(resolvers, ["9.9.9.9", "1.1.1.1"]);
+        let types: Vec<&String> = matches.get_many("allow_icmp").unwrap_or_default().collect();
+        assert_eq!(types, ["echoreq", "unreach"]);
+
+        let matches = new().get_matches_from(vec!["killswitch", "-e"]);
+        assert!(matches.get_many::<String>("allow_dns").is_none());
+    }
+
+    #[test]
+    fn test_local_subnets() {
+        let matches = new().get_matches_from(vec!["killswitch", "-e", "--local"]);
+        assert_eq!(
+            matches.get_many::<String>("local").map(Iterator::count),
+            Some(0)
+        );
+
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--local",
+            "192.168.20.0/24,!192.168.20.1",
+            "--exclude-gateway",
+        ]);
+        let subnets: Vec<&String> = matches.get_many("local").unwrap_or_default().collect();
+        assert_eq!(subnets, ["192.168.20.0/24", "!192.168.20.1"]);
+        assert!(matches.get_flag("exclude_gateway"));
+
+        let result = new().try_get_matches_from(vec!["killswitch", "-e", "--exclude-gateway"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_interface_selection() {
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--only-iface",
+            "en0,utun*",
+            "--ignore-iface",
+            "en5",
+            "--bridges",
+            "tunnel",
+        ]);
+        let only: Vec<&String> = matches.get_many("only_iface").unwrap_or_default().collect();
+        assert_eq!(only, ["en0", "utun*"]);
+        let ignore: Vec<&String> = matches
+            .get_many("ignore_iface")
+            .unwrap_or_default()
+            .collect();
+        assert_eq!(ignore, ["en5"]);
+        assert_eq!(
+            matches.get_one::<String>("bridges").map(String::as_str),
+            Some("tunnel")
+        );
+
+        let matches = new().get_matches_from(vec!["killswitch", "-e"]);
+        assert_eq!(
+            matches.get_one::<String>("bridges").map(String::as_str),
+            Some("block")
+        );
+        let result = new().try_get_matches_from(vec!["killswitch", "-e", "--bridges", "nat"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_discovery() {
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--paranoid",
+            "--mdns",
+            "en0,en1",
+            "--ssdp",
+            "none",
+        ]);
+        assert!(matches.get_flag("paranoid"));
+        let mdns: Vec<&String> = matches.get_many("mdns").unwrap_or_default().collect();
+        assert_eq!(mdns, ["en0", "en1"]);
+        let ssdp: Vec<&String> = matches.get_many("ssdp").unwrap_or_default().collect();
+        assert_eq!(ssdp, ["none"]);
+        assert!(matches.get_many::<String>("broadcast").is_none());
+
+        let result = new().try_get_matches_from(vec!["killswitch", "-s", "--paranoid"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_preset() {
+        let matches = new().get_matches_from(vec!["killswitch", "-e", "--preset", "tailscale"]);
+        assert_eq!(
+            matches.get_one::<String>("preset").map(String::as_str),
+            Some("tailscale")
+        );
+
+        let result = new().try_get_matches_from(vec!["killswitch", "-e", "--preset", "zerotier"]);
+        assert!(result.is_err());
+        let result = new().try_get_matches_from(vec!["killswitch", "-d", "--preset", "tailscale"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_accounts() {
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--exempt-user",
+            "_softwareupdate,backup",
+            "--exempt-group",
+            "staff",
+        ]);
+        let users: Vec<&String> = matches
+            .get_many::<String>("exempt_user")
+            .unwrap_or_default()
+            .collect();
+        assert_eq!(users, ["_softwareupdate", "backup"]);
+
+        let result = new().try_get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--exempt-user",
+            "backup",
+            "--only-user",
+            "torrent",
+        ]);
+        assert!(result.is_err());
+        let result = new().try_get_matches_from(vec!["killswitch", "-d", "--only-group", "staff"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_inbound() {
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--inbound",
+            "udp/51413,tcp/22@en0",
+        ]);
+        let ports: Vec<String> = matches
+            .get_many::<Inbound>("inbound")
+            .unwrap_or_default()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(ports, ["udp/51413", "tcp/22@en0"]);
+
+        for bad in ["22", "icmp/8", "tcp/0", "tcp/http", "tcp/22@"] {
+            let result = new().try_get_matches_from(vec!["killswitch", "-e", "--inbound", bad]);
+            assert!(result.is_err(), "{bad}");
+        }
+        let result = new().try_get_matches_from(vec!["killswitch", "-s", "--inbound", "tcp/22"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_deny() {
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--deny",
+            "25,tcp/465",
+            "--deny",
+            "203.0.113.0/24",
+            "--deny-file",
+            "/etc/killswitch/deny",
+        ]);
+        let deny: Vec<String> = matches
+            .get_many::<Deny>("deny")
+            .unwrap_or_default()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(deny, ["25", "tcp/465", "203.0.113.0/24"]);
+        assert_eq!(
+            matches.get_one::<PathBuf>("deny_file"),
+            Some(&PathBuf::from("/etc/killswitch/deny"))
+        );
+
+        for bad in ["0", "smtp", "icmp/8", "tcp/70000", "203.0.113.0/33"] {
+            let result = new().try_get_matches_from(vec!["killswitch", "-e", "--deny", bad]);
+            assert!(result.is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_fragments() {
+        let matches = new().get_matches_from(vec![
+            "killswitch",
+            "-e",
+            "--fragments",
+            "/etc/killswitch/pf.d",
+            "--unsafe-fragments",
+        ]);
+        assert_eq!(
+            matches.get_one::<PathBuf>("fragments"),
+            Some(&PathBuf::from("/etc/killswitch/pf.d"))
+        );
+        assert!(matches.get_flag("unsafe_fragments"));
+
+        let result = new().try_get_matches_from(vec!["killswitch", "-e", "--unsafe-fragments"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_anchor() {
+        let matches = new().get_matches_from(vec!["killswitch", "-e", "--anchor"]);
+        assert!(matches.get_flag("anchor"));
+
+        let result = new().try_get_matches_from(vec!["killswitch", "-d", "--anchor"]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_verbose_count() {
         let matches = new().get_matches_from(vec!["killswitch", "-vvv"]);
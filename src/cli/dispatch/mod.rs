@@ -1,49 +1,97 @@
 use crate::cli::{actions::Action, verbosity::Verbosity};
+use crate::killswitch::{BridgePolicy, Deny, Inbound, Options, PeerProto, Preset, Scope, Update};
 use anyhow::Result;
 use clap::ArgMatches;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// Convert CLI arguments to an Action
 ///
 /// # Errors
 /// Returns an error if no action is specified or if arguments are invalid
 pub fn handler(matches: &ArgMatches, verbose: Verbosity) -> Result<Action> {
+    match matches.subcommand() {
+        Some(("snapshot", sub)) => {
+            return Ok(Action::Snapshot {
+                output: sub.get_one::<PathBuf>("output").cloned(),
+                verbose,
+            });
+        }
+        Some(("refresh", sub)) => {
+            return Ok(Action::Refresh {
+                watch: sub.get_flag("watch"),
+                verbose,
+            });
+        }
+        Some(("blocked", sub)) => {
+            return Ok(Action::Blocked {
+                file: sub.get_one::<PathBuf>("file").cloned(),
+                time: Duration::from_secs(sub.get_one::<u64>("time").copied().unwrap_or(30)),
+                verbose,
+            });
+        }
+        Some(("lint", sub)) => {
+            return Ok(Action::Lint {
+                file: sub.get_one::<PathBuf>("file").cloned().unwrap_or_default(),
+                verbose,
+            });
+        }
+        Some(("diff", _)) => return Ok(Action::Diff { verbose }),
+        Some(("portal", sub)) => {
+            return Ok(Action::Portal {
+                window: sub
+                    .get_one::<Duration>("for")
+                    .copied()
+                    .unwrap_or(Duration::from_secs(120)),
+                verbose,
+            });
+        }
+        Some(("update", sub)) => {
+            let list = |id: &str| {
+                sub.get_many::<String>(id)
+                    .map(|values| values.cloned().collect())
+                    .unwrap_or_default()
+            };
+            return Ok(Action::Update {
+                changes: Update {
+                    add_peers: list("add_peer"),
+                    remove_peers: list("remove_peer"),
+                    add_allow: list("add_allow"),
+                    remove_allow: list("remove_allow"),
+                    add_local: list("add_local"),
+                    remove_local: list("remove_local"),
+                },
+                verbose,
+            });
+        }
+        _ => {}
+    }
+
     let enable = matches.get_flag("enable");
     let disable = matches.get_flag("disable");
     let status = matches.get_flag("status");
     let print = matches.get_flag("print");
 
     if enable {
-        let ipv4 = matches.get_one::<String>("ipv4").map(String::from);
-        let leak = matches.get_flag("leak");
-        let local = matches.get_flag("local");
+        let options = options(matches);
 
         if print {
             Ok(Action::Print {
-                ipv4,
-                leak,
-                local,
+                options,
+                snapshot: matches.get_one::<PathBuf>("snapshot").cloned(),
                 verbose,
             })
         } else {
-            Ok(Action::Enable {
-                ipv4,
-                leak,
-                local,
-                verbose,
-            })
+            Ok(Action::Enable { options, verbose })
         }
     } else if disable {
         Ok(Action::Disable { verbose })
     } else if status {
         Ok(Action::Status { verbose })
     } else if print {
-        let ipv4 = matches.get_one::<String>("ipv4").map(String::from);
-        let leak = matches.get_flag("leak");
-        let local = matches.get_flag("local");
         Ok(Action::Print {
-            ipv4,
-            leak,
-            local,
+            options: options(matches),
+            snapshot: matches.get_one::<PathBuf>("snapshot").cloned(),
             verbose,
         })
     } else {
@@ -51,6 +99,116 @@ pub fn handler(matches: &ArgMatches, verbose: Verbosity) -> Result<Action> {
     }
 }
 
+/// Collect the rule generation settings shared by enable and print
+fn options(matches: &ArgMatches) -> Options {
+    let list = |id: &str| {
+        matches
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default()
+    };
+    let mut options = Options {
+        peers: matches
+            .get_many::<String>("peer")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        peer_file: matches.get_one::<PathBuf>("peer_file").cloned(),
+        peer_port: matches.get_one::<u16>("peer_port").copied(),
+        peer_proto: matches
+            .get_one::<String>("peer_proto")
+            .map(|proto| match proto.as_str() {
+                "tcp" => PeerProto::Tcp,
+                _ => PeerProto::Udp,
+            }),
+        allow: matches
+            .get_many::<String>("allow")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        allow_file: matches.get_one::<PathBuf>("allow_file").cloned(),
+        bypass: matches
+            .get_many::<String>("bypass")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        bypass_file: matches.get_one::<PathBuf>("bypass_file").cloned(),
+        allow_dns: matches
+            .get_many::<String>("allow_dns")
+            .map(|values| values.cloned().collect()),
+        allow_icmp: matches
+            .get_many::<String>("allow_icmp")
+            .map(|values| values.cloned().collect()),
+        icmp_ifaces: matches
+            .get_many::<String>("icmp_iface")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        dns_lock: matches.get_flag("dns_lock"),
+        local: matches
+            .get_many::<String>("local")
+            .map(|values| values.cloned().collect()),
+        exclude_gateway: matches.get_flag("exclude_gateway"),
+        inbound: matches
+            .get_many::<Inbound>("inbound")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        deny: matches
+            .get_many::<Deny>("deny")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        deny_file: matches.get_one::<PathBuf>("deny_file").cloned(),
+        anchor: matches.get_flag("anchor"),
+        log: matches.get_flag("log"),
+        ignore_ifaces: matches
+            .get_many::<String>("ignore_iface")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        only_ifaces: matches
+            .get_many::<String>("only_iface")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        bridges: match matches.get_one::<String>("bridges").map(String::as_str) {
+            Some("pass") => BridgePolicy::Pass,
+            Some("tunnel") => BridgePolicy::Tunnel,
+            _ => BridgePolicy::Block,
+        },
+        preset: match matches.get_one::<String>("preset").map(String::as_str) {
+            Some("tailscale") => Some(Preset::Tailscale),
+            _ => None,
+        },
+        exempt_users: list("exempt_user"),
+        exempt_groups: list("exempt_group"),
+        only_users: list("only_user"),
+        only_groups: list("only_group"),
+        fragments: matches.get_one::<PathBuf>("fragments").cloned(),
+        unsafe_fragments: matches.get_flag("unsafe_fragments"),
+        broadcast: scope(matches, "broadcast"),
+        multicast: scope(matches, "multicast"),
+        mdns: scope(matches, "mdns"),
+        ssdp: scope(matches, "ssdp"),
+        ..Options::default()
+    };
+    if matches.get_flag("leak") {
+        options.allow_leak();
+    }
+    options
+}
+
+/// Interfaces a broadcast or multicast switch allows its traffic on, none
+/// unless given explicitly with `--paranoid`
+fn scope(matches: &ArgMatches, id: &str) -> Scope {
+    let Some(values) = matches.get_many::<String>(id) else {
+        return if matches.get_flag("paranoid") {
+            Scope::Blocked
+        } else {
+            Scope::All
+        };
+    };
+    let patterns: Vec<String> = values.cloned().collect();
+    match patterns.as_slice() {
+        [all] if all == "all" => Scope::All,
+        [none] if none == "none" => Scope::Blocked,
+        _ => Scope::Only(patterns),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,25 +259,300 @@ mod tests {
             "-e",
             "--local",
             "--leak",
+            "--dns-lock",
             "--ipv4",
             "10.0.0.1",
+            "--peer",
+            "2001:db8::1",
+            "--peer-file",
+            "peers.txt",
+            "--peer-port",
+            "1194",
+            "--peer-proto",
+            "tcp",
+            "--allow",
+            "198.51.100.0/24",
+            "--allow",
+            "sso.example.com",
+            "--allow-file",
+            "allow.txt",
+            "--bypass",
+            "bank.example",
+            "--bypass-file",
+            "bypass.txt",
         ]);
         let action = handler(&matches, Verbosity::Normal).unwrap();
         if let Action::Enable {
-            ipv4,
-            leak,
-            local,
+            options,
             verbose: _,
         } = action
         {
-            assert_eq!(ipv4, Some("10.0.0.1".to_string()));
-            assert!(leak);
-            assert!(local);
+            assert_eq!(options.peers, vec!["10.0.0.1", "2001:db8::1"]);
+            assert_eq!(options.peer_file, Some(PathBuf::from("peers.txt")));
+            assert_eq!(options.peer_port, Some(1194));
+            assert_eq!(options.peer_proto, Some(PeerProto::Tcp));
+            assert_eq!(options.allow, vec!["198.51.100.0/24", "sso.example.com"]);
+            assert_eq!(options.allow_file, Some(PathBuf::from("allow.txt")));
+            assert_eq!(options.bypass, vec!["bank.example"]);
+            assert_eq!(options.bypass_file, Some(PathBuf::from("bypass.txt")));
+            assert_eq!(options.allow_dns, Some(Vec::new()));
+            assert_eq!(options.allow_icmp, Some(Vec::new()));
+            assert!(options.dns_lock);
+            assert_eq!(options.local, Some(Vec::new()));
+            assert!(!options.exclude_gateway);
+            assert!(!options.anchor);
+            assert!(!options.log);
+            assert_eq!(options.bridges, BridgePolicy::Block);
+            assert_eq!(options.preset, None);
         } else {
             panic!("Expected Action::Enable");
         }
     }
 
+    #[allow(clippy::unwrap_used, clippy::panic)]
+    #[test]
+    fn test_handler_scoped_exemptions() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec![
+            "killswitch",
+            "--print",
+            "--leak",
+            "--allow-dns",
+            "9.9.9.9",
+            "--allow-icmp",
+            "unreach",
+            "--icmp-iface",
+            "en0",
+        ]);
+        let Action::Print { options, .. } = handler(&matches, Verbosity::Normal).unwrap() else {
+            panic!("Expected Action::Print");
+        };
+        // --leak does not widen explicit settings
+        assert_eq!(options.allow_dns, Some(vec!["9.9.9.9".to_string()]));
+        assert_eq!(options.allow_icmp, Some(vec!["unreach".to_string()]));
+        assert_eq!(options.icmp_ifaces, vec!["en0"]);
+    }
+
+    #[allow(clippy::unwrap_used, clippy::panic)]
+    #[test]
+    fn test_handler_discovery() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec!["killswitch", "--print"]);
+        let Action::Print { options, .. } = handler(&matches, Verbosity::Normal).unwrap() else {
+            panic!("Expected Action::Print");
+        };
+        assert_eq!(options.broadcast, Scope::All);
+        assert_eq!(options.mdns, Scope::All);
+
+        let matches = commands::new().get_matches_from(vec![
+            "killswitch",
+            "--print",
+            "--paranoid",
+            "--broadcast",
+            "all",
+            "--mdns",
+            "en0,utun*",
+        ]);
+        let Action::Print { options, .. } = handler(&matches, Verbosity::Normal).unwrap() else {
+            panic!("Expected Action::Print");
+        };
+        assert_eq!(options.broadcast, Scope::All);
+        assert_eq!(options.multicast, Scope::Blocked);
+        assert_eq!(
+            options.mdns,
+            Scope::Only(vec!["en0".to_string(), "utun*".to_string()])
+        );
+        assert_eq!(options.ssdp, Scope::Blocked);
+
+        let matches =
+            commands::new().get_matches_from(vec!["killswitch", "--print", "--ssdp", "none"]);
+        let Action::Print { options, .. } = handler(&matches, Verbosity::Normal).unwrap() else {
+            panic!("Expected Action::Print");
+        };
+        assert_eq!(options.ssdp, Scope::Blocked);
+        assert_eq!(options.multicast, Scope::All);
+    }
+
+    #[allow(clippy::unwrap_used, clippy::panic)]
+    #[test]
+    fn test_handler_preset() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec![
+            "killswitch",
+            "--print",
+            "--preset",
+            "tailscale",
+        ]);
+        let Action::Print { options, .. } = handler(&matches, Verbosity::Normal).unwrap() else {
+            panic!("Expected Action::Print");
+        };
+        assert_eq!(options.preset, Some(Preset::Tailscale));
+        assert_eq!(options.fragments, None);
+        assert!(!options.unsafe_fragments);
+    }
+
+    #[allow(clippy::unwrap_used, clippy::panic)]
+    #[test]
+    fn test_handler_accounts() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec![
+            "killswitch",
+            "--print",
+            "--only-user",
+            "torrent,501",
+            "--only-group",
+            "p2p",
+        ]);
+        let Action::Print { options, .. } = handler(&matches, Verbosity::Normal).unwrap() else {
+            panic!("Expected Action::Print");
+        };
+        assert_eq!(options.only_users, ["torrent", "501"]);
+        assert_eq!(options.only_groups, ["p2p"]);
+        assert!(options.exempt_users.is_empty());
+    }
+
+    #[allow(clippy::unwrap_used, clippy::panic)]
+    #[test]
+    fn test_handler_inbound() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec![
+            "killswitch",
+            "--print",
+            "--local",
+            "--inbound",
+            "tcp/22@en0",
+            "--inbound",
+            "udp/51413",
+        ]);
+        let Action::Print { options, .. } = handler(&matches, Verbosity::Normal).unwrap() else {
+            panic!("Expected Action::Print");
+        };
+        assert_eq!(
+            options.inbound,
+            [
+                Inbound {
+                    proto: PeerProto::Tcp,
+                    port: 22,
+                    iface: Some("en0".to_string()),
+                },
+                Inbound {
+                    proto: PeerProto::Udp,
+                    port: 51413,
+                    iface: None,
+                },
+            ]
+        );
+        assert!(options.deny.is_empty());
+
+        let matches = commands::new().get_matches_from(vec![
+            "killswitch",
+            "--print",
+            "--deny",
+            "tcp/25,198.51.100.0/24",
+        ]);
+        let Action::Print { options, .. } = handler(&matches, Verbosity::Normal).unwrap() else {
+            panic!("Expected Action::Print");
+        };
+        assert_eq!(
+            options.deny,
+            [
+                Deny::Port {
+                    proto: Some(PeerProto::Tcp),
+                    port: 25,
+                },
+                Deny::Network("198.51.100.0/24".to_string()),
+            ]
+        );
+        assert_eq!(options.deny_file, None);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_handler_snapshot() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec!["killswitch", "snapshot"]);
+        let action = handler(&matches, Verbosity::Normal).unwrap();
+        assert!(matches!(action, Action::Snapshot { output: None, .. }));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_handler_refresh() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec!["killswitch", "refresh", "-v"]);
+        let action = handler(&matches, Verbosity::Verbose).unwrap();
+        assert!(matches!(action, Action::Refresh { watch: false, .. }));
+        let matches = commands::new().get_matches_from(vec!["killswitch", "refresh", "-w"]);
+        let action = handler(&matches, Verbosity::Normal).unwrap();
+        assert!(matches!(action, Action::Refresh { watch: true, .. }));
+    }
+
+    #[allow(clippy::unwrap_used, clippy::panic)]
+    #[test]
+    fn test_handler_update() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec![
+            "killswitch",
+            "update",
+            "--add-peer",
+            "203.0.113.1",
+            "--remove-allow",
+            "sso.example.com",
+        ]);
+        let action = handler(&matches, Verbosity::Normal).unwrap();
+        let Action::Update { changes, .. } = action else {
+            panic!("Expected Action::Update");
+        };
+        assert_eq!(changes.add_peers, vec!["203.0.113.1"]);
+        assert_eq!(changes.remove_allow, vec!["sso.example.com"]);
+        assert!(changes.remove_peers.is_empty());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_handler_blocked() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec!["killswitch", "blocked"]);
+        let action = handler(&matches, Verbosity::Normal).unwrap();
+        assert!(matches!(
+            action,
+            Action::Blocked { file: None, time, .. } if time == Duration::from_secs(30)
+        ));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_handler_lint() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec!["killswitch", "lint", "rules.conf"]);
+        let action = handler(&matches, Verbosity::Normal).unwrap();
+        assert!(matches!(
+            action,
+            Action::Lint { file, .. } if file == std::path::Path::new("rules.conf")
+        ));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_handler_diff() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec!["killswitch", "diff"]);
+        let action = handler(&matches, Verbosity::Normal).unwrap();
+        assert!(matches!(action, Action::Diff { .. }));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_handler_portal() {
+        use crate::cli::verbosity::Verbosity;
+        let matches = commands::new().get_matches_from(vec!["killswitch", "portal", "--for", "3m"]);
+        let action = handler(&matches, Verbosity::Normal).unwrap();
+        assert!(matches!(
+            action,
+            Action::Portal { window, .. } if window == Duration::from_secs(180)
+        ));
+    }
+
     #[allow(clippy::unwrap_used)]
     #[test]
     fn test_handler_no_action() {
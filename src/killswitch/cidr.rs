@@ -0,0 +1,160 @@
+//! IPv4/IPv6 networks in CIDR notation (`192.168.1.0/24`).
+
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An address with a prefix length; a bare address is a host (`/32`, `/128`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl Cidr {
+    /// The address as given (may have host bits set, e.g. an interface address).
+    #[must_use]
+    pub const fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// The prefix length, `0` for a default route.
+    #[must_use]
+    pub const fn prefix_len(&self) -> u8 {
+        self.len
+    }
+
+    const fn max_len(addr: &IpAddr) -> u8 {
+        if addr.is_ipv4() { 32 } else { 128 }
+    }
+
+    fn bits(addr: &IpAddr) -> u128 {
+        match addr {
+            IpAddr::V4(v4) => u128::from(u32::from(*v4)),
+            IpAddr::V6(v6) => u128::from(*v6),
+        }
+    }
+
+    fn mask(&self) -> u128 {
+        let host_bits = u32::from(Self::max_len(&self.addr) - self.len);
+        u128::MAX.checked_shl(host_bits).unwrap_or(0)
+    }
+
+    /// The network with the host bits cleared.
+    #[must_use]
+    pub fn network(&self) -> Self {
+        let bits = Self::bits(&self.addr) & self.mask();
+        let addr = match self.addr {
+            // Truncation keeps the 32 address bits
+            #[allow(clippy::cast_possible_truncation)]
+            IpAddr::V4(_) => IpAddr::V4((bits as u32).into()),
+            IpAddr::V6(_) => IpAddr::V6(bits.into()),
+        };
+        Self {
+            addr,
+            len: self.len,
+        }
+    }
+
+    /// The last address of the network.
+    #[must_use]
+    pub fn last(&self) -> IpAddr {
+        let bits = Self::bits(&self.addr) | !self.mask();
+        match self.addr {
+            #[allow(clippy::cast_possible_truncation)]
+            IpAddr::V4(_) => IpAddr::V4((bits as u32).into()),
+            IpAddr::V6(_) => IpAddr::V6(bits.into()),
+        }
+    }
+
+    /// Whether `addr` is inside the network.
+    #[must_use]
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        addr.is_ipv4() == self.addr.is_ipv4()
+            && Self::bits(addr) & self.mask() == Self::bits(&self.addr) & self.mask()
+    }
+
+    /// Whether both networks share at least one address.
+    #[must_use]
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.contains(&other.addr) || other.contains(&self.addr)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .with_context(|| format!("Invalid network: {s}"))?;
+        let max = Self::max_len(&addr);
+        let len = match len.map(str::parse::<u8>) {
+            None => max,
+            Some(Ok(len)) if len <= max => len,
+            Some(_) => bail!("Invalid network prefix: {s}"),
+        };
+        Ok(Self { addr, len })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.len == Self::max_len(&self.addr) {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::unwrap_used)]
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    #[allow(clippy::unwrap_used)]
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!(cidr("192.168.1.0/24").to_string(), "192.168.1.0/24");
+        assert_eq!(cidr("192.168.1.1").to_string(), "192.168.1.1");
+        assert_eq!(cidr("192.168.1.1/32").to_string(), "192.168.1.1");
+        assert_eq!(cidr("2001:db8::/32").to_string(), "2001:db8::/32");
+        assert!("192.168.1.0/33".parse::<Cidr>().is_err());
+        assert!("printer/24".parse::<Cidr>().is_err());
+        assert!("192.168.1.0/".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_network_and_last() {
+        let iface = cidr("192.168.1.100/24");
+        assert_eq!(iface.network(), cidr("192.168.1.0/24"));
+        assert_eq!(iface.last(), ip("192.168.1.255"));
+        assert_eq!(cidr("0.0.0.0/0").last(), ip("255.255.255.255"));
+        assert_eq!(cidr("fd00::1/64").network(), cidr("fd00::/64"));
+    }
+
+    #[test]
+    fn test_contains_and_overlaps() {
+        let lan = cidr("192.168.1.0/24");
+        assert!(lan.contains(&ip("192.168.1.1")));
+        assert!(!lan.contains(&ip("192.168.2.1")));
+        assert!(!lan.contains(&ip("::1")));
+        assert!(cidr("192.168.0.0/16").overlaps(&lan));
+        assert!(lan.overlaps(&cidr("192.168.1.128/25")));
+        assert!(!lan.overlaps(&cidr("10.0.0.0/8")));
+    }
+}
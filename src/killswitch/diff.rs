@@ -0,0 +1,480 @@
+//! Semantic comparison of the loaded ruleset with a fresh generation.
+//!
+//! `pfctl -sr` prints the loaded rules with macros substituted, every list
+//! expanded into one rule per element and the defaults pf fills in (`inet`
+//! on rules with IPv4 addresses, `flags S/SA keep state`). The freshly
+//! generated ruleset is brought into the same form before comparing, and
+//! tables are compared entry by entry, so the differences read as what
+//! changed on the network ("peer 203.0.113.1 changed to 198.51.100.7",
+//! "utun3 missing") instead of as a text diff.
+
+use crate::killswitch::cidr::Cidr;
+use crate::killswitch::lint;
+use crate::killswitch::rules::PEERS_TABLE;
+use crate::killswitch::ruleset::{
+    Action, AddressFamily, Endpoint, Host, Interface, Port, Protocol, Rule, Ruleset,
+};
+use crate::killswitch::snapshot::NetworkSnapshot;
+use anyhow::{Result, bail};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Statements `pfctl -sr` prints for the stock anchors of `/etc/pf.conf`,
+/// outside the subset the kill switch generates
+const ANCHOR_STATEMENTS: &[&str] = &[
+    "anchor",
+    "scrub",
+    "scrub-anchor",
+    "nat-anchor",
+    "rdr-anchor",
+    "dummynet-anchor",
+    "load",
+];
+
+/// A difference between the loaded rules and a fresh generation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// An interface the fresh rules cover that has no loaded rules
+    Missing(String),
+    /// An interface with loaded rules that the fresh rules no longer cover
+    Gone(String),
+    /// A table entry replaced by another
+    Replaced {
+        table: String,
+        from: String,
+        to: String,
+    },
+    /// A table entry only in the fresh rules
+    Added { table: String, entry: String },
+    /// A table entry only in the loaded rules
+    Removed { table: String, entry: String },
+    /// A rule only in the fresh rules
+    RuleAdded(Rule),
+    /// A rule only in the loaded rules
+    RuleRemoved(Rule),
+}
+
+impl Change {
+    /// Whether `refresh` brings the loaded rules up to date, instead of
+    /// enabling the kill switch again.
+    #[must_use]
+    pub const fn is_table(&self) -> bool {
+        matches!(
+            self,
+            Self::Replaced { .. } | Self::Added { .. } | Self::Removed { .. }
+        )
+    }
+}
+
+/// How a table entry is named in messages
+fn entry_label(table: &str) -> String {
+    if table == PEERS_TABLE {
+        "peer".to_string()
+    } else {
+        format!("<{table}>")
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(iface) => write!(f, "{iface} missing"),
+            Self::Gone(iface) => write!(f, "{iface} gone, its rules are still loaded"),
+            Self::Replaced { table, from, to } => {
+                write!(f, "{} {from} changed to {to}", entry_label(table))
+            }
+            Self::Added { table, entry } => write!(f, "{} {entry} added", entry_label(table)),
+            Self::Removed { table, entry } => {
+                write!(f, "{} {entry} removed", entry_label(table))
+            }
+            Self::RuleAdded(rule) => write!(f, "+ {rule}"),
+            Self::RuleRemoved(rule) => write!(f, "- {rule}"),
+        }
+    }
+}
+
+/// Compare the loaded rules (`pfctl -sr` output) and tables with a fresh
+/// generation from `snapshot`.
+///
+/// Rules are compared regardless of their order. Rules on a missing or gone
+/// interface are reported through the interface alone.
+///
+/// # Errors
+/// Returns an error if the loaded rules cannot be parsed
+pub fn compare(
+    loaded: &str,
+    tables: impl Fn(&str) -> Vec<String>,
+    fresh: &Ruleset,
+    snapshot: &NetworkSnapshot,
+) -> Result<Vec<Change>> {
+    let loaded = parse_loaded(loaded)?;
+    let loaded = normalize(&loaded, snapshot);
+    let fresh_rules = normalize(fresh, snapshot);
+
+    let loaded_ifaces = interfaces(&loaded);
+    let fresh_ifaces = interfaces(&fresh_rules);
+    let mut changes: Vec<Change> = fresh_ifaces
+        .difference(&loaded_ifaces)
+        .map(|iface| Change::Missing(iface.clone()))
+        .collect();
+    changes.extend(
+        loaded_ifaces
+            .difference(&fresh_ifaces)
+            .map(|iface| Change::Gone(iface.clone())),
+    );
+
+    for table in fresh.tables() {
+        let entries: Vec<String> = tables(&table.name).iter().map(|a| canonical(a)).collect();
+        let expected: Vec<String> = table.addresses.iter().map(|a| canonical(a)).collect();
+        changes.extend(table_changes(&table.name, &entries, &expected));
+    }
+
+    let involves = |rule: &Rule, ifaces: &BTreeSet<String>| {
+        rule_interfaces(rule).any(|iface| !ifaces.contains(iface))
+    };
+    let mut remaining = loaded;
+    let mut added = Vec::new();
+    for rule in fresh_rules {
+        if let Some(index) = remaining.iter().position(|r| *r == rule) {
+            remaining.remove(index);
+        } else if !involves(&rule, &loaded_ifaces) {
+            added.push(Change::RuleAdded(rule));
+        }
+    }
+    changes.extend(added);
+    changes.extend(
+        remaining
+            .into_iter()
+            .filter(|rule| !involves(rule, &fresh_ifaces))
+            .map(Change::RuleRemoved),
+    );
+    Ok(changes)
+}
+
+/// Parse `pfctl -sr` output, skipping the stock anchors of the main ruleset
+fn parse_loaded(text: &str) -> Result<Ruleset> {
+    let rules: Vec<&str> = text
+        .lines()
+        .filter(|line| {
+            let first = line.split_whitespace().next().unwrap_or("");
+            !ANCHOR_STATEMENTS.contains(&first)
+        })
+        .collect();
+    let rules = rules.join("\n");
+    match lint::parse(&rules) {
+        Ok(parsed) => Ok(parsed.ruleset),
+        Err(errors) => {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            bail!("Cannot parse the loaded rules:\n{}", errors.join("\n"))
+        }
+    }
+}
+
+/// Pair the entries only in the loaded table with those only in the fresh
+/// one as replacements, the rest are added or removed
+fn table_changes(table: &str, loaded: &[String], fresh: &[String]) -> Vec<Change> {
+    let removed: Vec<&String> = loaded.iter().filter(|e| !fresh.contains(e)).collect();
+    let added: Vec<&String> = fresh.iter().filter(|e| !loaded.contains(e)).collect();
+    let mut changes: Vec<Change> = removed
+        .iter()
+        .zip(&added)
+        .map(|(from, to)| Change::Replaced {
+            table: table.to_string(),
+            from: (*from).clone(),
+            to: (*to).clone(),
+        })
+        .collect();
+    let paired = changes.len();
+    changes.extend(added.iter().skip(paired).map(|entry| Change::Added {
+        table: table.to_string(),
+        entry: (*entry).clone(),
+    }));
+    changes.extend(removed.iter().skip(paired).map(|entry| Change::Removed {
+        table: table.to_string(),
+        entry: (*entry).clone(),
+    }));
+    changes
+}
+
+/// An address as pfctl prints it (`10.0.0.1/32` is `10.0.0.1`)
+fn canonical(addr: &str) -> String {
+    addr.parse::<Cidr>()
+        .map_or_else(|_| addr.to_string(), |cidr| cidr.to_string())
+}
+
+fn rule_interfaces(rule: &Rule) -> impl Iterator<Item = &String> {
+    [&rule.interface, &rule.route_to]
+        .into_iter()
+        .filter_map(|iface| match iface {
+            Some(Interface::Name(name)) => Some(name),
+            _ => None,
+        })
+}
+
+fn interfaces(rules: &[Rule]) -> BTreeSet<String> {
+    rules.iter().flat_map(rule_interfaces).cloned().collect()
+}
+
+/// Bring rules into the form `pfctl -sr` prints them in
+fn normalize(ruleset: &Ruleset, snapshot: &NetworkSnapshot) -> Vec<Rule> {
+    let macros: HashMap<&str, &str> = ruleset
+        .macros()
+        .map(|m| (m.name.as_str(), m.value.as_str()))
+        .collect();
+    let resolve = |iface: &Option<Interface>| {
+        iface.as_ref().map(|iface| match iface {
+            Interface::Macro(name) => Interface::Name(
+                macros
+                    .get(name.as_str())
+                    .unwrap_or(&name.as_str())
+                    .to_string(),
+            ),
+            Interface::Name(_) => iface.clone(),
+        })
+    };
+
+    let mut rules = Vec::new();
+    for rule in ruleset.rules() {
+        let mut base = rule.clone();
+        base.interface = resolve(&rule.interface);
+        base.route_to = resolve(&rule.route_to);
+        // pf keeps state on pass rules unless told otherwise
+        base.keep_state = rule.action == Action::Pass;
+        for rule in expand(&base, &macros, snapshot) {
+            if let Some(rule) = with_family(rule) {
+                rules.push(rule);
+            }
+        }
+    }
+    rules
+}
+
+/// Expand protocol, host, port, user, group and ICMP type lists into one
+/// rule each
+fn expand(rule: &Rule, macros: &HashMap<&str, &str>, snapshot: &NetworkSnapshot) -> Vec<Rule> {
+    let protocols: Vec<Vec<Protocol>> = if rule.protocols.is_empty() {
+        vec![Vec::new()]
+    } else {
+        rule.protocols.iter().map(|p| vec![*p]).collect()
+    };
+    let icmp_types: Vec<Vec<_>> = if rule.icmp_types.is_empty() {
+        vec![Vec::new()]
+    } else {
+        rule.icmp_types.iter().map(|t| vec![t.clone()]).collect()
+    };
+    let froms = endpoints(&rule.from, macros, snapshot);
+    let tos = endpoints(&rule.to, macros, snapshot);
+    let owners = owners(rule);
+
+    let mut rules = Vec::new();
+    for protocols in &protocols {
+        for from in &froms {
+            for to in &tos {
+                for (users, groups) in &owners {
+                    for icmp_types in &icmp_types {
+                        let mut rule = rule.clone();
+                        rule.protocols.clone_from(protocols);
+                        rule.from = from.clone();
+                        rule.to = to.clone();
+                        rule.users.clone_from(users);
+                        rule.groups.clone_from(groups);
+                        rule.icmp_types.clone_from(icmp_types);
+                        rules.push(rule);
+                    }
+                }
+            }
+        }
+    }
+    rules
+}
+
+/// Every single user and group combination of a rule
+fn owners(rule: &Rule) -> Vec<(Vec<u32>, Vec<u32>)> {
+    let split = |ids: &[u32]| -> Vec<Vec<u32>> {
+        if ids.is_empty() {
+            vec![Vec::new()]
+        } else {
+            ids.iter().map(|id| vec![*id]).collect()
+        }
+    };
+    let groups = split(&rule.groups);
+    split(&rule.users)
+        .into_iter()
+        .flat_map(|users| groups.iter().map(move |g| (users.clone(), g.clone())))
+        .collect()
+}
+
+fn endpoints(
+    endpoint: &Endpoint,
+    macros: &HashMap<&str, &str>,
+    snapshot: &NetworkSnapshot,
+) -> Vec<Endpoint> {
+    let ports = match &endpoint.port {
+        Some(Port::List(ports)) => ports.iter().map(|p| Some(Port::Single(*p))).collect(),
+        Some(port) => vec![Some(port.clone())],
+        None => vec![None],
+    };
+    let hosts = hosts(&endpoint.host, macros, snapshot);
+    hosts
+        .iter()
+        .flat_map(|host| {
+            ports.iter().map(|port| Endpoint {
+                host: host.clone(),
+                port: port.clone(),
+            })
+        })
+        .collect()
+}
+
+fn hosts(host: &Host, macros: &HashMap<&str, &str>, snapshot: &NetworkSnapshot) -> Vec<Host> {
+    match host {
+        Host::Any | Host::Table(_) => vec![host.clone()],
+        Host::Address(addr) => vec![Host::Address(canonical(addr))],
+        Host::Macro(name) => {
+            let value = macros.get(name.as_str()).copied().unwrap_or(name);
+            vec![Host::Address(canonical(value))]
+        }
+        // pf substitutes the networks of the interface when loading
+        Host::Network(name) => {
+            let iface = macros.get(name.as_str()).copied().unwrap_or(name);
+            let networks: Vec<Host> = snapshot
+                .interfaces
+                .iter()
+                .filter(|i| i.name() == iface)
+                .filter_map(|i| i.ip().parse::<Cidr>().ok())
+                .map(|cidr| Host::Address(cidr.network().to_string()))
+                .collect();
+            if networks.is_empty() {
+                vec![Host::Address(format!("{iface}:network"))]
+            } else {
+                networks
+            }
+        }
+        Host::Not(inner) => hosts(inner, macros, snapshot)
+            .into_iter()
+            .map(|h| Host::Not(Box::new(h)))
+            .collect(),
+        Host::List(list) => list
+            .iter()
+            .flat_map(|h| hosts(h, macros, snapshot))
+            .collect(),
+    }
+}
+
+/// The address family pf infers from the addresses and protocol, `None`
+/// for a combination pf drops when expanding (IPv4 source, IPv6 destination)
+fn with_family(mut rule: Rule) -> Option<Rule> {
+    fn family(host: &Host) -> Option<AddressFamily> {
+        match host {
+            Host::Address(addr) => addr.parse::<Cidr>().ok().map(|_| {
+                if addr.contains(':') {
+                    AddressFamily::Inet6
+                } else {
+                    AddressFamily::Inet
+                }
+            }),
+            Host::Not(host) => family(host),
+            _ => None,
+        }
+    }
+    let protocol = rule.protocols.iter().find_map(|p| match p {
+        Protocol::Icmp => Some(AddressFamily::Inet),
+        Protocol::Icmp6 => Some(AddressFamily::Inet6),
+        Protocol::Tcp | Protocol::Udp => None,
+    });
+    let implied = [family(&rule.from.host), family(&rule.to.host), protocol];
+    for family in implied.into_iter().flatten() {
+        match rule.family {
+            Some(known) if known != family => return None,
+            _ => rule.family = Some(family),
+        }
+    }
+    Some(rule)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::killswitch::network::InterfaceInfo;
+
+    fn snapshot() -> NetworkSnapshot {
+        NetworkSnapshot {
+            interfaces: vec![
+                InterfaceInfo::new("en0", "aa:bb:cc:dd:ee:ff", "192.168.1.10/24", false),
+                InterfaceInfo::new("utun3", "", "10.8.0.2", true),
+            ],
+            ..NetworkSnapshot::default()
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    fn fresh(text: &str) -> Ruleset {
+        lint::parse(text).unwrap().ruleset
+    }
+
+    const FRESH: &str = "int_en0 = \"en0\"
+vpn_utun3 = \"utun3\"
+table <vpn_peers> persist { 198.51.100.7 }
+block all
+pass quick on lo0 all
+pass out on $int_en0 proto { tcp, udp } from any to <vpn_peers> port { 443, 1194 } keep state
+pass from $int_en0:network to $int_en0:network
+pass on $vpn_utun3 inet all
+";
+
+    // As printed by `pfctl -sr` for FRESH
+    const LOADED: &str = "scrub-anchor \"com.apple/*\" all fragment reassemble
+block drop all
+pass quick on lo0 all flags S/SA keep state
+pass out on en0 proto tcp from any to <vpn_peers> port = https flags S/SA keep state
+pass out on en0 proto tcp from any to <vpn_peers> port = openvpn flags S/SA keep state
+pass out on en0 proto udp from any to <vpn_peers> port = https keep state
+pass out on en0 proto udp from any to <vpn_peers> port = openvpn keep state
+pass inet from 192.168.1.0/24 to 192.168.1.0/24 flags S/SA keep state
+pass on utun3 inet all flags S/SA keep state
+";
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_compare_unchanged() {
+        let tables = |_: &str| vec!["198.51.100.7".to_string()];
+        let changes = compare(LOADED, tables, &fresh(FRESH), &snapshot()).unwrap();
+        assert_eq!(changes, []);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_compare_changes() {
+        let loaded = LOADED.replace("utun3", "utun2").replace(
+            "pass inet from 192.168.1.0/24 to 192.168.1.0/24 flags S/SA keep state\n",
+            "pass inet from 10.0.0.0/24 to 10.0.0.0/24 flags S/SA keep state\n",
+        );
+        let tables = |_: &str| vec!["203.0.113.1".to_string(), "203.0.113.2".to_string()];
+        let changes = compare(&loaded, tables, &fresh(FRESH), &snapshot()).unwrap();
+        let messages: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "utun3 missing",
+                "utun2 gone, its rules are still loaded",
+                "peer 203.0.113.1 changed to 198.51.100.7",
+                "peer 203.0.113.2 removed",
+                "+ pass inet from 192.168.1.0/24 to 192.168.1.0/24 keep state",
+                "- pass inet from 10.0.0.0/24 to 10.0.0.0/24 keep state",
+            ]
+        );
+        assert!(changes.get(2).is_some_and(Change::is_table));
+        assert!(!changes.iter().all(Change::is_table));
+    }
+
+    #[test]
+    fn test_compare_unparseable() {
+        let result = compare(
+            "pass on en0 proto gre all\n",
+            |_| Vec::new(),
+            &Ruleset::new(),
+            &snapshot(),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! User pf fragments spliced into the generated ruleset.
+//!
+//! A fragment directory holds one subdirectory per position, each with
+//! `*.conf` files read in name order:
+//!
+//! - `before-block/`: before `block all`, only `quick` rules take effect
+//! - `after-exemptions/`: after the physical interface and bridge rules
+//! - `after-tunnel/`: last, after the VPN interface `pass`
+
+use crate::killswitch::lint::{self, Parsed};
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Where a fragment is spliced into the ruleset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    BeforeBlock,
+    AfterExemptions,
+    AfterTunnel,
+}
+
+impl Position {
+    pub const ALL: [Self; 3] = [Self::BeforeBlock, Self::AfterExemptions, Self::AfterTunnel];
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::BeforeBlock => "before-block",
+            Self::AfterExemptions => "after-exemptions",
+            Self::AfterTunnel => "after-tunnel",
+        })
+    }
+}
+
+/// A parsed fragment file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    pub position: Position,
+    /// Path relative to the fragment directory, e.g. `after-tunnel/game.conf`
+    pub name: String,
+    pub parsed: Parsed,
+}
+
+/// Read and parse the fragments of `dir`; missing position subdirectories
+/// are skipped.
+///
+/// # Errors
+/// Returns an error if the directory or a fragment cannot be read, or a
+/// fragment is not valid pf syntax
+pub fn load(dir: &Path) -> Result<Vec<Fragment>> {
+    if !dir.is_dir() {
+        bail!("Fragment directory not found: {}", dir.display());
+    }
+    let mut fragments = Vec::new();
+    for position in Position::ALL {
+        let subdir = dir.join(position.to_string());
+        if !subdir.is_dir() {
+            continue;
+        }
+        let entries = fs::read_dir(&subdir)
+            .with_context(|| format!("Failed to read {}", subdir.display()))?;
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry
+                .with_context(|| format!("Failed to read {}", subdir.display()))?
+                .path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "conf") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        for path in paths {
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read fragment {}", path.display()))?;
+            let name = format!(
+                "{position}/{}",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            );
+            let parsed = lint::parse(&text).map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                anyhow::anyhow!("Invalid fragment {name}:\n  {}", errors.join("\n  "))
+            })?;
+            fragments.push(Fragment {
+                position,
+                name,
+                parsed,
+            });
+        }
+    }
+    Ok(fragments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("killswitch-fragments-{}", std::process::id()));
+        let write = |name: &str, text: &str| {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        };
+        write(
+            "after-tunnel/20-block.conf",
+            "block out quick proto tcp to any port 25\n",
+        );
+        write(
+            "after-tunnel/10-game.conf",
+            "pass out on $vpn_utun3 proto udp to any port 27015\n",
+        );
+        write("after-tunnel/notes.txt", "not a fragment\n");
+        write("before-block/lan.conf", "# LAN game server\n");
+
+        let fragments = load(&dir).unwrap();
+        let names: Vec<&str> = fragments.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "before-block/lan.conf",
+                "after-tunnel/10-game.conf",
+                "after-tunnel/20-block.conf"
+            ]
+        );
+        assert_eq!(
+            fragments.first().map(|f| f.position),
+            Some(Position::BeforeBlock)
+        );
+
+        write("after-exemptions/bad.conf", "pass on en0 proto gre all\n");
+        let err = load(&dir).unwrap_err().to_string();
+        assert!(err.contains("after-exemptions/bad.conf"), "{err}");
+        assert!(
+            err.contains("line 1: error: unsupported protocol `gre`"),
+            "{err}"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(load(&dir).is_err());
+    }
+}
@@ -0,0 +1,1067 @@
+//! Offline parser and linter for pf rulesets.
+//!
+//! Parses the subset of the pf grammar the kill switch generates (macros,
+//! `set` options, tables and filter rules) back into a [`Ruleset`], on any
+//! OS and without `pfctl`. The parsed ruleset is then checked for mistakes
+//! pf would accept but that break the kill switch: undefined macros, rules
+//! never evaluated because an earlier `quick` rule matches all of their
+//! packets, and `pass` rules that let traffic to any destination out of a
+//! physical interface.
+
+use crate::killswitch::cidr::Cidr;
+use crate::killswitch::is_private_addr;
+use crate::killswitch::ruleset::{
+    Action, AddressFamily, BlockPolicy, Direction, Endpoint, Host, IcmpType, Interface, Macro,
+    Optimization, PfOption, Port, Protocol, Rule, Ruleset, Statement, Table,
+};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Port names `pfctl -sr` prints instead of numbers (`/etc/services`)
+const SERVICES: &[(&str, u16)] = &[
+    ("ssh", 22),
+    ("smtp", 25),
+    ("bootps", 67),
+    ("bootpc", 68),
+    ("www", 80),
+    ("http", 80),
+    ("ntp", 123),
+    ("https", 443),
+    ("isakmp", 500),
+    ("submission", 587),
+    ("dhcpv6-client", 546),
+    ("dhcpv6-server", 547),
+    ("domain-s", 853),
+    ("openvpn", 1194),
+    ("ssdp", 1900),
+    ("ms-wbt-server", 3389),
+    ("stun", 3478),
+    ("ipsec-nat-t", 4500),
+    ("mdns", 5353),
+    ("rfb", 5900),
+    ("domain", 53),
+];
+
+/// Interfaces whose traffic goes through the tunnel (or never leaves the
+/// machine)
+const TUNNEL_IFACES: &[&str] = &["utun", "tun", "tap", "ppp", "ipsec", "wg", "lo"];
+
+/// How serious a finding is: errors make pfctl reject the ruleset or
+/// break the kill switch, warnings are likely mistakes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        })
+    }
+}
+
+/// A finding on a line of the ruleset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Line number, starting at 1
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.severity, self.message)
+    }
+}
+
+/// A parsed ruleset with the line each statement starts on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Parsed {
+    pub ruleset: Ruleset,
+    pub lines: Vec<usize>,
+}
+
+impl Parsed {
+    /// Rules with their line number.
+    pub fn rules(&self) -> impl Iterator<Item = (usize, &Rule)> {
+        self.ruleset
+            .statements()
+            .iter()
+            .zip(&self.lines)
+            .filter_map(|(statement, &line)| match statement {
+                Statement::Rule(rule) => Some((line, rule)),
+                _ => None,
+            })
+    }
+}
+
+/// Parse a pf ruleset.
+///
+/// Comments and blank lines are kept, so rendering the parsed ruleset gives
+/// back a generated one unchanged.
+///
+/// # Errors
+/// Returns a diagnostic for every line that is not valid pf syntax or uses
+/// a construct outside the supported subset.
+pub fn parse(text: &str) -> Result<Parsed, Vec<Diagnostic>> {
+    let mut parsed = Parsed::default();
+    let mut errors = Vec::new();
+    for (line, content) in logical_lines(text) {
+        match parse_line(&content) {
+            Ok(statement) => {
+                parsed.ruleset.push(statement);
+                parsed.lines.push(line);
+            }
+            Err(message) => errors.push(Diagnostic::error(line, message)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Check a parsed ruleset for undefined macros, rules shadowed by an
+/// earlier `quick` rule and `pass` rules that defeat the kill switch.
+#[must_use]
+pub fn check(parsed: &Parsed) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut macros: HashMap<&str, &str> = HashMap::new();
+    let rules: Vec<(usize, &Rule)> = parsed.rules().collect();
+    let mut index = 0;
+    for (statement, &line) in parsed.ruleset.statements().iter().zip(&parsed.lines) {
+        match statement {
+            Statement::Macro(m) => {
+                macros.insert(&m.name, &m.value);
+            }
+            Statement::Rule(rule) => {
+                for name in macro_refs(rule) {
+                    if !macros.contains_key(name) {
+                        diagnostics
+                            .push(Diagnostic::error(line, format!("undefined macro ${name}")));
+                    }
+                }
+                if let Some(message) = defeats(rule, &macros)
+                    && !narrowed_to_others(&rules, index)
+                {
+                    diagnostics.push(Diagnostic::warning(line, message));
+                }
+                index += 1;
+            }
+            _ => {}
+        }
+    }
+
+    for (i, (line, rule)) in rules.iter().enumerate() {
+        let shadow = rules
+            .iter()
+            .take(i)
+            .find(|(_, earlier)| earlier.quick && covers(earlier, rule));
+        if let Some((quick, _)) = shadow {
+            diagnostics.push(Diagnostic::warning(
+                *line,
+                format!("unreachable: the quick rule on line {quick} matches all of its packets"),
+            ));
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.line);
+    diagnostics
+}
+
+/// Parse and check a pf ruleset: syntax errors, or the findings of
+/// [`check`].
+#[must_use]
+pub fn lint(text: &str) -> Vec<Diagnostic> {
+    match parse(text) {
+        Ok(parsed) => check(&parsed),
+        Err(errors) => errors,
+    }
+}
+
+/// Check a fragment spliced into `ruleset`: macros must be defined by either,
+/// generated macros and tables cannot be redefined, `set` options cannot
+/// follow rules, and `pass` rules must stay on the
+/// tunnel (or loopback) interfaces unless `physical` is set.
+#[must_use]
+pub fn check_fragment(parsed: &Parsed, ruleset: &Ruleset, physical: bool) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut macros: HashMap<&str, &str> = ruleset
+        .macros()
+        .map(|m| (m.name.as_str(), m.value.as_str()))
+        .collect();
+    for (statement, &line) in parsed.ruleset.statements().iter().zip(&parsed.lines) {
+        match statement {
+            Statement::Macro(m) if ruleset.macros().any(|g| g.name == m.name) => {
+                diagnostics.push(Diagnostic::error(
+                    line,
+                    format!("redefines the generated macro ${}", m.name),
+                ));
+            }
+            Statement::Macro(m) => {
+                macros.insert(&m.name, &m.value);
+            }
+            Statement::Table(t) if ruleset.table(&t.name).is_some() => {
+                diagnostics.push(Diagnostic::error(
+                    line,
+                    format!("redefines the generated table <{}>", t.name),
+                ));
+            }
+            Statement::Option(_) => {
+                diagnostics.push(Diagnostic::error(
+                    line,
+                    "`set` options are not allowed in a fragment",
+                ));
+            }
+            Statement::Rule(rule) => {
+                for name in macro_refs(rule) {
+                    if !macros.contains_key(name) {
+                        diagnostics
+                            .push(Diagnostic::error(line, format!("undefined macro ${name}")));
+                    }
+                }
+                if rule.action == Action::Pass && !physical {
+                    let iface = [&rule.interface, &rule.route_to]
+                        .into_iter()
+                        .flatten()
+                        .find(|iface| !is_tunnel(iface, &macros));
+                    if let Some(iface) = iface {
+                        diagnostics.push(Diagnostic::error(
+                            line,
+                            format!("`pass` on the physical interface {iface}"),
+                        ));
+                    } else if rule.interface.is_none() {
+                        diagnostics.push(Diagnostic::error(
+                            line,
+                            "`pass` without `on` also matches the physical interfaces",
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    diagnostics
+}
+
+/// Join continued lines (`\` at the end) and number them by their first line
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (index, raw) in text.lines().enumerate() {
+        let (start, mut content) = pending.take().unwrap_or((index + 1, String::new()));
+        if let Some(continued) = raw.strip_suffix('\\') {
+            content.push_str(continued);
+            content.push(' ');
+            pending = Some((start, content));
+        } else {
+            content.push_str(raw);
+            lines.push((start, content));
+        }
+    }
+    lines.extend(pending);
+    lines
+}
+
+fn parse_line(line: &str) -> Result<Statement, String> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        return Ok(Statement::Blank);
+    }
+    if let Some(comment) = trimmed.strip_prefix('#') {
+        let comment = comment.strip_prefix(' ').unwrap_or(comment);
+        return Ok(Statement::Comment(comment.to_string()));
+    }
+
+    let tokens = tokenize(strip_comment(trimmed))?;
+    let mut cursor = Cursor::new(&tokens);
+    let statement = match cursor.peek() {
+        Some("set") => Statement::Option(parse_option(&mut cursor)?),
+        Some("table") => Statement::Table(parse_table(&mut cursor)?),
+        Some("pass" | "block") => Statement::Rule(parse_rule(&mut cursor)?),
+        Some(_) if tokens.get(1).is_some_and(|t| t == "=") => {
+            Statement::Macro(parse_macro(&mut cursor)?)
+        }
+        Some(word) => return Err(format!("unsupported statement `{word}`")),
+        None => return Ok(Statement::Blank),
+    };
+    if let Some(extra) = cursor.peek() {
+        return Err(format!("unexpected `{extra}`"));
+    }
+    Ok(statement)
+}
+
+/// Drop a trailing `# comment`, outside quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return line.get(..i).unwrap_or(line),
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Split a line into words, quoted strings, `<table>` references and the
+/// punctuation `{ } , ! =`
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '{' | '}' | ',' | '!' | '=' => {
+                tokens.push(c.to_string());
+                chars.next();
+            }
+            '"' | '<' => {
+                let close = if c == '"' { '"' } else { '>' };
+                let mut token = String::from(c);
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some(next) if next == close => break,
+                        Some(next) => token.push(next),
+                        None => return Err(format!("missing closing `{close}`")),
+                    }
+                }
+                token.push(close);
+                tokens.push(token);
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "{},!=\"<".contains(next) {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push(word);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Position in the tokens of a statement
+struct Cursor<'a> {
+    tokens: &'a [String],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    const fn new(tokens: &'a [String]) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    /// Consume the token if it is `word`
+    fn eat(&mut self, word: &str) -> bool {
+        let found = self.peek() == Some(word);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == word => Ok(()),
+            Some(token) => Err(format!("expected `{word}`, found `{token}`")),
+            None => Err(format!("expected `{word}`")),
+        }
+    }
+
+    fn word(&mut self, what: &str) -> Result<&'a str, String> {
+        self.next().ok_or_else(|| format!("missing {what}"))
+    }
+
+    /// A single item or a `{ a, b }` list (commas optional)
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        if !self.eat("{") {
+            return Ok(vec![item(self)?]);
+        }
+        let mut items = Vec::new();
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return Err("missing closing `}`".to_string());
+            }
+            if !self.eat(",") {
+                items.push(item(self)?);
+            }
+        }
+        Ok(items)
+    }
+}
+
+fn parse_macro(cursor: &mut Cursor) -> Result<Macro, String> {
+    let name = cursor.word("macro name")?;
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!("invalid macro name `{name}`"));
+    }
+    cursor.expect("=")?;
+    let value = cursor.word("macro value")?;
+    Ok(Macro {
+        name: name.to_string(),
+        value: unquote(value).to_string(),
+    })
+}
+
+fn unquote(token: &str) -> &str {
+    token
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .unwrap_or(token)
+}
+
+fn parse_option(cursor: &mut Cursor) -> Result<PfOption, String> {
+    cursor.expect("set")?;
+    match cursor.word("option")? {
+        "block-policy" => match cursor.word("block policy")? {
+            "drop" => Ok(PfOption::BlockPolicy(BlockPolicy::Drop)),
+            "return" => Ok(PfOption::BlockPolicy(BlockPolicy::Return)),
+            other => Err(format!("invalid block policy `{other}`")),
+        },
+        "ruleset-optimization" => match cursor.word("optimization")? {
+            "none" => Ok(PfOption::RulesetOptimization(Optimization::None)),
+            "basic" => Ok(PfOption::RulesetOptimization(Optimization::Basic)),
+            "profile" => Ok(PfOption::RulesetOptimization(Optimization::Profile)),
+            other => Err(format!("invalid ruleset optimization `{other}`")),
+        },
+        "skip" => {
+            cursor.expect("on")?;
+            Ok(PfOption::Skip(cursor.word("interface")?.to_string()))
+        }
+        other => Err(format!("unsupported option `set {other}`")),
+    }
+}
+
+fn parse_table(cursor: &mut Cursor) -> Result<Table, String> {
+    cursor.expect("table")?;
+    let name = table_name(cursor.word("table name")?)?;
+    let mut table = Table {
+        name: name.to_string(),
+        persist: false,
+        addresses: Vec::new(),
+    };
+    while let Some(word) = cursor.peek() {
+        match word {
+            "persist" => table.persist = true,
+            // Read-only and counting tables behave the same for the linter
+            "const" | "counters" => {}
+            "{" => {
+                table.addresses = cursor.list(|c| {
+                    let negated = c.eat("!");
+                    let addr = c.word("address")?;
+                    address(addr)?;
+                    Ok(if negated {
+                        format!("!{addr}")
+                    } else {
+                        addr.to_string()
+                    })
+                })?;
+                continue;
+            }
+            other => return Err(format!("unsupported table option `{other}`")),
+        }
+        cursor.next();
+    }
+    Ok(table)
+}
+
+fn table_name(token: &str) -> Result<&str, String> {
+    token
+        .strip_prefix('<')
+        .and_then(|t| t.strip_suffix('>'))
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| format!("invalid table `{token}`"))
+}
+
+fn parse_rule(cursor: &mut Cursor) -> Result<Rule, String> {
+    let mut rule = match cursor.next() {
+        Some("pass") => Rule::pass(),
+        _ => Rule::block(),
+    };
+    if rule.action == Action::Block {
+        // The block policy of the rule, `set block-policy` is the default
+        let _ = cursor.eat("drop") || cursor.eat("return");
+    }
+    if cursor.eat("in") {
+        rule = rule.direction(Direction::In);
+    } else if cursor.eat("out") {
+        rule = rule.direction(Direction::Out);
+    }
+    if cursor.eat("log") {
+        rule = rule.log();
+    }
+    if cursor.eat("quick") {
+        rule = rule.quick();
+    }
+    if cursor.eat("on") {
+        rule.interface = Some(interface(cursor.word("interface")?)?);
+    }
+    if cursor.eat("route-to") {
+        rule.route_to = Some(interface(cursor.word("route-to interface")?)?);
+    }
+    if cursor.eat("inet") {
+        rule = rule.family(AddressFamily::Inet);
+    } else if cursor.eat("inet6") {
+        rule = rule.family(AddressFamily::Inet6);
+    }
+    if cursor.eat("proto") {
+        rule.protocols = cursor.list(|c| protocol(c.word("protocol")?))?;
+    }
+    if !cursor.eat("all") {
+        if cursor.eat("from") {
+            rule.from = endpoint(cursor)?;
+        }
+        if cursor.eat("to") {
+            rule.to = endpoint(cursor)?;
+        }
+    }
+    parse_rule_options(cursor, &mut rule)?;
+    Ok(rule)
+}
+
+/// ICMP types, TCP flags, state and labels after the addresses
+fn parse_rule_options(cursor: &mut Cursor, rule: &mut Rule) -> Result<(), String> {
+    while let Some(word) = cursor.next() {
+        match word {
+            "icmp-type" | "icmp6-type" => {
+                rule.icmp_types.extend(cursor.list(|c| {
+                    let name = c.word("ICMP type")?.to_string();
+                    let code = if c.eat("code") {
+                        let code = c.word("ICMP code")?;
+                        Some(
+                            code.parse()
+                                .map_err(|_| format!("invalid ICMP code `{code}`"))?,
+                        )
+                    } else {
+                        None
+                    };
+                    Ok(IcmpType { name, code })
+                })?);
+            }
+            // `pfctl -sr` prints `user = 501`
+            "user" | "group" => {
+                cursor.eat("=");
+                let ids = cursor.list(|c| {
+                    let id = c.word(word)?;
+                    id.parse::<u32>()
+                        .map_err(|_| format!("invalid {word} `{id}`, use a numeric id"))
+                })?;
+                if word == "user" {
+                    rule.users = ids;
+                } else {
+                    rule.groups = ids;
+                }
+            }
+            // pf's default for TCP is `flags S/SA`
+            "flags" => {
+                cursor.word("TCP flags")?;
+            }
+            "keep" | "modulate" | "synproxy" => {
+                cursor.expect("state")?;
+                rule.keep_state = true;
+            }
+            "no" => {
+                cursor.expect("state")?;
+                rule.keep_state = false;
+            }
+            "label" => {
+                cursor.word("label")?;
+            }
+            other => return Err(format!("unexpected `{other}`")),
+        }
+    }
+    Ok(())
+}
+
+fn interface(token: &str) -> Result<Interface, String> {
+    if let Some(name) = token.strip_prefix('$') {
+        return Ok(Interface::Macro(name.to_string()));
+    }
+    if token.is_empty()
+        || !token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+    {
+        return Err(format!("invalid interface `{token}`"));
+    }
+    Ok(Interface::Name(token.to_string()))
+}
+
+fn protocol(token: &str) -> Result<Protocol, String> {
+    match token {
+        "tcp" => Ok(Protocol::Tcp),
+        "udp" => Ok(Protocol::Udp),
+        "icmp" => Ok(Protocol::Icmp),
+        "icmp6" | "ipv6-icmp" => Ok(Protocol::Icmp6),
+        other => Err(format!("unsupported protocol `{other}`")),
+    }
+}
+
+fn endpoint(cursor: &mut Cursor) -> Result<Endpoint, String> {
+    let host = host(cursor)?;
+    let port = if cursor.eat("port") {
+        Some(port(cursor)?)
+    } else {
+        None
+    };
+    Ok(Endpoint { host, port })
+}
+
+fn host(cursor: &mut Cursor) -> Result<Host, String> {
+    if cursor.eat("!") {
+        return Ok(Host::Not(Box::new(host(cursor)?)));
+    }
+    if cursor.peek() == Some("{") {
+        return Ok(Host::List(cursor.list(host)?));
+    }
+    let token = cursor.word("address")?;
+    if token == "any" {
+        return Ok(Host::Any);
+    }
+    if token.starts_with('<') {
+        return Ok(Host::Table(table_name(token)?.to_string()));
+    }
+    if let Some(name) = token.strip_prefix('$') {
+        return Ok(match name.strip_suffix(":network") {
+            Some(iface) => Host::Network(iface.to_string()),
+            None => Host::Macro(name.to_string()),
+        });
+    }
+    address(token)?;
+    Ok(Host::Address(token.to_string()))
+}
+
+/// Accept addresses, networks, `self`, `iface:network` and hostnames
+fn address(token: &str) -> Result<(), String> {
+    let valid = token.parse::<Cidr>().is_ok()
+        || token == "self"
+        || token.split_once(':').is_some_and(|(iface, modifier)| {
+            ["network", "broadcast", "peer"].contains(&modifier) && interface(iface).is_ok()
+        })
+        || (token.contains(|c: char| c.is_ascii_alphabetic())
+            && token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".-".contains(c)));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid address `{token}`"))
+    }
+}
+
+fn port(cursor: &mut Cursor) -> Result<Port, String> {
+    cursor.eat("=");
+    if cursor.peek() == Some("{") {
+        return Ok(Port::List(cursor.list(|c| port_number(c.word("port")?))?));
+    }
+    let token = cursor.word("port")?;
+    if let Some((low, high)) = token.split_once(':') {
+        return Ok(Port::Range(port_number(low)?, port_number(high)?));
+    }
+    Ok(Port::Single(port_number(token)?))
+}
+
+fn port_number(token: &str) -> Result<u16, String> {
+    token
+        .parse()
+        .ok()
+        .or_else(|| {
+            SERVICES
+                .iter()
+                .find(|(name, _)| *name == token)
+                .map(|&(_, port)| port)
+        })
+        .ok_or_else(|| format!("invalid port `{token}`"))
+}
+
+/// Macros a rule refers to
+fn macro_refs(rule: &Rule) -> Vec<&str> {
+    fn host_refs<'a>(host: &'a Host, refs: &mut Vec<&'a str>) {
+        match host {
+            Host::Macro(name) | Host::Network(name) => refs.push(name),
+            Host::Not(host) => host_refs(host, refs),
+            Host::List(hosts) => hosts.iter().for_each(|h| host_refs(h, refs)),
+            Host::Any | Host::Address(_) | Host::Table(_) => {}
+        }
+    }
+    let mut refs = Vec::new();
+    for iface in [&rule.interface, &rule.route_to].into_iter().flatten() {
+        if let Interface::Macro(name) = iface {
+            refs.push(name.as_str());
+        }
+    }
+    host_refs(&rule.from.host, &mut refs);
+    host_refs(&rule.to.host, &mut refs);
+    refs
+}
+
+/// Whether an interface carries tunnel (or loopback) traffic: a tunnel
+/// interface name, directly or as the value of its macro (the `vpn_` prefix
+/// proves nothing)
+fn is_tunnel(iface: &Interface, macros: &HashMap<&str, &str>) -> bool {
+    let name = match iface {
+        Interface::Macro(name) => macros.get(name.as_str()).copied().unwrap_or_default(),
+        Interface::Name(name) => name,
+    };
+    TUNNEL_IFACES.iter().any(|prefix| {
+        name.strip_prefix(prefix)
+            .is_some_and(|unit| unit.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
+/// Why a pass rule defeats the kill switch: it lets traffic to any
+/// destination out of a physical interface (or every interface)
+fn defeats(rule: &Rule, macros: &HashMap<&str, &str>) -> Option<String> {
+    // Undefined macros are already an error
+    let undefined = |iface: &Interface| matches!(iface, Interface::Macro(name) if !macros.contains_key(name.as_str()));
+    let outside = |iface: &Option<Interface>| {
+        iface
+            .as_ref()
+            .is_none_or(|i| !is_tunnel(i, macros) && !undefined(i))
+    };
+    let icmp = !rule.protocols.is_empty()
+        && rule
+            .protocols
+            .iter()
+            .all(|p| matches!(p, Protocol::Icmp | Protocol::Icmp6));
+    if rule.action != Action::Pass
+        || rule.direction == Some(Direction::In)
+        || !outside(&rule.interface)
+        || !outside(&rule.route_to)
+        || icmp
+        // Scoped to the sockets of some accounts on purpose
+        || !rule.users.is_empty()
+        || !rule.groups.is_empty()
+        || rule.from.port.is_some()
+        || !(any_host(&rule.from.host) || local_source(&rule.from.host))
+        || !any_host(&rule.to.host)
+    {
+        return None;
+    }
+    Some(match &rule.to.port {
+        None => "passes all traffic outside the tunnel, defeating the kill switch".to_string(),
+        Some(port) => format!("passes port {port} to any destination outside the tunnel"),
+    })
+}
+
+/// Whether the pass rule at `index` only applies to the accounts not listed
+/// by an owner block that wins over it on the same interface, as generated
+/// for `--only-user`: a later block, or an earlier `quick` one
+fn narrowed_to_others(rules: &[(usize, &Rule)], index: usize) -> bool {
+    let Some((_, pass)) = rules.get(index) else {
+        return false;
+    };
+    rules.iter().enumerate().any(|(i, (_, block))| {
+        block.action == Action::Block
+            && (!block.users.is_empty() || !block.groups.is_empty())
+            && block.interface == pass.interface
+            && block.direction == pass.direction
+            && block.protocols == pass.protocols
+            && block.from == pass.from
+            && block.to == pass.to
+            && if pass.quick {
+                i < index && block.quick
+            } else {
+                i > index
+            }
+    })
+}
+
+/// Whether a host matches (nearly) every address: `any`, a default route,
+/// a negation (all but a host or a network), or a list with one of them
+fn any_host(host: &Host) -> bool {
+    match host {
+        Host::Any => true,
+        Host::Address(addr) => addr.parse::<Cidr>().is_ok_and(|net| net.prefix_len() == 0),
+        Host::Not(inner) => !any_host(inner),
+        Host::List(hosts) => hosts.iter().any(any_host),
+        _ => false,
+    }
+}
+
+/// Whether a source holds the address of the machine on a local network,
+/// so that it does not narrow what the rule lets out
+fn local_source(host: &Host) -> bool {
+    match host {
+        Host::Network(_) => true,
+        Host::Address(addr) => addr.parse::<Cidr>().is_ok_and(|net| {
+            let network = net.network().addr();
+            is_private_addr(&network) && !network.is_multicast()
+        }),
+        _ => false,
+    }
+}
+
+/// Whether every packet `rule` matches is matched by `quick` first
+fn covers(quick: &Rule, rule: &Rule) -> bool {
+    let side = |q: &Endpoint, r: &Endpoint| {
+        (q.host == Host::Any || q.host == r.host) && (q.port.is_none() || q.port == r.port)
+    };
+    (quick.direction.is_none() || quick.direction == rule.direction)
+        && (quick.interface.is_none() || quick.interface == rule.interface)
+        && (quick.family.is_none() || quick.family == rule.family)
+        && (quick.protocols.is_empty()
+            || (!rule.protocols.is_empty()
+                && rule.protocols.iter().all(|p| quick.protocols.contains(p))))
+        && side(&quick.from, &rule.from)
+        && side(&quick.to, &rule.to)
+        && (quick.icmp_types.is_empty() || quick.icmp_types == rule.icmp_types)
+        && (quick.users.is_empty() || quick.users == rule.users)
+        && (quick.groups.is_empty() || quick.groups == rule.groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_parse_rule() {
+        let parsed = parse(
+            "int_en0 = \"en0\"\n\
+             pass out quick on $int_en0 inet proto { tcp udp } from ! 10.0.0.1 to { <allow>, $int_en0:network } port = domain keep state # dns\n",
+        )
+        .unwrap();
+        let (line, rule) = parsed.rules().next().unwrap();
+        assert_eq!(line, 2);
+        assert_eq!(
+            rule.to_string(),
+            "pass out quick on $int_en0 inet proto {tcp, udp} from ! 10.0.0.1 to { <allow>, $int_en0:network } port 53 keep state"
+        );
+    }
+
+    #[test]
+    fn test_parse_pfctl_output() {
+        // As printed by `pfctl -sr`
+        let rules = "block drop all
+pass on en0 inet proto udp from any port 67:68 to any port 67:68 keep state
+pass on en0 proto udp from any to <vpn_peers> port = 51820 keep state
+pass on utun3 all flags S/SA keep state
+pass on en0 inet6 proto ipv6-icmp all icmp6-type neighbrsol keep state
+pass out on en0 proto tcp all user = 501 flags S/SA keep state
+";
+        assert_eq!(lint(rules), []);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let diagnostics = lint(
+            "pass on en0 proto gre all\nblock all\nnat on en0 from any to any -> 1.2.3.4\ntable <x> { 1.2.3.4\n",
+        );
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, [1, 3, 4]);
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+        assert_eq!(
+            diagnostics.first().map(ToString::to_string).as_deref(),
+            Some("line 1: error: unsupported protocol `gre`")
+        );
+    }
+
+    #[test]
+    fn test_undefined_macro() {
+        let diagnostics =
+            lint("pass on $vpn_utun3 all\nvpn_utun3 = \"utun3\"\npass on $vpn_utun3 all\n");
+        assert_eq!(
+            diagnostics,
+            [Diagnostic::error(1, "undefined macro $vpn_utun3")]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_after_quick() {
+        let diagnostics = lint(
+            "block quick proto udp to any port 53\n\
+             pass on en0 proto udp to 1.1.1.1 port 53\n\
+             pass on en0 proto tcp to 1.1.1.1 port 53\n",
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics.first().map(|d| d.line), Some(2));
+    }
+
+    #[test]
+    fn test_defeating_pass() {
+        let diagnostics = lint(
+            "block all\n\
+             pass out on en0 all\n\
+             pass out on en0 proto tcp to any port 443\n\
+             pass on utun3 all\n\
+             pass in on en0 proto udp to any port 41641\n\
+             pass on en0 inet proto icmp all icmp-type echoreq\n\
+             pass out on en0 to 0.0.0.0/0\n\
+             pass out on en0 inet6 to ::/0\n\
+             pass out on en0 from 10.0.0.0/8 to any\n\
+             pass out on en0 from $int_en0:network to any\n\
+             pass out on en0 to ! 10.9.9.9\n\
+             pass out on en0 from ! 10.9.9.9 to { 192.0.2.1, 0.0.0.0/0 }\n\
+             pass out on en0 to ! 10.0.0.0/8\n\
+             pass out on en0 from 224.0.0.0/4 to any\n\
+             pass out on en0 from 203.0.113.5 to any\n\
+             pass out on en0 to 10.0.0.0/8\n\
+             pass out on en0 from any port 123 to any\n",
+        );
+        let found: Vec<(usize, Severity)> = diagnostics
+            .iter()
+            .filter(|d| d.message != "undefined macro $int_en0")
+            .map(|d| (d.line, d.severity))
+            .collect();
+        assert_eq!(
+            found,
+            [2, 3, 7, 8, 9, 10, 11, 12, 13].map(|line| (line, Severity::Warning))
+        );
+        assert!(
+            diagnostics
+                .first()
+                .is_some_and(|d| d.message.contains("defeating the kill switch"))
+        );
+    }
+
+    #[test]
+    fn test_only_accounts_pass() {
+        // The other accounts pass on purpose, the listed ones are blocked
+        let only = "block all\n\
+             pass out on en0 proto {tcp, udp} all keep state\n\
+             block out on en0 proto {tcp, udp} all user { 501, 502 }\n";
+        assert_eq!(lint(only), []);
+        // First match (anchor): the quick blocks come first
+        let quick = "block out quick on en0 proto {tcp, udp} all group 20\n\
+             pass out quick on en0 proto {tcp, udp} all keep state\n\
+             block quick all\n";
+        assert_eq!(lint(quick), []);
+        // A block that loses to the pass does not narrow it
+        let before = "block out on en0 proto {tcp, udp} all user 501\n\
+             pass out on en0 proto {tcp, udp} all keep state\n";
+        let found: Vec<usize> = lint(before).iter().map(|d| d.line).collect();
+        assert_eq!(found, [2]);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_check_fragment() {
+        let macros = parse("int_en0 = \"en0\"\nvpn_utun3 = \"utun3\"\n")
+            .unwrap()
+            .ruleset;
+        let parsed = parse(
+            "set skip on en1\n\
+             pass on $vpn_utun3 proto udp to any port 27015\n\
+             pass out on $int_en0 proto udp to 192.168.1.50 port 27015\n\
+             pass quick proto tcp to any port 22\n\
+             block out quick on $int_en0 proto tcp to any port 25\n\
+             pass on $vpn_utun9 all\n\
+             vpn_x = \"en0\"\n\
+             pass out on $vpn_x all\n\
+             vpn_utun3 = \"en0\"\n\
+             pass out on $vpn_utun3 all\n\
+             vpn_wg = \"utun4\"\n\
+             pass out on $vpn_wg all\n",
+        )
+        .unwrap();
+        let found: Vec<String> = check_fragment(&parsed, &macros, false)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "line 1: error: `set` options are not allowed in a fragment",
+                "line 3: error: `pass` on the physical interface $int_en0",
+                "line 4: error: `pass` without `on` also matches the physical interfaces",
+                "line 6: error: undefined macro $vpn_utun9",
+                "line 6: error: `pass` on the physical interface $vpn_utun9",
+                // The `vpn_` prefix does not make a tunnel
+                "line 8: error: `pass` on the physical interface $vpn_x",
+                "line 9: error: redefines the generated macro $vpn_utun3",
+            ]
+        );
+        // Still an error with --unsafe-fragments
+        assert_eq!(check_fragment(&parsed, &macros, true).len(), 3);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_check_fragment_tables() {
+        let generated = parse(
+            "int_en0 = \"en0\"\n\
+             table <vpn_peers> persist { 203.0.113.1 }\n\
+             table <allow> persist { 192.0.2.10 }\n",
+        )
+        .unwrap()
+        .ruleset;
+        let parsed = parse(
+            "table <allow> persist { 0.0.0.0/0 }\n\
+             table <vpn_peers> persist { ::/0 }\n\
+             table <games> persist { 198.51.100.7 }\n\
+             pass out on $int_en0 to <allow>\n",
+        )
+        .unwrap();
+        let found: Vec<String> = check_fragment(&parsed, &generated, true)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        // Errors even with --unsafe-fragments, its own tables are fine
+        assert_eq!(
+            found,
+            [
+                "line 1: error: redefines the generated table <allow>",
+                "line 2: error: redefines the generated table <vpn_peers>",
+            ]
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_continued_lines() {
+        let parsed =
+            parse("table <allow> persist \\\n  { 10.0.0.0/8, !10.0.0.1 }\nblock all\n").unwrap();
+        assert_eq!(parsed.lines, [1, 3]);
+        assert_eq!(
+            parsed.ruleset.table("allow").map(|t| t.addresses.clone()),
+            Some(vec!["10.0.0.0/8".to_string(), "!10.0.0.1".to_string()])
+        );
+    }
+}
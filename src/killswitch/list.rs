@@ -0,0 +1,55 @@
+//! Plain-text list files (peers, allowlists, ...).
+//!
+//! One entry per line (or several separated by whitespace or commas);
+//! blank lines and `#` comments are ignored.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Read the entries of a list file.
+///
+/// # Errors
+/// Returns an error if the file cannot be read.
+pub fn read(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read list file {}", path.display()))?;
+    Ok(parse(&content))
+}
+
+/// Parse the entries of a list file.
+#[must_use]
+pub fn parse(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+        .filter(|entry| !entry.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        let content =
+            "# VPN pool\n203.0.113.1\n\n198.51.100.7 # backup\n2001:db8::1, 2001:db8::2\n";
+        assert_eq!(
+            parse(content),
+            vec!["203.0.113.1", "198.51.100.7", "2001:db8::1", "2001:db8::2"]
+        );
+    }
+
+    #[test]
+    fn test_parse_list_empty() {
+        assert!(parse("# nothing here\n\n").is_empty());
+    }
+
+    #[test]
+    fn test_read_missing_file() {
+        assert!(read(Path::new("/nonexistent/killswitch.list")).is_err());
+    }
+}
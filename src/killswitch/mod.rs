@@ -1,11 +1,34 @@
+mod cidr;
+pub mod diff;
+mod fragments;
+pub mod lint;
+pub mod list;
 mod network;
+mod options;
 mod pf;
+pub mod pflog;
 mod rules;
+pub mod ruleset;
+pub mod snapshot;
+mod state;
+
+pub use self::options::{BridgePolicy, Deny, Inbound, Options, PeerProto, Preset, Scope, Update};
 
 use crate::cli::verbosity::Verbosity;
+use crate::killswitch::snapshot::NetworkSnapshot;
+use crate::killswitch::state::State;
 use anyhow::{Context, Result, bail};
+use nix::unistd::{Group, User};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+/// Bounds of the delay between two refreshes of the bypassed domains
+const MIN_REFRESH: Duration = Duration::from_secs(30);
+const MAX_REFRESH: Duration = Duration::from_secs(3600);
 
-/// Check if an IP address is in a private/reserved range (RFC 1918, loopback, link-local)
+/// Check if an IP address is in a private/reserved range (RFC 1918, CGNAT,
+/// loopback, link-local)
 #[must_use]
 pub fn is_private_ip(ip: &std::net::Ipv4Addr) -> bool {
     let o = ip.octets();
@@ -15,12 +38,48 @@ pub fn is_private_ip(ip: &std::net::Ipv4Addr) -> bool {
         || (o[0] == 172 && (16..=31).contains(&o[1]))
         // 192.168.0.0/16
         || (o[0] == 192 && o[1] == 168)
+        // 100.64.0.0/10 (CGNAT, also used by Tailscale)
+        || (o[0] == 100 && (64..=127).contains(&o[1]))
         // 127.0.0.0/8 (loopback)
         || o[0] == 127
         // 169.254.0.0/16 (link-local)
         || (o[0] == 169 && o[1] == 254)
 }
 
+/// Check if an IPv6 address is private/reserved (loopback, unspecified, ULA,
+/// link-local, multicast, IPv4-mapped private addresses)
+///
+/// NAT64 addresses (`64:ff9b::/96`) are checked against the embedded IPv4
+/// address, so a public IPv4 peer reached through NAT64 is not private.
+#[must_use]
+pub fn is_private_ipv6(ip: &std::net::Ipv6Addr) -> bool {
+    let s = ip.segments();
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_private_ip(&v4);
+    }
+    if s[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let o = ip.octets();
+        return is_private_ip(&std::net::Ipv4Addr::new(o[12], o[13], o[14], o[15]));
+    }
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // fc00::/7 (unique local)
+        || (s[0] & 0xfe00) == 0xfc00
+        // fe80::/10 (link-local)
+        || (s[0] & 0xffc0) == 0xfe80
+        // ff00::/8 (multicast)
+        || (s[0] & 0xff00) == 0xff00
+}
+
+/// Check if an address of either family is private/reserved
+#[must_use]
+pub fn is_private_addr(ip: &std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V4(v4) => is_private_ip(v4),
+        std::net::IpAddr::V6(v6) => is_private_ipv6(v6),
+    }
+}
+
 fn check_root() -> Result<()> {
     let euid = unsafe { libc::geteuid() };
     if euid != 0 {
@@ -29,32 +88,319 @@ fn check_root() -> Result<()> {
     Ok(())
 }
 
-fn validate_ipv4(ip: &str) -> Result<()> {
-    use std::net::IpAddr;
-    let addr: IpAddr = ip.parse().context("Invalid IP address")?;
-    let IpAddr::V4(v4) = addr else {
-        bail!("IPv6 addresses are not supported: {ip}");
-    };
-    if is_private_ip(&v4) {
+fn validate_peer(ip: &str) -> Result<IpAddr> {
+    let addr: IpAddr = ip
+        .parse()
+        .with_context(|| format!("Invalid IP address: {ip}"))?;
+    if is_private_addr(&addr) {
         bail!("{ip} is a private/reserved IP address. VPN peer must be a public IP");
     }
-    Ok(())
+    Ok(addr)
+}
+
+/// Expand a peer given as an address or a hostname.
+///
+/// Hostnames resolve to every public A and AAAA record, so providers rotating
+/// the addresses behind a name keep working.
+fn expand_peer(peer: &str, verbose: Verbosity) -> Result<Vec<IpAddr>> {
+    expand_peer_with(
+        peer,
+        |host| network::resolve_hostname_all(host, verbose),
+        verbose,
+    )
+}
+
+/// [`expand_peer`] with the hostname resolver given
+fn expand_peer_with(
+    peer: &str,
+    resolve: impl Fn(&str) -> Result<Vec<IpAddr>>,
+    verbose: Verbosity,
+) -> Result<Vec<IpAddr>> {
+    if peer.parse::<IpAddr>().is_ok() {
+        return Ok(vec![validate_peer(peer)?]);
+    }
+
+    let mut addrs = Vec::new();
+    for addr in resolve(peer)? {
+        if is_private_addr(&addr) {
+            if verbose.is_debug() {
+                eprintln!("  Skipping private address {addr} of {peer}");
+            }
+        } else {
+            addrs.push(addr);
+        }
+    }
+    if addrs.is_empty() {
+        bail!("{peer} has no public IP address. VPN peer must be a public IP");
+    }
+    if verbose.is_verbose() {
+        let list: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+        eprintln!("  Resolved {peer}: {}", list.join(", "));
+    }
+    Ok(addrs)
+}
+
+/// Expand an allowlist entry: a CIDR network, an address or a hostname
+/// (resolved to all of its addresses)
+fn expand_destination(entry: &str, verbose: Verbosity) -> Result<Vec<String>> {
+    expand_destination_with(entry, |host| network::resolve_hostname_all(host, verbose))
+}
+
+/// [`expand_destination`] with the hostname resolver given
+fn expand_destination_with(
+    entry: &str,
+    resolve: impl Fn(&str) -> Result<Vec<IpAddr>>,
+) -> Result<Vec<String>> {
+    if entry.contains('/') || entry.parse::<IpAddr>().is_ok() {
+        return Ok(vec![entry.parse::<cidr::Cidr>()?.to_string()]);
+    }
+    let addrs = resolve(entry)?;
+    Ok(addrs.iter().map(ToString::to_string).collect())
+}
+
+/// Resolve the destinations allowed outside the tunnel from user input and
+/// the allowlist file
+fn resolve_allowlist(options: &Options, verbose: Verbosity) -> Result<Vec<String>> {
+    let mut entries = options.allow.clone();
+    if let Some(path) = &options.allow_file {
+        entries.extend(list::read(path)?);
+    }
+
+    let mut allow: Vec<String> = Vec::new();
+    for entry in &entries {
+        for addr in expand_destination(entry, verbose)? {
+            if !allow.contains(&addr) {
+                allow.push(addr);
+            }
+        }
+    }
+    Ok(allow)
+}
+
+/// Collect the denied ports and networks from user input and the deny list
+/// file
+fn resolve_deny(options: &Options) -> Result<Vec<Deny>> {
+    let mut deny = options.deny.clone();
+    if let Some(path) = &options.deny_file {
+        for entry in list::read(path)? {
+            let entry = entry
+                .parse()
+                .with_context(|| format!("Invalid deny list {}", path.display()))?;
+            if !deny.contains(&entry) {
+                deny.push(entry);
+            }
+        }
+    }
+    Ok(deny)
+}
+
+/// Resolve the bypassed domains from user input and the bypass list file
+///
+/// Returns their addresses and the shortest TTL of the records, if any.
+fn resolve_bypass(options: &Options, verbose: Verbosity) -> Result<(Vec<String>, Option<u32>)> {
+    resolve_bypass_with(
+        options,
+        |domain| network::resolve_hostname_ttl(domain, verbose),
+        verbose,
+    )
+}
+
+/// [`resolve_bypass`] with the resolver (addresses and TTL) given
+fn resolve_bypass_with(
+    options: &Options,
+    resolve: impl Fn(&str) -> Result<(Vec<IpAddr>, u32)>,
+    verbose: Verbosity,
+) -> Result<(Vec<String>, Option<u32>)> {
+    let mut domains = options.bypass.clone();
+    if let Some(path) = &options.bypass_file {
+        domains.extend(list::read(path)?);
+    }
+
+    let mut bypass: Vec<String> = Vec::new();
+    let mut ttl: Option<u32> = None;
+    for domain in &domains {
+        let (addrs, domain_ttl) = resolve(domain)
+            .with_context(|| format!("Could not resolve bypassed domain {domain}"))?;
+        if verbose.is_verbose() {
+            let list: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+            eprintln!("  Bypassing {domain}: {}", list.join(", "));
+        }
+        for addr in addrs.iter().map(ToString::to_string) {
+            if !bypass.contains(&addr) {
+                bypass.push(addr);
+            }
+        }
+        ttl = Some(ttl.map_or(domain_ttl, |t| t.min(domain_ttl)));
+    }
+    Ok((bypass, ttl))
+}
+
+/// Delay until the next refresh: the shortest TTL within sane bounds
+fn refresh_interval(ttl: Option<u32>) -> Duration {
+    let ttl = ttl.unwrap_or(network::DEFAULT_TTL);
+    Duration::from_secs(u64::from(ttl)).clamp(MIN_REFRESH, MAX_REFRESH)
+}
+
+/// Resolve every address the rules refer to
+fn resolve_targets(
+    options: &Options,
+    snapshot: &NetworkSnapshot,
+    verbose: Verbosity,
+) -> Result<rules::Targets> {
+    Ok(rules::Targets {
+        peers: resolve_peers(options, snapshot, verbose)?,
+        allow: resolve_allowlist(options, verbose)?,
+        bypass: resolve_bypass(options, verbose)?.0,
+        tailscale: resolve_tailscale(options, verbose)?,
+        fragments: load_fragments(options)?,
+        exempt: resolve_owners(&options.exempt_users, &options.exempt_groups)?,
+        only: resolve_owners(&options.only_users, &options.only_groups)?,
+        deny: resolve_deny(options)?,
+    })
+}
+
+/// Resolve account and group names (or numeric ids) to UIDs and GIDs
+fn resolve_owners(users: &[String], groups: &[String]) -> Result<rules::Owners> {
+    Ok(rules::Owners {
+        users: users
+            .iter()
+            .map(|user| resolve_uid(user))
+            .collect::<Result<_>>()?,
+        groups: groups
+            .iter()
+            .map(|group| resolve_gid(group))
+            .collect::<Result<_>>()?,
+    })
+}
+
+/// The UID of a local account, given by name or number
+fn resolve_uid(user: &str) -> Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    // getpwnam_r with an owned buffer, the lookups may run concurrently
+    match User::from_name(user).with_context(|| format!("Failed to look up user {user}"))? {
+        Some(entry) => Ok(entry.uid.as_raw()),
+        None => bail!("Unknown user: {user}"),
+    }
+}
+
+/// The GID of a local group, given by name or number
+fn resolve_gid(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    match Group::from_name(group).with_context(|| format!("Failed to look up group {group}"))? {
+        Some(entry) => Ok(entry.gid.as_raw()),
+        None => bail!("Unknown group: {group}"),
+    }
 }
 
-/// Resolve the VPN peer IP from user input or auto-detection
-fn resolve_vpn_ip(ipv4: Option<&str>, verbose: Verbosity) -> Result<String> {
-    if let Some(ip) = ipv4 {
-        validate_ipv4(ip)?;
+/// Read the user fragments, if a fragment directory is set
+fn load_fragments(options: &Options) -> Result<Vec<fragments::Fragment>> {
+    match &options.fragments {
+        Some(dir) => fragments::load(dir),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Resolve the Tailscale control plane and DERP relays for the tailscale
+/// preset (empty without it)
+///
+/// The relays come from the running `tailscaled`; without it only the
+/// control plane is allowed until the next refresh.
+fn resolve_tailscale(options: &Options, verbose: Verbosity) -> Result<Vec<String>> {
+    if options.preset != Some(Preset::Tailscale) {
+        return Ok(Vec::new());
+    }
+    let mut candidates: Vec<String> = Vec::new();
+    for host in network::TAILSCALE_CONTROL {
+        candidates.extend(expand_destination(host, verbose)?);
+    }
+    match network::get_tailscale_derp() {
+        Ok(relays) => candidates.extend(relays),
+        Err(e) => {
+            if verbose.is_verbose() {
+                eprintln!("  Tailscale DERP relays unavailable: {e}");
+            }
+        }
+    }
+
+    let mut addrs: Vec<String> = Vec::new();
+    for addr in candidates {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    Ok(addrs)
+}
+
+/// Resolve the VPN peers (IPv4 or IPv6) from user input, the peer list file
+/// or the peers detected in the snapshot
+fn resolve_peers(
+    options: &Options,
+    snapshot: &NetworkSnapshot,
+    verbose: Verbosity,
+) -> Result<Vec<IpAddr>> {
+    let mut given = options.peers.clone();
+    if let Some(path) = &options.peer_file {
+        given.extend(list::read(path)?);
+    }
+
+    let candidates = if options.has_peers() {
         if verbose.is_debug() {
-            eprintln!("  Using provided VPN gateway: {ip}");
+            eprintln!("  Using provided VPN peers: {}", given.join(", "));
         }
-        Ok(ip.to_string())
+        given
     } else {
-        if verbose.is_verbose() {
-            eprintln!("  Auto-detecting VPN gateway address...");
+        snapshot.peers.clone()
+    };
+
+    let mut peers: Vec<IpAddr> = Vec::new();
+    for candidate in &candidates {
+        for addr in expand_peer(candidate, verbose)? {
+            if !peers.contains(&addr) {
+                peers.push(addr);
+            }
         }
-        network::detect_vpn_gateway(verbose)
     }
+
+    if peers.is_empty() {
+        bail!("Could not detect VPN peer IP. Please specify it manually with --peer");
+    }
+    Ok(peers)
+}
+
+/// Capture the interfaces, and the routes and resolvers when the options
+/// need them, without peer detection
+fn capture_local(options: &Options) -> Result<NetworkSnapshot> {
+    let dns = if options.dns_lock {
+        network::get_dns_resolvers()?
+    } else {
+        Vec::new()
+    };
+    let routes = if options.exclude_gateway {
+        network::get_routes()?
+    } else {
+        Vec::new()
+    };
+    Ok(NetworkSnapshot {
+        interfaces: network::get_interfaces()?,
+        routes,
+        dns,
+        ..NetworkSnapshot::default()
+    })
+}
+
+/// Capture the network state, skipping peer detection when peers are given
+fn capture_snapshot(options: &Options, verbose: Verbosity) -> Result<NetworkSnapshot> {
+    if options.has_peers() {
+        return capture_local(options);
+    }
+    if verbose.is_verbose() {
+        eprintln!("  Auto-detecting VPN gateway address...");
+    }
+    NetworkSnapshot::capture(verbose)
 }
 
 /// Enable the VPN kill switch
@@ -64,23 +410,43 @@ fn resolve_vpn_ip(ipv4: Option<&str>, verbose: Verbosity) -> Result<String> {
 /// - Not running with root privileges
 /// - VPN gateway address cannot be detected (when not provided)
 /// - Firewall rules cannot be generated or applied
-pub fn enable(leak: bool, local: bool, ipv4: Option<&str>, verbose: Verbosity) -> Result<()> {
+pub fn enable(options: &Options, verbose: Verbosity) -> Result<()> {
     check_root()?;
+    pf::expire_portal(verbose)?;
 
-    let vpn_ip = resolve_vpn_ip(ipv4, verbose)?;
+    let snapshot = capture_snapshot(options, verbose)?;
+    let targets = resolve_targets(options, &snapshot, verbose)?;
 
     if verbose.is_debug() {
-        eprintln!("  VPN gateway: {vpn_ip}");
+        for peer in &targets.peers {
+            eprintln!("  VPN gateway: {peer}");
+        }
         eprintln!("  Generating firewall rules...");
     }
 
-    let rules_content = rules::generate(&vpn_ip, leak, local, verbose)?;
+    let ruleset = rules::generate(&snapshot, &targets, options, verbose)?;
+    let rules_content = ruleset.to_string();
 
     if verbose.is_debug() {
         eprintln!("  Applying rules to pf...");
     }
 
-    pf::apply_rules(&rules_content, verbose)?;
+    pf::apply_rules(
+        &rules_content,
+        options.anchor,
+        &rules::stale_states(&ruleset),
+        verbose,
+    )?;
+
+    // Keep the detected tunnel so the rules can be generated again offline
+    let mut saved = options.clone();
+    saved.peer_port = options.peer_port.or(snapshot.peer_port);
+    saved.peer_proto = options.peer_proto.or(snapshot.peer_proto);
+    State {
+        options: saved,
+        peers: targets.peers.iter().map(ToString::to_string).collect(),
+    }
+    .save()?;
 
     Ok(())
 }
@@ -94,33 +460,384 @@ pub fn enable(leak: bool, local: bool, ipv4: Option<&str>, verbose: Verbosity) -
 pub fn disable(verbose: Verbosity) -> Result<()> {
     check_root()?;
     pf::disable(verbose)?;
+    State::remove()?;
+    Ok(())
+}
+
+/// Result of a [`refresh`]
+#[derive(Debug)]
+pub struct Refreshed {
+    pub peers: Vec<IpAddr>,
+    /// Addresses of the bypassed domains
+    pub bypass: Vec<String>,
+    /// When to refresh again, based on the DNS TTL of the bypassed domains
+    pub next: Duration,
+}
+
+/// Re-resolve the VPN peers, allowed destinations and bypassed domains of
+/// the active kill switch and replace the content of their tables, without
+/// reloading the ruleset
+///
+/// Meant to be run periodically (cron, launchd or `refresh --watch`) when
+/// peers or bypassed domains are hostnames whose addresses rotate.
+///
+/// # Errors
+/// Returns an error if:
+/// - Not running with root privileges
+/// - The kill switch is not enabled
+/// - The peers cannot be resolved or the table cannot be updated
+pub fn refresh(verbose: Verbosity) -> Result<Refreshed> {
+    check_root()?;
+    pf::expire_portal(verbose)?;
+
+    let mut state = State::load()?;
+    let snapshot = capture_snapshot(&state.options, verbose)?;
+    let peers = resolve_peers(&state.options, &snapshot, verbose)?;
+
+    state.peers = peers.iter().map(ToString::to_string).collect();
+    pf::replace_table(
+        rules::PEERS_TABLE,
+        &state.peers,
+        state.options.anchor,
+        verbose,
+    )?;
+    // Replaced even when empty, stale destinations must not stay allowed
+    let allow = resolve_allowlist(&state.options, verbose)?;
+    pf::replace_table(rules::ALLOW_TABLE, &allow, state.options.anchor, verbose)?;
+    let (bypass, ttl) = resolve_bypass(&state.options, verbose)?;
+    pf::replace_table(rules::BYPASS_TABLE, &bypass, state.options.anchor, verbose)?;
+    // Tailscale may move its relays, the control plane its addresses
+    let tailscale = resolve_tailscale(&state.options, verbose)?;
+    pf::replace_table(
+        rules::TAILSCALE_TABLE,
+        &tailscale,
+        state.options.anchor,
+        verbose,
+    )?;
+    // The VPN may push other resolvers after reconnecting
+    let locked = rules::locked_resolvers(&snapshot);
+    if state.options.dns_lock && !locked.is_empty() {
+        pf::replace_table(
+            rules::DNS_LOCK_TABLE,
+            &locked,
+            state.options.anchor,
+            verbose,
+        )?;
+    }
+    state.save()?;
+
+    Ok(Refreshed {
+        peers,
+        bypass,
+        next: refresh_interval(ttl),
+    })
+}
+
+/// Add then remove entries of a list, keeping it free of duplicates
+fn edit_list(list: &mut Vec<String>, add: &[String], remove: &[String]) {
+    for entry in add {
+        if !list.contains(entry) {
+            list.push(entry.clone());
+        }
+    }
+    list.retain(|entry| !remove.contains(entry));
+}
+
+/// Apply the peer changes to the state (settings and resolved peers)
+fn update_peers(state: &mut State, changes: &Update, verbose: Verbosity) -> Result<()> {
+    // Keep the auto-detected peers once peers are given explicitly
+    if !state.options.has_peers() {
+        state.options.peers.clone_from(&state.peers);
+    }
+    let mut added = Vec::new();
+    for peer in &changes.add_peers {
+        added.extend(expand_peer(peer, verbose)?.iter().map(ToString::to_string));
+    }
+    let mut removed = Vec::new();
+    for peer in &changes.remove_peers {
+        // A hostname that no longer resolves can still be removed
+        let addrs = expand_peer(peer, verbose).unwrap_or_default();
+        removed.extend(addrs.iter().map(ToString::to_string));
+        removed.push(peer.clone());
+    }
+
+    edit_list(&mut state.options.peers, &changes.add_peers, &removed);
+    edit_list(&mut state.peers, &added, &removed);
+    if state.peers.is_empty() {
+        bail!("Cannot remove every VPN peer, disable the kill switch instead");
+    }
     Ok(())
 }
 
+/// Ensure a table to update is part of the active ruleset
+fn require_table(table: &str, anchor: bool, flag: &str) -> Result<()> {
+    if !pf::tables(anchor)?.iter().any(|t| t == table) {
+        bail!("No <{table}> table in the active ruleset, enable the kill switch with {flag}");
+    }
+    Ok(())
+}
+
+/// Add or remove VPN peers, allowed destinations and local subnets of the
+/// active kill switch with `pfctl -t <table> -T`, without reloading the
+/// ruleset (the states of open connections are kept)
+///
+/// The changes are saved for `refresh`; entries of `--peer-file` or
+/// `--allow-file` come back on the next refresh unless removed from the file.
+///
+/// # Errors
+/// Returns an error if:
+/// - Not running with root privileges
+/// - The kill switch is not enabled, or without the table to update
+/// - An entry cannot be resolved, or every peer would be removed
+pub fn update(changes: &Update, verbose: Verbosity) -> Result<()> {
+    check_root()?;
+
+    let mut state = State::load()?;
+    let anchor = state.options.anchor;
+
+    if changes.peers() {
+        update_peers(&mut state, changes, verbose)?;
+        pf::replace_table(rules::PEERS_TABLE, &state.peers, anchor, verbose)?;
+    }
+    if changes.allow() {
+        require_table(rules::ALLOW_TABLE, anchor, "--allow")?;
+        edit_list(
+            &mut state.options.allow,
+            &changes.add_allow,
+            &changes.remove_allow,
+        );
+        let allow = resolve_allowlist(&state.options, verbose)?;
+        pf::replace_table(rules::ALLOW_TABLE, &allow, anchor, verbose)?;
+    }
+    if changes.local() {
+        require_table(rules::LOCAL_TABLE, anchor, "--local <SUBNET>")?;
+        let subnets = state.options.local.get_or_insert_with(Vec::new);
+        edit_list(subnets, &changes.add_local, &changes.remove_local);
+        let snapshot = NetworkSnapshot {
+            interfaces: network::get_interfaces()?,
+            routes: network::get_routes()?,
+            ..NetworkSnapshot::default()
+        };
+        let nets = rules::local_table(&snapshot, &state.options)?;
+        pf::replace_table(rules::LOCAL_TABLE, &nets, anchor, verbose)?;
+    }
+    state.save()?;
+
+    Ok(())
+}
+
+/// Open a captive portal window: DNS and web are allowed on the physical
+/// interfaces to their network for `window`, then the strict rules come back
+///
+/// Works offline: peers are taken from the saved state and the allowed
+/// destinations from the live tables, whose content is left untouched.
+/// The strict rules are restored by the first `killswitch` run after the
+/// window, at the latest by a launchd job, see [`expire_portal`].
+///
+/// # Errors
+/// Returns an error if:
+/// - Not running with root privileges
+/// - The kill switch is not enabled
+/// - The rules cannot be generated or applied
+pub fn portal(window: Duration, verbose: Verbosity) -> Result<()> {
+    check_root()?;
+
+    let state = State::load()?;
+    let anchor = state.options.anchor;
+    let snapshot = capture_local(&state.options)?;
+    let targets = rules::Targets {
+        peers: state
+            .peers
+            .iter()
+            .map(|peer| validate_peer(peer))
+            .collect::<Result<_>>()?,
+        allow: pf::table_addresses(rules::ALLOW_TABLE, anchor).unwrap_or_default(),
+        bypass: pf::table_addresses(rules::BYPASS_TABLE, anchor).unwrap_or_default(),
+        tailscale: pf::table_addresses(rules::TAILSCALE_TABLE, anchor).unwrap_or_default(),
+        fragments: load_fragments(&state.options)?,
+        exempt: resolve_owners(&state.options.exempt_users, &state.options.exempt_groups)?,
+        only: resolve_owners(&state.options.only_users, &state.options.only_groups)?,
+        deny: resolve_deny(&state.options)?,
+    };
+
+    let generate = |portal: bool| -> Result<String> {
+        let options = Options {
+            portal,
+            ..state.options.clone()
+        };
+        let mut rules = rules::generate(&snapshot, &targets, &options, verbose)?;
+        // Reloading a table definition would reset its live content
+        rules
+            .statements_mut()
+            .retain(|s| !matches!(s, ruleset::Statement::Table(_)));
+        Ok(rules.to_string())
+    };
+    let strict = generate(false)?;
+    let open = generate(true)?;
+
+    pf::apply_portal(&open, &strict, window, anchor, verbose)
+}
+
+/// Restore the strict rules once a captive portal window has expired.
+/// Returns whether they were restored.
+///
+/// # Errors
+/// Returns an error if the strict rules cannot be loaded
+pub fn expire_portal(verbose: Verbosity) -> Result<bool> {
+    pf::expire_portal(verbose)
+}
+
+/// Summarize the packets dropped by the kill switch, read from a saved
+/// pflog capture or captured live on `pflog0` for `duration`
+///
+/// Only rules generated with `--log` send packets to `pflog0`.
+///
+/// # Errors
+/// Returns an error if:
+/// - Not running with root privileges (live capture)
+/// - The capture cannot be read or is not a pflog capture
+pub fn blocked(file: Option<&Path>, duration: Duration) -> Result<Vec<pflog::Flow>> {
+    let data = if let Some(path) = file {
+        std::fs::read(path).with_context(|| format!("Failed to read capture {}", path.display()))?
+    } else {
+        check_root()?;
+        pflog::capture(duration)?
+    };
+    Ok(pflog::summarize(&pflog::parse(&data)?))
+}
+
+/// Lint a pf ruleset file offline: syntax, undefined macros, unreachable
+/// rules and pass rules that defeat the kill switch
+///
+/// # Errors
+/// Returns an error if the file cannot be read
+pub fn lint(file: &Path) -> Result<Vec<lint::Diagnostic>> {
+    let text = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read ruleset {}", file.display()))?;
+    Ok(lint::lint(&text))
+}
+
 /// Get the current status of the VPN kill switch
 ///
 /// # Errors
 /// Returns an error if the firewall status cannot be queried
 #[must_use = "status returns the current state which should be displayed or checked"]
 pub fn status() -> Result<String> {
-    pf::status()
+    use std::fmt::Write;
+
+    pf::expire_portal(Verbosity::Normal)?;
+    let anchor = pf::anchor_loaded();
+    let Some(rules) = pf::active_rules(anchor)? else {
+        return Ok("VPN kill switch: DISABLED".to_string());
+    };
+
+    let mut out = String::from("VPN kill switch: ENABLED");
+    if anchor {
+        let _ = write!(out, " (anchor {})", pf::ANCHOR);
+    }
+    out.push('\n');
+    let sections = [
+        ("Allowed outside the VPN", rules::ALLOW_TABLE),
+        ("DNS locked to", rules::DNS_LOCK_TABLE),
+        ("Denied on every interface", rules::DENY_TABLE),
+    ];
+    for (title, table) in sections {
+        let addrs = pf::table_addresses(table, anchor).unwrap_or_default();
+        if !addrs.is_empty() {
+            let _ = writeln!(out, "\n{title}:");
+            for addr in &addrs {
+                let _ = writeln!(out, "  {addr}");
+            }
+        }
+    }
+    let ipv6 = pf::ipv6_passes(&rules, |table| {
+        pf::table_addresses(table, anchor).unwrap_or_default()
+    });
+    if ipv6.is_empty() {
+        let _ = writeln!(
+            out,
+            "\nIPv6: fully blocked (neighbor discovery and DHCPv6 on-link only)"
+        );
+    } else {
+        let _ = writeln!(out, "\nIPv6 passed by:");
+        for rule in &ipv6 {
+            let _ = writeln!(out, "  {rule}");
+        }
+    }
+    let inbound = pf::inbound_ports(&rules);
+    if inbound.is_empty() {
+        let _ = writeln!(out, "\nInbound connections: all blocked");
+    } else {
+        let _ = writeln!(out, "\nInbound ports open:");
+        for port in &inbound {
+            let _ = writeln!(out, "  {port}");
+        }
+    }
+    let _ = write!(out, "\n{rules}");
+    Ok(out)
+}
+
+/// Compare the loaded rules with the rules `enable` would generate now from
+/// the saved options and the current network
+///
+/// # Errors
+/// Returns an error if:
+/// - Not running with root privileges
+/// - The kill switch is not enabled
+/// - The rules cannot be generated or the loaded ones cannot be parsed
+pub fn diff(verbose: Verbosity) -> Result<Vec<diff::Change>> {
+    check_root()?;
+
+    let state = State::load()?;
+    let anchor = state.options.anchor;
+    let Some(loaded) = pf::active_rules(anchor)? else {
+        bail!("The kill switch is not enabled");
+    };
+    let snapshot = capture_snapshot(&state.options, verbose)?;
+    let targets = resolve_targets(&state.options, &snapshot, verbose)?;
+    let fresh = rules::generate(&snapshot, &targets, &state.options, verbose)?;
+
+    diff::compare(
+        &loaded,
+        |table| pf::table_addresses(table, anchor).unwrap_or_default(),
+        &fresh,
+        &snapshot,
+    )
 }
 
 /// Generate firewall rules without applying them
 ///
+/// When `snapshot` is given, rules are generated from it instead of the
+/// live network state, and its detected peers are used when no peer is given.
+///
 /// # Errors
 /// Returns an error if:
 /// - VPN gateway address cannot be detected (when not provided)
 /// - Rules cannot be generated
 pub fn generate_rules(
-    leak: bool,
-    local: bool,
-    ipv4: Option<&str>,
+    options: &Options,
+    snapshot: Option<&NetworkSnapshot>,
     verbose: Verbosity,
 ) -> Result<String> {
-    let vpn_ip = resolve_vpn_ip(ipv4, verbose)?;
+    let captured;
+    let snapshot = if let Some(snapshot) = snapshot {
+        snapshot
+    } else {
+        captured = capture_snapshot(options, verbose)?;
+        &captured
+    };
 
-    rules::generate(&vpn_ip, leak, local, verbose)
+    let targets = resolve_targets(options, snapshot, verbose)?;
+
+    Ok(rules::generate(snapshot, &targets, options, verbose)?.to_string())
+}
+
+/// Capture the current network state (interfaces, peers, routes and DNS)
+///
+/// # Errors
+/// Returns an error if interface detection fails
+pub fn snapshot(verbose: Verbosity) -> Result<NetworkSnapshot> {
+    NetworkSnapshot::capture(verbose)
 }
 
 /// Show active network interfaces, VPN peer IP, and usage hints.
@@ -157,10 +874,15 @@ pub fn show_interfaces(verbose: Verbosity) -> Result<String> {
         let _ = writeln!(out, "\nPublic IP address: \x1b[0;31m{public_ip}\x1b[0m");
     }
 
-    // Try to detect VPN peer IP
+    // Try to detect VPN peer IPs
     match network::detect_vpn_gateway(verbose) {
-        Ok(peer) => {
-            let _ = writeln!(out, "PEER IP address:   \x1b[0;33m{peer}\x1b[0m");
+        Ok(peers) => {
+            let peers = peers
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let _ = writeln!(out, "PEER IP address:   \x1b[0;33m{peers}\x1b[0m");
         }
         Err(_) if !has_vpn => {
             let _ = writeln!(out, "\nNo VPN interface found, verify VPN is connected");
@@ -173,3 +895,290 @@ pub fn show_interfaces(verbose: Verbosity) -> Result<String> {
 
     Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_peer_ipv6() {
+        assert!(validate_peer("2001:db8::1").is_ok());
+        assert!(validate_peer("2606:4700::1111").is_ok());
+        assert!(validate_peer("::1").is_err());
+        assert!(validate_peer("fd00::1").is_err());
+        assert!(validate_peer("fe80::1").is_err());
+        assert!(validate_peer("ff02::1").is_err());
+    }
+
+    #[test]
+    fn test_validate_peer_nat64() {
+        // 64:ff9b::/96 embeds the IPv4 address in the last 32 bits
+        assert!(validate_peer("64:ff9b::cb00:7101").is_ok()); // 203.0.113.1
+        assert!(validate_peer("64:ff9b::a08:1").is_err()); // 10.8.0.1
+        assert!(validate_peer("::ffff:192.168.1.1").is_err());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_resolve_peers_dedup_and_order() {
+        let options = Options {
+            peers: vec![
+                "203.0.113.1".to_string(),
+                "2001:db8::1".to_string(),
+                "203.0.113.1".to_string(),
+            ],
+            ..Options::default()
+        };
+        let peers =
+            resolve_peers(&options, &NetworkSnapshot::default(), Verbosity::Normal).unwrap();
+        assert_eq!(
+            peers,
+            vec![
+                "203.0.113.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_resolve_peers_from_snapshot() {
+        let snapshot = NetworkSnapshot {
+            peers: vec!["203.0.113.1".to_string(), "198.51.100.7".to_string()],
+            ..NetworkSnapshot::default()
+        };
+        let peers = resolve_peers(&Options::default(), &snapshot, Verbosity::Normal).unwrap();
+        assert_eq!(peers.len(), 2);
+        assert!(
+            resolve_peers(
+                &Options::default(),
+                &NetworkSnapshot::default(),
+                Verbosity::Normal
+            )
+            .is_err()
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_expand_peer() {
+        assert_eq!(
+            expand_peer("203.0.113.1", Verbosity::Normal).unwrap(),
+            vec!["203.0.113.1".parse::<IpAddr>().unwrap()]
+        );
+        assert!(expand_peer("10.8.0.1", Verbosity::Normal).is_err());
+
+        let resolve = |host: &str| -> Result<Vec<IpAddr>> {
+            match host {
+                "localhost" => Ok(vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()]),
+                "vpn.example.com" => Ok(vec![
+                    "10.8.0.1".parse().unwrap(),
+                    "203.0.113.1".parse().unwrap(),
+                    "2001:db8::1".parse().unwrap(),
+                ]),
+                _ => bail!("Could not resolve {host}"),
+            }
+        };
+        assert_eq!(
+            expand_peer_with("vpn.example.com", resolve, Verbosity::Normal).unwrap(),
+            vec![
+                "203.0.113.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+        // Resolves, but only to loopback
+        assert!(expand_peer_with("localhost", resolve, Verbosity::Normal).is_err());
+        assert!(expand_peer_with("nonexistent.invalid", resolve, Verbosity::Normal).is_err());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_expand_destination() {
+        assert_eq!(
+            expand_destination("198.51.100.0/24", Verbosity::Normal).unwrap(),
+            vec!["198.51.100.0/24"]
+        );
+        assert_eq!(
+            expand_destination("2001:db8::/32", Verbosity::Normal).unwrap(),
+            vec!["2001:db8::/32"]
+        );
+        assert_eq!(
+            expand_destination("10.1.2.3", Verbosity::Normal).unwrap(),
+            vec!["10.1.2.3"]
+        );
+        assert!(expand_destination("198.51.100.0/33", Verbosity::Normal).is_err());
+        assert!(expand_destination("sso/24", Verbosity::Normal).is_err());
+        let resolve = |host: &str| -> Result<Vec<IpAddr>> {
+            match host {
+                "sso.example.com" => Ok(vec!["192.0.2.10".parse().unwrap()]),
+                _ => bail!("Could not resolve {host}"),
+            }
+        };
+        assert_eq!(
+            expand_destination_with("sso.example.com", resolve).unwrap(),
+            vec!["192.0.2.10"]
+        );
+        assert!(expand_destination_with("nonexistent.invalid", resolve).is_err());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_resolve_allowlist() {
+        let path =
+            std::env::temp_dir().join(format!("killswitch-allow-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# monitoring
+192.0.2.10
+198.51.100.0/24
+",
+        )
+        .unwrap();
+        let options = Options {
+            allow: vec!["198.51.100.0/24".to_string()],
+            allow_file: Some(path.clone()),
+            ..Options::default()
+        };
+        let allow = resolve_allowlist(&options, Verbosity::Normal);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(allow.unwrap(), vec!["198.51.100.0/24", "192.0.2.10"]);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_resolve_deny() {
+        let path = std::env::temp_dir().join(format!("killswitch-deny-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# mail
+25
+tcp/465
+# telemetry
+203.0.113.0/24
+",
+        )
+        .unwrap();
+        let mut options = Options {
+            deny: vec!["25".parse().unwrap()],
+            deny_file: Some(path.clone()),
+            ..Options::default()
+        };
+        let deny = resolve_deny(&options).unwrap();
+        let entries: Vec<String> = deny.iter().map(ToString::to_string).collect();
+        assert_eq!(entries, ["25", "tcp/465", "203.0.113.0/24"]);
+
+        std::fs::write(&path, "smtp\n").unwrap();
+        let err = resolve_deny(&options).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Invalid deny entry smtp"),
+            "{err:#}"
+        );
+        std::fs::remove_file(&path).unwrap();
+        options.deny_file = None;
+        assert_eq!(resolve_deny(&options).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_refresh_interval() {
+        assert_eq!(refresh_interval(Some(90)), Duration::from_secs(90));
+        assert_eq!(refresh_interval(Some(5)), MIN_REFRESH);
+        assert_eq!(refresh_interval(Some(86400)), MAX_REFRESH);
+        assert_eq!(
+            refresh_interval(None),
+            Duration::from_secs(u64::from(network::DEFAULT_TTL))
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_resolve_bypass() {
+        let (bypass, ttl) = resolve_bypass(&Options::default(), Verbosity::Normal).unwrap();
+        assert!(bypass.is_empty());
+        assert_eq!(ttl, None);
+        let resolve = |domain: &str| -> Result<(Vec<IpAddr>, u32)> {
+            match domain {
+                "a.example.com" => Ok((vec!["192.0.2.1".parse().unwrap()], 300)),
+                "b.example.com" => Ok((vec!["192.0.2.1".parse().unwrap()], 60)),
+                _ => bail!("Could not resolve {domain}"),
+            }
+        };
+        let mut options = Options {
+            bypass: vec!["a.example.com".to_string(), "b.example.com".to_string()],
+            ..Options::default()
+        };
+        let (bypass, ttl) = resolve_bypass_with(&options, resolve, Verbosity::Normal).unwrap();
+        assert_eq!(bypass, ["192.0.2.1"]);
+        assert_eq!(ttl, Some(60));
+        options.bypass = vec!["nonexistent.invalid".to_string()];
+        assert!(resolve_bypass_with(&options, resolve, Verbosity::Normal).is_err());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_resolve_owners() {
+        let owners =
+            resolve_owners(&["root".to_string(), "501".to_string()], &["0".to_string()]).unwrap();
+        assert_eq!(owners.users, [0, 501]);
+        assert_eq!(owners.groups, [0]);
+        assert!(resolve_owners(&[], &[]).unwrap().is_empty());
+        assert!(resolve_uid("nonexistent-killswitch-user").is_err());
+        assert!(resolve_gid("nonexistent-killswitch-group").is_err());
+    }
+
+    #[test]
+    fn test_edit_list() {
+        let mut list = vec!["a".to_string(), "b".to_string()];
+        edit_list(
+            &mut list,
+            &["b".to_string(), "c".to_string()],
+            &["a".to_string()],
+        );
+        assert_eq!(list, vec!["b", "c"]);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_update_peers() {
+        let mut state = State {
+            options: Options::default(),
+            peers: vec!["203.0.113.1".to_string()],
+        };
+        let changes = Update {
+            add_peers: vec!["198.51.100.7".to_string()],
+            ..Update::default()
+        };
+        update_peers(&mut state, &changes, Verbosity::Normal).unwrap();
+        // The detected peer is kept once peers are explicit
+        assert_eq!(state.options.peers, vec!["203.0.113.1", "198.51.100.7"]);
+        assert_eq!(state.peers, vec!["203.0.113.1", "198.51.100.7"]);
+
+        let changes = Update {
+            remove_peers: vec!["203.0.113.1".to_string()],
+            ..Update::default()
+        };
+        update_peers(&mut state, &changes, Verbosity::Normal).unwrap();
+        assert_eq!(state.options.peers, vec!["198.51.100.7"]);
+        assert_eq!(state.peers, vec!["198.51.100.7"]);
+
+        let changes = Update {
+            remove_peers: vec!["198.51.100.7".to_string()],
+            ..Update::default()
+        };
+        assert!(update_peers(&mut state, &changes, Verbosity::Normal).is_err());
+
+        let changes = Update {
+            add_peers: vec!["10.8.0.1".to_string()],
+            ..Update::default()
+        };
+        assert!(update_peers(&mut state, &changes, Verbosity::Normal).is_err());
+    }
+
+    #[test]
+    fn test_validate_peer_ipv4() {
+        assert!(validate_peer("203.0.113.1").is_ok());
+        assert!(validate_peer("10.8.0.1").is_err());
+        assert!(validate_peer("100.100.100.100").is_err());
+        assert!(validate_peer("100.128.0.1").is_ok());
+        assert!(validate_peer("not-an-ip").is_err());
+    }
+}
@@ -3,11 +3,14 @@
 //! This module provides functions to detect:
 //! - VPN peer IP (the remote server's public IP address)
 //! - Active network interfaces
+//! - Routing table entries and DNS resolvers
 //! - Public IP address
 
 use crate::cli::verbosity::Verbosity;
-use crate::killswitch::is_private_ip;
+use crate::killswitch::{PeerProto, is_private_ip, is_private_ipv6};
 use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::net::{IpAddr, ToSocketAddrs};
 use std::process::Command;
 
@@ -15,14 +18,51 @@ use std::process::Command;
 // VPN Peer IP Detection
 // ============================================================================
 
-/// Detect the VPN server's public IP address (the remote peer endpoint).
+/// A detected VPN peer and, when known, how the tunnel reaches it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerEndpoint {
+    pub address: String,
+    pub port: Option<u16>,
+    pub proto: Option<PeerProto>,
+}
+
+impl PeerEndpoint {
+    fn new(address: &str, port: Option<u16>, proto: Option<PeerProto>) -> Self {
+        Self {
+            address: address.to_string(),
+            port,
+            proto,
+        }
+    }
+}
+
+impl fmt::Display for PeerEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Some(port) if self.address.contains(':') => write!(f, "[{}]:{port}", self.address)?,
+            Some(port) => write!(f, "{}:{port}", self.address)?,
+            None => write!(f, "{}", self.address)?,
+        }
+        if let Some(proto) = self.proto {
+            write!(f, "/{proto}")?;
+        }
+        Ok(())
+    }
+}
+
+type DetectFn = fn(Verbosity) -> Result<Vec<PeerEndpoint>>;
+
+/// Detect the VPN servers' public IP addresses (the remote peer endpoints).
 ///
-/// This is the IP that firewall rules must allow traffic to in order to keep
+/// These are the IPs that firewall rules must allow traffic to in order to keep
 /// the VPN tunnel alive. Not to be confused with:
 /// - Local tunnel IP (e.g., `10.8.0.2`) - your address inside the tunnel
 /// - Tunnel gateway (e.g., `10.8.0.1`) - the server's address inside the tunnel
 ///
-/// Detection methods tried in order:
+/// Every detection method is tried and all peers found are returned (in
+/// order, without duplicates), so clients failing over between several
+/// servers keep working. The tunnel port and protocol are kept when the
+/// method reports them (`WireGuard` endpoints, scutil `RemoteAddress`):
 /// 1. netstat - Parse routing table for UGSH/UGSc routes (most reliable)
 /// 2. `WireGuard` - Query `wg show` for endpoint IPs
 /// 3. Tailscale - Query `tailscale status` for exit node
@@ -30,49 +70,61 @@ use std::process::Command;
 ///
 /// # Errors
 /// Returns an error if no VPN peer IP can be detected.
-pub fn detect_vpn_peer(verbose: Verbosity) -> Result<String> {
-    // Method 1: netstat routing table (most reliable for traditional VPNs)
-    if verbose.is_debug() {
-        eprintln!("  Trying netstat routing table...");
-    }
-    if let Ok(peer) = detect_peer_from_netstat(verbose) {
-        return Ok(peer);
-    }
-
-    // Method 2: WireGuard
-    if verbose.is_debug() {
-        eprintln!("  Trying WireGuard (wg show)...");
-    }
-    if let Ok(peer) = detect_peer_from_wireguard(verbose) {
-        return Ok(peer);
-    }
+pub fn detect_vpn_peers(verbose: Verbosity) -> Result<Vec<PeerEndpoint>> {
+    let methods: [(&str, DetectFn); 4] = [
+        // Method 1: netstat routing table (most reliable for traditional VPNs)
+        ("netstat routing table", detect_peer_from_netstat),
+        // Method 2: WireGuard
+        ("WireGuard (wg show)", detect_peer_from_wireguard),
+        // Method 3: Tailscale
+        ("Tailscale", detect_peer_from_tailscale),
+        // Method 4: macOS scutil (Network Extension VPNs)
+        ("scutil (macOS Network Extension)", detect_peer_from_scutil),
+    ];
 
-    // Method 3: Tailscale
-    if verbose.is_debug() {
-        eprintln!("  Trying Tailscale...");
-    }
-    if let Ok(peer) = detect_peer_from_tailscale(verbose) {
-        return Ok(peer);
+    let mut peers: Vec<PeerEndpoint> = Vec::new();
+    for (name, detect) in methods {
+        if verbose.is_debug() {
+            eprintln!("  Trying {name}...");
+        }
+        for peer in detect(verbose).unwrap_or_default() {
+            match peers.iter_mut().find(|p| p.address == peer.address) {
+                // A later method may know the port of an already found peer
+                Some(known) if known.port.is_none() => *known = peer,
+                Some(_) => {}
+                None => peers.push(peer),
+            }
+        }
     }
 
-    // Method 4: macOS scutil (Network Extension VPNs)
-    if verbose.is_debug() {
-        eprintln!("  Trying scutil (macOS Network Extension)...");
-    }
-    if let Ok(peer) = detect_peer_from_scutil(verbose) {
-        return Ok(peer);
+    if peers.is_empty() {
+        bail!("Could not detect VPN peer IP. Please specify it manually with --peer");
     }
-
-    bail!("Could not detect VPN peer IP. Please specify it manually with --ipv4")
+    Ok(peers)
 }
 
 /// Detect VPN peer IP from netstat routing table.
 ///
 /// Looks for routes with UGSH (Up, Gateway, Static, Host) or `UGSc` flags.
-/// These routes point directly to the VPN server's public IP.
-fn detect_peer_from_netstat(verbose: Verbosity) -> Result<String> {
+/// These routes point directly to the VPN server's public IP. The IPv4
+/// table is searched first, then the IPv6 table.
+fn detect_peer_from_netstat(verbose: Verbosity) -> Result<Vec<PeerEndpoint>> {
+    let mut peers = Vec::new();
+    for family in ["inet", "inet6"] {
+        if let Ok(found) = detect_peer_from_netstat_family(family, verbose) {
+            peers.extend(found);
+        }
+    }
+
+    if peers.is_empty() {
+        bail!("No VPN peer found in routing table");
+    }
+    Ok(peers)
+}
+
+fn detect_peer_from_netstat_family(family: &str, verbose: Verbosity) -> Result<Vec<PeerEndpoint>> {
     let output = Command::new("netstat")
-        .args(["-rn", "-f", "inet"])
+        .args(["-rn", "-f", family])
         .output()
         .context("Failed to execute netstat")?;
 
@@ -81,6 +133,7 @@ fn detect_peer_from_netstat(verbose: Verbosity) -> Result<String> {
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut peers = Vec::new();
 
     // Look for routes with UGSH or UGSc flags
     // Format: "Destination  Gateway  Flags  Netif Expire"
@@ -95,20 +148,23 @@ fn detect_peer_from_netstat(verbose: Verbosity) -> Result<String> {
                 if verbose.is_verbose() {
                     eprintln!("  Detected VPN peer via netstat: {peer_ip}");
                 }
-                return Ok(peer_ip);
+                peers.push(PeerEndpoint::new(&peer_ip, None, None));
             } else if verbose.is_debug() {
                 eprintln!("  Skipping non-public route destination: {peer_ip}");
             }
         }
     }
 
-    bail!("No VPN peer found in routing table")
+    if peers.is_empty() {
+        bail!("No VPN peer found in routing table");
+    }
+    Ok(peers)
 }
 
 /// Detect VPN peer IP from `WireGuard`.
 ///
 /// Parses `wg show` output for endpoint addresses.
-fn detect_peer_from_wireguard(verbose: Verbosity) -> Result<String> {
+fn detect_peer_from_wireguard(verbose: Verbosity) -> Result<Vec<PeerEndpoint>> {
     let output = Command::new("wg")
         .args(["show"])
         .output()
@@ -119,31 +175,33 @@ fn detect_peer_from_wireguard(verbose: Verbosity) -> Result<String> {
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut peers = Vec::new();
 
-    // Look for "endpoint: <ip>:<port>" lines
+    // Look for "endpoint: <ip>:<port>" or "endpoint: [<ipv6>]:<port>" lines
     for line in stdout.lines() {
         let trimmed = line.trim();
         if let Some(endpoint) = trimmed.strip_prefix("endpoint:") {
-            let endpoint = endpoint.trim();
-            // Extract IP from "IP:port" format
-            if let Some(ip) = endpoint.split(':').next()
-                && is_valid_vpn_peer(ip)
-            {
+            let (ip, port) = split_port(endpoint.trim());
+            if is_valid_vpn_peer(ip) {
+                let peer = PeerEndpoint::new(ip, port, Some(PeerProto::Udp));
                 if verbose.is_verbose() {
-                    eprintln!("  Detected VPN peer via WireGuard: {ip}");
+                    eprintln!("  Detected VPN peer via WireGuard: {peer}");
                 }
-                return Ok(ip.to_string());
+                peers.push(peer);
             }
         }
     }
 
-    bail!("No WireGuard endpoint found")
+    if peers.is_empty() {
+        bail!("No WireGuard endpoint found");
+    }
+    Ok(peers)
 }
 
 /// Detect VPN peer IP from Tailscale.
 ///
 /// Queries `tailscale status` for exit node information.
-fn detect_peer_from_tailscale(verbose: Verbosity) -> Result<String> {
+fn detect_peer_from_tailscale(verbose: Verbosity) -> Result<Vec<PeerEndpoint>> {
     // First check if using an exit node
     let output = Command::new("tailscale")
         .args(["status", "--json"])
@@ -164,6 +222,7 @@ fn detect_peer_from_tailscale(verbose: Verbosity) -> Result<String> {
 
     // Try to find the exit node's IP from peer list
     // This is a simplified approach - full JSON parsing would be more robust
+    let mut peers = Vec::new();
     for line in stdout.lines() {
         let trimmed = line.trim();
         // Look for public IPs in the output that could be exit node endpoints
@@ -171,27 +230,89 @@ fn detect_peer_from_tailscale(verbose: Verbosity) -> Result<String> {
             && let Some(start) = trimmed.find(':')
             && let Some(addr_part) = trimmed.get(start + 1..)
         {
-            let addr = addr_part.trim().trim_matches('"').trim_matches(',');
-            // Extract IP from "IP:port" format
-            if let Some(ip) = addr.split(':').next()
-                && is_valid_vpn_peer(ip)
-            {
+            let addr = addr_part.trim().trim_matches(',').trim_matches('"');
+            // Extract IP from "IP:port" or "[IPv6]:port" format
+            let (ip, port) = split_port(addr);
+            if is_valid_vpn_peer(ip) {
+                // Direct Tailscale connections are WireGuard over UDP
+                let peer = PeerEndpoint::new(ip, port, Some(PeerProto::Udp));
                 if verbose.is_verbose() {
-                    eprintln!("  Detected VPN peer via Tailscale: {ip}");
+                    eprintln!("  Detected VPN peer via Tailscale: {peer}");
                 }
-                return Ok(ip.to_string());
+                peers.push(peer);
             }
         }
     }
 
-    bail!("No Tailscale exit node peer found")
+    if peers.is_empty() {
+        bail!("No Tailscale exit node peer found");
+    }
+    Ok(peers)
+}
+
+/// Tailscale coordination servers, reached outside the tunnel to log in and
+/// receive the network map.
+pub const TAILSCALE_CONTROL: &[&str] = &["controlplane.tailscale.com", "login.tailscale.com"];
+
+/// Addresses of the Tailscale DERP relays, from `tailscale debug derp-map`.
+///
+/// # Errors
+/// Returns an error if tailscale is not running or lists no relay.
+pub fn get_tailscale_derp() -> Result<Vec<String>> {
+    let output = Command::new("tailscale")
+        .args(["debug", "derp-map"])
+        .output()
+        .context("Failed to execute tailscale debug derp-map")?;
+
+    if !output.status.success() {
+        bail!("tailscale debug derp-map command failed");
+    }
+
+    let relays = parse_derp_map(&String::from_utf8_lossy(&output.stdout))?;
+    if relays.is_empty() {
+        bail!("No Tailscale DERP relay found");
+    }
+    Ok(relays)
+}
+
+/// Parse the `IPv4` and `IPv6` addresses of the nodes of a DERP map.
+///
+/// # Errors
+/// Returns an error if the map is not valid JSON.
+pub fn parse_derp_map(json: &str) -> Result<Vec<String>> {
+    let map: serde_json::Value = serde_json::from_str(json).context("Invalid DERP map")?;
+    let mut relays = Vec::new();
+    let regions = map
+        .get("Regions")
+        .and_then(serde_json::Value::as_object)
+        .into_iter()
+        .flat_map(|regions| regions.values());
+    for region in regions {
+        let nodes = region
+            .get("Nodes")
+            .and_then(serde_json::Value::as_array)
+            .into_iter()
+            .flatten();
+        for node in nodes {
+            for family in ["IPv4", "IPv6"] {
+                // "none" disables a family, an empty value means DNS is used
+                let Some(addr) = node.get(family).and_then(serde_json::Value::as_str) else {
+                    continue;
+                };
+                if addr.parse::<IpAddr>().is_ok() && !relays.iter().any(|r| r == addr) {
+                    relays.push(addr.to_string());
+                }
+            }
+        }
+    }
+    Ok(relays)
 }
 
 /// Detect VPN peer IP via macOS Network Extension (scutil).
 ///
 /// Works for VPN apps that use macOS Network Extension framework
 /// (e.g., `NordVPN`, `ProtonVPN`, Fortinet).
-fn detect_peer_from_scutil(verbose: Verbosity) -> Result<String> {
+fn detect_peer_from_scutil(verbose: Verbosity) -> Result<Vec<PeerEndpoint>> {
     let list_output = Command::new("scutil")
         .args(["--nc", "list"])
         .output()
@@ -202,6 +323,7 @@ fn detect_peer_from_scutil(verbose: Verbosity) -> Result<String> {
     }
 
     let stdout = String::from_utf8_lossy(&list_output.stdout);
+    let mut peers = Vec::new();
 
     for line in stdout.lines() {
         if !line.contains("(Connected)") {
@@ -233,73 +355,140 @@ fn detect_peer_from_scutil(verbose: Verbosity) -> Result<String> {
             let trimmed = detail_line.trim();
             if let Some(raw) = trimmed.strip_prefix("RemoteAddress : ") {
                 let raw = raw.trim();
-                let host = strip_port(raw);
+                let (host, port) = split_port(raw);
 
-                // Resolve hostname to IP if needed
+                // Resolve hostname to every IP if needed
                 let resolved = if host.parse::<IpAddr>().is_ok() {
-                    host.to_string()
+                    vec![host.to_string()]
                 } else {
-                    match resolve_hostname_v4(host, verbose) {
-                        Some(ip) => ip,
-                        None => continue,
+                    match resolve_hostname_all(host, verbose) {
+                        Ok(addrs) => addrs.iter().map(ToString::to_string).collect(),
+                        Err(e) => {
+                            if verbose.is_debug() {
+                                eprintln!("  {e:#}");
+                            }
+                            continue;
+                        }
                     }
                 };
 
-                if is_valid_vpn_peer(&resolved) {
-                    if verbose.is_verbose() {
-                        eprintln!("  Detected VPN peer via scutil: {resolved}");
+                for addr in resolved {
+                    if is_valid_vpn_peer(&addr) {
+                        // The protocol depends on the VPN type, only the port is known
+                        let peer = PeerEndpoint::new(&addr, port, None);
+                        if verbose.is_verbose() {
+                            eprintln!("  Detected VPN peer via scutil: {peer}");
+                        }
+                        peers.push(peer);
+                    } else if verbose.is_debug() {
+                        eprintln!("  Skipping non-public RemoteAddress: {addr}");
                     }
-                    return Ok(resolved);
-                } else if verbose.is_debug() {
-                    eprintln!("  Skipping non-public RemoteAddress: {resolved}");
                 }
             }
         }
     }
 
-    bail!("No VPN peer found via scutil")
+    if peers.is_empty() {
+        bail!("No VPN peer found via scutil");
+    }
+    Ok(peers)
 }
 
-/// Strip optional port suffix from a remote address.
+/// Split the optional port suffix from a remote address.
 ///
 /// Handles `host:port`, `ip:port`, and `[ipv6]:port` forms.
-/// Returns the bare host/IP.
-fn strip_port(raw: &str) -> &str {
+/// Returns the bare host/IP and the port, if any.
+fn split_port(raw: &str) -> (&str, Option<u16>) {
     if let Some(rest) = raw.strip_prefix('[') {
         // [ipv6]:port — extract content between brackets
-        rest.split(']').next().unwrap_or(raw)
-    } else if raw.matches(':').count() == 1 {
+        let (host, after) = rest.split_once(']').unwrap_or((rest, ""));
+        (host, after.strip_prefix(':').and_then(|p| p.parse().ok()))
+    } else if let Some((host, port)) = raw.split_once(':')
+        && !port.contains(':')
+    {
         // host:port or ipv4:port — split on the single colon
-        raw.split(':').next().unwrap_or(raw)
+        (host, port.parse().ok())
     } else {
         // bare IP, bare hostname, or bare IPv6 (multiple colons, no brackets)
-        raw
+        (raw, None)
     }
 }
 
-/// Resolve a hostname to its first IPv4 address.
-fn resolve_hostname_v4(host: &str, verbose: Verbosity) -> Option<String> {
+/// Resolve a hostname to all of its IPv4 and IPv6 addresses.
+///
+/// # Errors
+/// Returns an error if the name cannot be resolved.
+pub fn resolve_hostname_all(host: &str, verbose: Verbosity) -> Result<Vec<IpAddr>> {
     if verbose.is_debug() {
         eprintln!("  Resolving hostname: {host}");
     }
-    match format!("{host}:0").to_socket_addrs() {
-        Ok(addrs) => {
-            if let Some(addr) = addrs.into_iter().find(std::net::SocketAddr::is_ipv4) {
-                Some(addr.ip().to_string())
-            } else {
-                if verbose.is_debug() {
-                    eprintln!("  No IPv4 address for: {host}");
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    for addr in format!("{host}:0")
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {host}"))?
+    {
+        if !addrs.contains(&addr.ip()) {
+            addrs.push(addr.ip());
+        }
+    }
+    if addrs.is_empty() {
+        bail!("{host} has no address");
+    }
+    Ok(addrs)
+}
+
+/// TTL assumed when the resolver does not report one
+pub const DEFAULT_TTL: u32 = 300;
+
+/// Resolve a hostname to all of its IPv4 and IPv6 addresses together with
+/// the shortest TTL of the records.
+///
+/// The records are queried with `dig`, falling back to the system resolver
+/// (and [`DEFAULT_TTL`]) when it is not available.
+///
+/// # Errors
+/// Returns an error if the name cannot be resolved.
+pub fn resolve_hostname_ttl(host: &str, verbose: Verbosity) -> Result<(Vec<IpAddr>, u32)> {
+    let answer = Command::new("dig")
+        .args(["+noall", "+answer", host, "A", host, "AAAA"])
+        .output();
+
+    if let Ok(output) = answer
+        && output.status.success()
+    {
+        let records = parse_dig_answer(&String::from_utf8_lossy(&output.stdout));
+        if let Some(ttl) = records.iter().map(|(_, ttl)| *ttl).min() {
+            let mut addrs: Vec<IpAddr> = Vec::new();
+            for (addr, _) in records {
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
                 }
-                None
             }
-        }
-        Err(e) => {
             if verbose.is_debug() {
-                eprintln!("  DNS resolution failed for {host}: {e}");
+                eprintln!("  Resolved {host} via dig (TTL {ttl}s)");
             }
-            None
+            return Ok((addrs, ttl));
         }
     }
+
+    Ok((resolve_hostname_all(host, verbose)?, DEFAULT_TTL))
+}
+
+/// Parse the A and AAAA records of `dig +noall +answer` output.
+///
+/// Format: "name.  TTL  IN  A|AAAA  address", other records (CNAME) are skipped.
+#[must_use]
+pub fn parse_dig_answer(stdout: &str) -> Vec<(IpAddr, u32)> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [_, ttl, "IN", "A" | "AAAA", addr] => Some((addr.parse().ok()?, ttl.parse().ok()?)),
+                _ => None,
+            }
+        })
+        .collect()
 }
 
 /// Extract destination IP from netstat routing table line.
@@ -318,14 +507,16 @@ fn extract_route_destination(line: &str) -> Option<String> {
     }
 }
 
-/// Check if an IP is a valid VPN peer (public, routable IPv4 address).
+/// Check if an IP is a valid VPN peer (public, routable IPv4 or IPv6 address).
 fn is_valid_vpn_peer(ip: &str) -> bool {
     let Ok(addr) = ip.parse::<IpAddr>() else {
         return false;
     };
 
-    let IpAddr::V4(ipv4) = addr else {
-        return false; // Only IPv4 supported for now
+    let ipv4 = match addr {
+        IpAddr::V4(ipv4) => ipv4,
+        // Rejects loopback, unspecified, ULA, link-local and multicast
+        IpAddr::V6(ipv6) => return !is_private_ipv6(&ipv6),
     };
 
     let octets = ipv4.octets();
@@ -358,6 +549,7 @@ fn is_valid_vpn_peer(ip: &str) -> bool {
 // ============================================================================
 
 /// Represents a detected network interface.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct InterfaceInfo {
     name: String,
     mac: String,
@@ -366,6 +558,17 @@ pub struct InterfaceInfo {
 }
 
 impl InterfaceInfo {
+    /// Create an interface description (used by snapshots and tests).
+    #[must_use]
+    pub fn new(name: &str, mac: &str, ip: &str, is_p2p: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            mac: mac.to_string(),
+            ip: ip.to_string(),
+            is_p2p,
+        }
+    }
+
     /// Get the interface name (e.g., "en0", "utun0").
     #[must_use]
     pub fn name(&self) -> &str {
@@ -389,9 +592,16 @@ impl InterfaceInfo {
     pub fn is_p2p(&self) -> bool {
         self.is_p2p
     }
+
+    /// Check if this entry is a global (non link-local) IPv6 address.
+    #[must_use]
+    pub fn is_ipv6(&self) -> bool {
+        self.ip.contains(':')
+    }
 }
 
-/// Discover active network interfaces (up, non-loopback, IPv4).
+/// Discover active network interfaces (up, non-loopback, IPv4 and global
+/// IPv6), one entry per address.
 ///
 /// Returns both regular interfaces and point-to-point (VPN) interfaces.
 ///
@@ -406,7 +616,12 @@ pub fn get_interfaces() -> Result<Vec<InterfaceInfo>> {
         bail!("ifconfig command failed");
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(parse_ifconfig(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse `ifconfig` output into active interfaces.
+#[must_use]
+pub fn parse_ifconfig(stdout: &str) -> Vec<InterfaceInfo> {
     let mut interfaces = Vec::new();
     let mut current_name = String::new();
     let mut current_mac = String::new();
@@ -467,9 +682,226 @@ pub fn get_interfaces() -> Result<Vec<InterfaceInfo>> {
                 });
             }
         }
+
+        // IPv6: "inet6 2001:db8::5 prefixlen 64 autoconf secured"
+        if let Some(rest) = trimmed.strip_prefix("inet6 ") {
+            let parts: Vec<&str> = rest.split_whitespace().collect();
+            // Link-local addresses carry a scope: "fe80::1%en0"
+            let Some(ip) = parts
+                .first()
+                .and_then(|ip| ip.parse::<std::net::Ipv6Addr>().ok())
+            else {
+                continue;
+            };
+            if ip.is_loopback() || ip.is_unicast_link_local() {
+                continue;
+            }
+            let ip_display = match parts.iter().position(|&s| s == "prefixlen") {
+                Some(pos) if !current_is_p2p => match parts.get(pos + 1) {
+                    Some(len) => format!("{ip}/{len}"),
+                    None => ip.to_string(),
+                },
+                _ => ip.to_string(),
+            };
+            interfaces.push(InterfaceInfo {
+                name: current_name.clone(),
+                mac: current_mac.clone(),
+                ip: ip_display,
+                is_p2p: current_is_p2p,
+            });
+        }
+    }
+
+    interfaces
+}
+
+// ============================================================================
+// Routing Table
+// ============================================================================
+
+/// A routing table entry as reported by `netstat -rn`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    pub destination: String,
+    pub gateway: String,
+    pub flags: String,
+    pub interface: String,
+}
+
+/// Read the routing table (IPv4 and IPv6).
+///
+/// # Errors
+/// Returns an error if netstat fails to execute.
+pub fn get_routes() -> Result<Vec<Route>> {
+    let output = Command::new("netstat")
+        .args(["-rn"])
+        .output()
+        .context("Failed to execute netstat")?;
+
+    if !output.status.success() {
+        bail!("netstat command failed");
+    }
+
+    Ok(parse_netstat_routes(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Parse `netstat -rn` output.
+///
+/// Format: "Destination  Gateway  Flags  Netif Expire"
+#[must_use]
+pub fn parse_netstat_routes(stdout: &str) -> Vec<Route> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let [destination, gateway, flags, interface, ..] = parts.as_slice() else {
+                return None;
+            };
+            if *destination == "Destination" || !flags.starts_with('U') {
+                return None;
+            }
+            Some(Route {
+                destination: (*destination).to_string(),
+                gateway: (*gateway).to_string(),
+                flags: (*flags).to_string(),
+                interface: (*interface).to_string(),
+            })
+        })
+        .collect()
+}
+
+// ============================================================================
+// DNS Resolvers
+// ============================================================================
+
+/// A configured DNS resolver, optionally scoped to an interface.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsResolver {
+    pub address: String,
+    pub interface: Option<String>,
+}
+
+/// Discover the configured DNS resolvers.
+///
+/// Uses `scutil --dns` on macOS, then systemd-resolved (`resolvectl dns`) and
+/// falls back to `/etc/resolv.conf`.
+///
+/// # Errors
+/// Returns an error if no resolver configuration can be read.
+pub fn get_dns_resolvers() -> Result<Vec<DnsResolver>> {
+    if let Ok(output) = Command::new("scutil").args(["--dns"]).output()
+        && output.status.success()
+    {
+        let resolvers = parse_scutil_dns(&String::from_utf8_lossy(&output.stdout));
+        if !resolvers.is_empty() {
+            return Ok(resolvers);
+        }
+    }
+
+    if let Ok(output) = Command::new("resolvectl").args(["dns"]).output()
+        && output.status.success()
+    {
+        let resolvers = parse_resolvectl_dns(&String::from_utf8_lossy(&output.stdout));
+        if !resolvers.is_empty() {
+            return Ok(resolvers);
+        }
+    }
+
+    let conf =
+        std::fs::read_to_string("/etc/resolv.conf").context("Failed to read /etc/resolv.conf")?;
+    Ok(parse_resolv_conf(&conf))
+}
+
+/// Parse `scutil --dns` output.
+///
+/// Each `resolver #N` block lists `nameserver[i] : <ip>` entries and an
+/// optional `if_index : <n> (<iface>)` line scoping it to an interface.
+#[must_use]
+pub fn parse_scutil_dns(stdout: &str) -> Vec<DnsResolver> {
+    let mut resolvers: Vec<DnsResolver> = Vec::new();
+    let mut block: Vec<String> = Vec::new();
+    let mut block_iface: Option<String> = None;
+
+    let mut flush = |block: &mut Vec<String>, iface: &mut Option<String>| {
+        for address in block.drain(..) {
+            let resolver = DnsResolver {
+                address,
+                interface: iface.clone(),
+            };
+            if !resolvers.contains(&resolver) {
+                resolvers.push(resolver);
+            }
+        }
+        *iface = None;
+    };
+
+    for line in stdout.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("resolver #") || trimmed.starts_with("DNS configuration") {
+            flush(&mut block, &mut block_iface);
+        } else if trimmed.starts_with("nameserver[")
+            && let Some((_, addr)) = trimmed.split_once(':')
+        {
+            block.push(addr.trim().to_string());
+        } else if trimmed.starts_with("if_index")
+            && let Some(start) = trimmed.find('(')
+            && let Some(end) = trimmed.find(')')
+            && let Some(name) = trimmed.get(start + 1..end)
+        {
+            block_iface = Some(name.to_string());
+        }
+    }
+    flush(&mut block, &mut block_iface);
+
+    resolvers
+}
+
+/// Parse `resolvectl dns` output.
+///
+/// Format: `Global: <ip> ...` and `Link <n> (<iface>): <ip> ...`.
+#[must_use]
+pub fn parse_resolvectl_dns(stdout: &str) -> Vec<DnsResolver> {
+    let mut resolvers: Vec<DnsResolver> = Vec::new();
+    for line in stdout.lines() {
+        let Some((scope, servers)) = line.split_once(": ") else {
+            continue;
+        };
+        let interface = scope
+            .split_once('(')
+            .and_then(|(_, rest)| rest.strip_suffix(')'))
+            .map(String::from);
+        for address in servers.split_whitespace() {
+            // Servers may carry a port or SNI: 1.1.1.1:853#cloudflare-dns.com
+            let address = address.split('#').next().unwrap_or(address);
+            let (address, _) = split_port(address);
+            if address.parse::<IpAddr>().is_err() {
+                continue;
+            }
+            let resolver = DnsResolver {
+                address: address.to_string(),
+                interface: interface.clone(),
+            };
+            if !resolvers.contains(&resolver) {
+                resolvers.push(resolver);
+            }
+        }
     }
+    resolvers
+}
 
-    Ok(interfaces)
+/// Parse `nameserver` lines from a resolv.conf file.
+#[must_use]
+pub fn parse_resolv_conf(conf: &str) -> Vec<DnsResolver> {
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .map(|addr| DnsResolver {
+            address: addr.trim().to_string(),
+            interface: None,
+        })
+        .filter(|r| r.address.parse::<IpAddr>().is_ok())
+        .collect()
 }
 
 // ============================================================================
@@ -519,12 +951,12 @@ pub fn hex_to_cidr(hex: &str) -> Option<u8> {
 // Legacy Compatibility
 // ============================================================================
 
-/// Alias for `detect_vpn_peer` to maintain backward compatibility.
+/// Alias for `detect_vpn_peers` to maintain backward compatibility.
 ///
 /// # Errors
 /// Returns an error if no VPN peer IP can be detected.
-pub fn detect_vpn_gateway(verbose: Verbosity) -> Result<String> {
-    detect_vpn_peer(verbose)
+pub fn detect_vpn_gateway(verbose: Verbosity) -> Result<Vec<PeerEndpoint>> {
+    detect_vpn_peers(verbose)
 }
 
 // ============================================================================
@@ -557,6 +989,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_extract_route_destination_ipv6() {
+        let line = "2001:db8::1                             fe80::1%en0                     UGSH                en0";
+        assert_eq!(
+            extract_route_destination(line),
+            Some("2001:db8::1".to_string())
+        );
+    }
+
     #[test]
     fn test_extract_route_destination_default_returns_none() {
         let line = "default            192.168.1.1        UGSc              en0";
@@ -615,9 +1056,20 @@ mod tests {
     }
 
     #[test]
-    fn test_is_valid_vpn_peer_rejects_ipv6() {
+    fn test_is_valid_vpn_peer_public_ipv6() {
+        assert!(is_valid_vpn_peer("2001:db8::1"));
+        assert!(is_valid_vpn_peer("2a01:4f8::1"));
+        assert!(is_valid_vpn_peer("64:ff9b::cb00:7101")); // NAT64 of 203.0.113.1
+    }
+
+    #[test]
+    fn test_is_valid_vpn_peer_rejects_private_ipv6() {
         assert!(!is_valid_vpn_peer("::1"));
-        assert!(!is_valid_vpn_peer("2001:db8::1"));
+        assert!(!is_valid_vpn_peer("::"));
+        assert!(!is_valid_vpn_peer("fd12:3456::1"));
+        assert!(!is_valid_vpn_peer("fe80::1"));
+        assert!(!is_valid_vpn_peer("ff02::1"));
+        assert!(!is_valid_vpn_peer("64:ff9b::a08:1")); // NAT64 of 10.8.0.1
     }
 
     #[test]
@@ -648,37 +1100,256 @@ mod tests {
     }
 
     // -------------------------------------------------------------------------
-    // Port stripping tests
+    // Port splitting tests
     // -------------------------------------------------------------------------
 
     #[test]
-    fn test_strip_port_bare_ipv4() {
-        assert_eq!(strip_port("1.2.3.4"), "1.2.3.4");
+    fn test_split_port_bare_ipv4() {
+        assert_eq!(split_port("1.2.3.4"), ("1.2.3.4", None));
     }
 
     #[test]
-    fn test_strip_port_ipv4_with_port() {
-        assert_eq!(strip_port("1.2.3.4:51820"), "1.2.3.4");
+    fn test_split_port_ipv4_with_port() {
+        assert_eq!(split_port("1.2.3.4:51820"), ("1.2.3.4", Some(51820)));
     }
 
     #[test]
-    fn test_strip_port_hostname_with_port() {
-        assert_eq!(strip_port("myvpn.example.com:51820"), "myvpn.example.com");
+    fn test_split_port_hostname_with_port() {
+        assert_eq!(
+            split_port("myvpn.example.com:51820"),
+            ("myvpn.example.com", Some(51820))
+        );
     }
 
     #[test]
-    fn test_strip_port_bare_hostname() {
-        assert_eq!(strip_port("myvpn.example.com"), "myvpn.example.com");
+    fn test_split_port_bare_hostname() {
+        assert_eq!(split_port("myvpn.example.com"), ("myvpn.example.com", None));
     }
 
     #[test]
-    fn test_strip_port_ipv6_bracketed_with_port() {
-        assert_eq!(strip_port("[::1]:51820"), "::1");
+    fn test_split_port_ipv6_bracketed_with_port() {
+        assert_eq!(split_port("[::1]:51820"), ("::1", Some(51820)));
     }
 
     #[test]
-    fn test_strip_port_bare_ipv6() {
+    fn test_split_port_bare_ipv6() {
         // Bare IPv6 has multiple colons, no brackets — returned as-is
-        assert_eq!(strip_port("2001:db8::1"), "2001:db8::1");
+        assert_eq!(split_port("2001:db8::1"), ("2001:db8::1", None));
+    }
+
+    #[test]
+    fn test_split_port_invalid_port() {
+        assert_eq!(split_port("1.2.3.4:vpn"), ("1.2.3.4", None));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_parse_dig_answer() {
+        let stdout = "bank.example.\t\t60\tIN\tCNAME\tedge.bank.example.
+edge.bank.example.\t20\tIN\tA\t192.0.2.10
+edge.bank.example.\t45\tIN\tA\t192.0.2.11
+edge.bank.example.\t30\tIN\tAAAA\t2001:db8::10
+";
+        assert_eq!(
+            parse_dig_answer(stdout),
+            vec![
+                ("192.0.2.10".parse().unwrap(), 20),
+                ("192.0.2.11".parse().unwrap(), 45),
+                ("2001:db8::10".parse().unwrap(), 30),
+            ]
+        );
+        assert!(parse_dig_answer("").is_empty());
+    }
+
+    #[test]
+    fn test_peer_endpoint_display() {
+        let peer = PeerEndpoint::new("203.0.113.1", Some(51820), Some(PeerProto::Udp));
+        assert_eq!(peer.to_string(), "203.0.113.1:51820/udp");
+        let peer = PeerEndpoint::new("2001:db8::1", Some(443), None);
+        assert_eq!(peer.to_string(), "[2001:db8::1]:443");
+        let peer = PeerEndpoint::new("203.0.113.1", None, None);
+        assert_eq!(peer.to_string(), "203.0.113.1");
+    }
+
+    // -------------------------------------------------------------------------
+    // ifconfig / netstat / DNS parsing tests
+    // -------------------------------------------------------------------------
+
+    const IFCONFIG: &str = "lo0: flags=8049<UP,LOOPBACK,RUNNING,MULTICAST> mtu 16384
+\tinet 127.0.0.1 netmask 0xff000000
+en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+\tether a4:83:e7:00:00:01
+\tinet6 fe80::1%en0 prefixlen 64 secured scopeid 0x6
+\tinet 192.168.1.100 netmask 0xffffff00 broadcast 192.168.1.255
+en1: flags=8822<BROADCAST,SMART,SIMPLEX,MULTICAST> mtu 1500
+\tether a4:83:e7:00:00:02
+utun3: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1380
+\tinet 10.8.0.2 --> 10.8.0.1 netmask 0xffffffff
+";
+
+    #[test]
+    fn test_parse_ifconfig() {
+        let interfaces = parse_ifconfig(IFCONFIG);
+        assert_eq!(
+            interfaces,
+            vec![
+                InterfaceInfo::new("en0", "a4:83:e7:00:00:01", "192.168.1.100/24", false),
+                InterfaceInfo::new("utun3", "", "10.8.0.2", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ifconfig_ipv6() {
+        let stdout = "en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+\tether a4:83:e7:00:00:01
+\tinet6 fe80::1%en0 prefixlen 64 secured scopeid 0x6
+\tinet 192.168.1.100 netmask 0xffffff00 broadcast 192.168.1.255
+\tinet6 2001:db8::5 prefixlen 64 autoconf secured
+utun3: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1380
+\tinet6 fe80::2%utun3 prefixlen 64 scopeid 0x10
+\tinet 10.8.0.2 --> 10.8.0.1 netmask 0xffffffff
+\tinet6 fd00::2 prefixlen 128
+";
+        let interfaces = parse_ifconfig(stdout);
+        assert_eq!(
+            interfaces,
+            vec![
+                InterfaceInfo::new("en0", "a4:83:e7:00:00:01", "192.168.1.100/24", false),
+                InterfaceInfo::new("en0", "a4:83:e7:00:00:01", "2001:db8::5/64", false),
+                InterfaceInfo::new("utun3", "", "10.8.0.2", true),
+                InterfaceInfo::new("utun3", "", "fd00::2", true),
+            ]
+        );
+        assert!(interfaces.iter().filter(|i| i.is_ipv6()).count() == 2);
+    }
+
+    #[test]
+    fn test_parse_netstat_routes() {
+        let out = "Routing tables
+
+Internet:
+Destination        Gateway            Flags               Netif Expire
+default            192.168.1.1        UGScg                 en0
+52.1.2.3           192.168.1.1        UGSH                  en0
+";
+        let routes = parse_netstat_routes(out);
+        assert_eq!(routes.len(), 2);
+        assert_eq!(
+            routes.get(1),
+            Some(&Route {
+                destination: "52.1.2.3".to_string(),
+                gateway: "192.168.1.1".to_string(),
+                flags: "UGSH".to_string(),
+                interface: "en0".to_string(),
+            })
+        );
+        assert!(routes.first().is_some_and(|r| r.destination == "default"));
+    }
+
+    #[test]
+    fn test_parse_scutil_dns() {
+        let out = "DNS configuration
+
+resolver #1
+  search domain[0] : lan
+  nameserver[0] : 10.8.0.1
+  if_index : 21 (utun3)
+  flags    : Request A records
+
+resolver #2
+  nameserver[0] : 192.168.1.1
+  nameserver[1] : 2001:db8::53
+
+DNS configuration (for scoped queries)
+
+resolver #1
+  nameserver[0] : 10.8.0.1
+  if_index : 21 (utun3)
+";
+        let resolvers = parse_scutil_dns(out);
+        assert_eq!(
+            resolvers,
+            vec![
+                DnsResolver {
+                    address: "10.8.0.1".to_string(),
+                    interface: Some("utun3".to_string()),
+                },
+                DnsResolver {
+                    address: "192.168.1.1".to_string(),
+                    interface: None,
+                },
+                DnsResolver {
+                    address: "2001:db8::53".to_string(),
+                    interface: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let conf = "# generated\nsearch lan\nnameserver 1.1.1.1\nnameserver bogus\n";
+        assert_eq!(
+            parse_resolv_conf(conf),
+            vec![DnsResolver {
+                address: "1.1.1.1".to_string(),
+                interface: None,
+            }]
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_parse_derp_map() {
+        let json = r#"{
+            "Regions": {
+                "1": {
+                    "RegionID": 1,
+                    "RegionCode": "nyc",
+                    "Nodes": [
+                        {"Name": "1f", "HostName": "derp1f.tailscale.com", "IPv4": "199.38.181.104", "IPv6": "2607:f740:f::bc"},
+                        {"Name": "1g", "HostName": "derp1g.tailscale.com", "IPv4": "209.177.145.120", "IPv6": "none"}
+                    ]
+                },
+                "900": {
+                    "RegionID": 900,
+                    "Nodes": [
+                        {"Name": "900a", "HostName": "derp.example.com", "IPv4": "", "IPv6": ""},
+                        {"Name": "900b", "HostName": "derp2.example.com", "IPv4": "199.38.181.104"}
+                    ]
+                }
+            }
+        }"#;
+        assert_eq!(
+            parse_derp_map(json).unwrap(),
+            ["199.38.181.104", "2607:f740:f::bc", "209.177.145.120"]
+        );
+        assert!(parse_derp_map("{}").unwrap().is_empty());
+        assert!(parse_derp_map("not json").is_err());
+    }
+
+    #[test]
+    fn test_parse_resolvectl_dns() {
+        let stdout = "Global:
+Link 2 (en0): 192.168.1.1
+Link 5 (wg0): 10.2.0.1 fd00::1 1.1.1.1#cloudflare-dns.com
+Link 6 (wg1): 1.1.1.1:853#cloudflare-dns.com [2606:4700::1111]:853#cloudflare-dns.com
+";
+        let resolvers = parse_resolvectl_dns(stdout);
+        assert_eq!(
+            resolvers
+                .iter()
+                .map(|r| (r.address.as_str(), r.interface.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("192.168.1.1", Some("en0")),
+                ("10.2.0.1", Some("wg0")),
+                ("fd00::1", Some("wg0")),
+                ("1.1.1.1", Some("wg0")),
+                ("1.1.1.1", Some("wg1")),
+                ("2606:4700::1111", Some("wg1")),
+            ]
+        );
     }
 }
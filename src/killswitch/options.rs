@@ -0,0 +1,321 @@
+use crate::killswitch::cidr::Cidr;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Settings used to generate the kill switch rules.
+// Independent command line switches, not states
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    /// VPN peer addresses or hostnames (auto-detected when empty and no peer
+    /// file is given)
+    pub peers: Vec<String>,
+    /// File listing additional VPN peers, one per line
+    pub peer_file: Option<PathBuf>,
+    /// Port the VPN tunnel uses to reach the peers (detected when not given)
+    pub peer_port: Option<u16>,
+    /// Transport protocol of the VPN tunnel (detected when not given)
+    pub peer_proto: Option<PeerProto>,
+    /// Destinations (CIDRs, addresses or hostnames) allowed outside the tunnel
+    pub allow: Vec<String>,
+    /// File listing additional allowed destinations, one per line
+    pub allow_file: Option<PathBuf>,
+    /// Domains resolved and allowed outside the tunnel, refreshed on their TTL
+    pub bypass: Vec<String>,
+    /// File listing additional bypassed domains, one per line
+    pub bypass_file: Option<PathBuf>,
+    /// Allow DNS outside the VPN: `None` blocks it, an empty list allows any
+    /// resolver, otherwise only the listed resolvers
+    pub allow_dns: Option<Vec<String>>,
+    /// Allow ICMP outside the VPN: `None` blocks it, an empty list allows
+    /// echo requests (ping), otherwise only the listed types
+    pub allow_icmp: Option<Vec<String>>,
+    /// Interfaces ICMP is allowed on (every physical interface when empty)
+    pub icmp_ifaces: Vec<String>,
+    /// Only allow DNS to the resolvers pushed by the VPN, through the tunnel
+    pub dns_lock: bool,
+    /// Allow local network traffic: `None` blocks it, an empty list allows
+    /// the network of each interface, otherwise only the listed subnets
+    /// (`!addr` entries are excluded)
+    pub local: Option<Vec<String>>,
+    /// Exclude the default gateway from the local networks
+    pub exclude_gateway: bool,
+    /// Load the rules into a dedicated pf anchor instead of replacing the
+    /// whole ruleset
+    pub anchor: bool,
+    /// Log the blocked packets to `pflog0`
+    pub log: bool,
+    /// Glob patterns of interfaces left without rules (their traffic is
+    /// blocked)
+    pub ignore_ifaces: Vec<String>,
+    /// Glob patterns of the only interfaces to generate rules for (every
+    /// interface when empty)
+    pub only_ifaces: Vec<String>,
+    /// What to do with virtualization bridges and Apple's peer-to-peer links
+    /// (`bridge*`, `vmnet*`, `awdl*`, `llw*`)
+    pub bridges: BridgePolicy,
+    /// Interfaces broadcasts (`255.255.255.255`) are allowed on
+    pub broadcast: Scope,
+    /// Interfaces UDP multicast (`224.0.0.0/4`) is allowed on
+    pub multicast: Scope,
+    /// Interfaces mDNS (`224.0.0.251` port 5353) is allowed on
+    pub mdns: Scope,
+    /// Interfaces SSDP (`239.255.255.250` port 1900) is allowed on
+    pub ssdp: Scope,
+    /// Rules for a VPN that needs more than its peers outside the tunnel
+    pub preset: Option<Preset>,
+    /// Ports open to inbound connections (every other inbound connection is
+    /// blocked)
+    pub inbound: Vec<Inbound>,
+    /// Ports and networks blocked on every interface, the tunnel included
+    pub deny: Vec<Deny>,
+    /// File listing additional denied ports and networks, one per line
+    pub deny_file: Option<PathBuf>,
+    /// Local accounts (names or UIDs) allowed outside the tunnel
+    pub exempt_users: Vec<String>,
+    /// Local groups (names or GIDs) allowed outside the tunnel
+    pub exempt_groups: Vec<String>,
+    /// Only enforce the kill switch for these accounts (names or UIDs),
+    /// every other account is allowed outside the tunnel
+    pub only_users: Vec<String>,
+    /// Only enforce the kill switch for these groups (names or GIDs)
+    pub only_groups: Vec<String>,
+    /// Directory of pf fragments spliced into the ruleset
+    pub fragments: Option<PathBuf>,
+    /// Allow fragments to pass traffic on the physical interfaces
+    pub unsafe_fragments: bool,
+    /// Allow a captive portal login on the physical interfaces (set for the
+    /// window of `killswitch portal`, never saved)
+    #[serde(skip)]
+    pub portal: bool,
+}
+
+impl Options {
+    /// The legacy `--leak`: DNS to any resolver and ping on every interface.
+    pub fn allow_leak(&mut self) {
+        self.allow_dns.get_or_insert_with(Vec::new);
+        self.allow_icmp.get_or_insert_with(Vec::new);
+    }
+
+    /// Whether peers were given explicitly instead of auto-detected.
+    #[must_use]
+    pub fn has_peers(&self) -> bool {
+        !self.peers.is_empty() || self.peer_file.is_some()
+    }
+}
+
+/// Live changes to the tables of the active kill switch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Update {
+    /// VPN peer addresses or hostnames to add to `<vpn_peers>`
+    pub add_peers: Vec<String>,
+    /// VPN peer addresses or hostnames to remove from `<vpn_peers>`
+    pub remove_peers: Vec<String>,
+    /// Destinations to add to `<allow>`
+    pub add_allow: Vec<String>,
+    /// Destinations to remove from `<allow>`
+    pub remove_allow: Vec<String>,
+    /// Subnets (or `!addr` exclusions) to add to `<local_nets>`
+    pub add_local: Vec<String>,
+    /// Subnets (or `!addr` exclusions) to remove from `<local_nets>`
+    pub remove_local: Vec<String>,
+}
+
+impl Update {
+    /// Whether the peers change.
+    #[must_use]
+    pub fn peers(&self) -> bool {
+        !self.add_peers.is_empty() || !self.remove_peers.is_empty()
+    }
+
+    /// Whether the allowed destinations change.
+    #[must_use]
+    pub fn allow(&self) -> bool {
+        !self.add_allow.is_empty() || !self.remove_allow.is_empty()
+    }
+
+    /// Whether the local subnets change.
+    #[must_use]
+    pub fn local(&self) -> bool {
+        !self.add_local.is_empty() || !self.remove_local.is_empty()
+    }
+}
+
+/// Policy for virtualization bridges and peer-to-peer links.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BridgePolicy {
+    /// No rules: their traffic is blocked
+    #[default]
+    Block,
+    /// Pass all of their traffic
+    Pass,
+    /// Route the traffic of their network through the tunnel
+    Tunnel,
+}
+
+impl fmt::Display for BridgePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Block => write!(f, "block"),
+            Self::Pass => write!(f, "pass"),
+            Self::Tunnel => write!(f, "tunnel"),
+        }
+    }
+}
+
+/// Interfaces a kind of broadcast or multicast traffic is allowed on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Every interface
+    #[default]
+    All,
+    /// Interfaces matching these glob patterns
+    Only(Vec<String>),
+    /// No interface
+    #[serde(rename = "none")]
+    Blocked,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::All => write!(f, "all"),
+            Self::Only(patterns) => write!(f, "{}", patterns.join(", ")),
+            Self::Blocked => write!(f, "none"),
+        }
+    }
+}
+
+/// Rules for VPNs that reach more than their peers outside the tunnel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// Control plane, DERP relays, direct UDP paths and `MagicDNS`
+    Tailscale,
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tailscale => write!(f, "tailscale"),
+        }
+    }
+}
+
+/// Transport protocol used by the VPN tunnel to reach its peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerProto {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for PeerProto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "tcp"),
+            Self::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// A port open to inbound connections: `PROTO/PORT[@IFACE]`, e.g. `tcp/22@en0`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inbound {
+    pub proto: PeerProto,
+    pub port: u16,
+    /// Interface the port is open on, every VPN interface when `None`
+    pub iface: Option<String>,
+}
+
+impl FromStr for Inbound {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (spec, iface) = match s.split_once('@') {
+            Some((spec, iface)) => (spec, Some(iface)),
+            None => (s, None),
+        };
+        let Some((proto, port)) = spec.split_once('/') else {
+            bail!("Invalid inbound port {s}, use PROTO/PORT[@IFACE] (e.g. tcp/22@en0)");
+        };
+        let proto = match proto {
+            "tcp" => PeerProto::Tcp,
+            "udp" => PeerProto::Udp,
+            _ => bail!("Invalid inbound protocol {proto}, use tcp or udp"),
+        };
+        let Some(port) = port.parse::<u16>().ok().filter(|&port| port > 0) else {
+            bail!("Invalid inbound port number {port}");
+        };
+        if iface.is_some_and(str::is_empty) {
+            bail!("Missing interface after @ in {s}");
+        }
+        Ok(Self {
+            proto,
+            port,
+            iface: iface.map(String::from),
+        })
+    }
+}
+
+impl fmt::Display for Inbound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.proto, self.port)?;
+        if let Some(iface) = &self.iface {
+            write!(f, "@{iface}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An outgoing port or network blocked on every interface: `PORT`,
+/// `PROTO/PORT` or a CIDR, e.g. `tcp/25` or `203.0.113.0/24`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Deny {
+    /// A destination port, TCP and UDP when no protocol is given
+    Port { proto: Option<PeerProto>, port: u16 },
+    /// A destination address or CIDR network
+    Network(String),
+}
+
+impl FromStr for Deny {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (proto, port) = match s.split_once('/') {
+            Some(("tcp", port)) => (Some(PeerProto::Tcp), port),
+            Some(("udp", port)) => (Some(PeerProto::Udp), port),
+            _ => (None, s),
+        };
+        if proto.is_some() || port.bytes().all(|b| b.is_ascii_digit()) {
+            let Some(port) = port.parse::<u16>().ok().filter(|&port| port > 0) else {
+                bail!("Invalid denied port {s}");
+            };
+            return Ok(Self::Port { proto, port });
+        }
+        match s.parse::<Cidr>() {
+            Ok(network) => Ok(Self::Network(network.to_string())),
+            Err(_) => bail!("Invalid deny entry {s}, use PORT, PROTO/PORT or a CIDR (e.g. tcp/25)"),
+        }
+    }
+}
+
+impl fmt::Display for Deny {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Port {
+                proto: Some(proto),
+                port,
+            } => write!(f, "{proto}/{port}"),
+            Self::Port { proto: None, port } => write!(f, "{port}"),
+            Self::Network(network) => f.write_str(network),
+        }
+    }
+}
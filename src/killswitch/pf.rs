@@ -1,14 +1,72 @@
 use crate::cli::verbosity::Verbosity;
+use crate::killswitch::cidr::Cidr;
+use crate::killswitch::lint;
+use crate::killswitch::ruleset::{Action, Direction, Host};
 use anyhow::{Context, Result, bail};
 use std::fs;
 use std::io::Write;
+use std::net::IpAddr;
 use std::path::Path;
 use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const PF_RULES_PATH: &str = "/tmp/killswitch.pf.conf";
 const PF_SYSTEM_CONF: &str = "/etc/pf.conf";
 
-pub fn apply_rules(rules: &str, verbose: Verbosity) -> Result<()> {
+/// Rules of an open captive portal window and the strict rules restored
+/// after it; in a root-only directory since they are loaded unattended
+const PF_PORTAL_PATH: &str = "/var/run/killswitch.portal.pf.conf";
+const PF_RESTORE_PATH: &str = "/var/run/killswitch.restore.pf.conf";
+
+/// First line of the portal rules file, followed by the expiry of the window
+/// in seconds since the Unix epoch
+const PORTAL_EXPIRES: &str = "# killswitch portal expires at ";
+
+/// launchd job restoring the strict rules once the portal window expires,
+/// even if nothing else runs `killswitch` meanwhile
+const PORTAL_JOB_LABEL: &str = "com.github.vpn-kill-switch.portal";
+const PORTAL_JOB_PATH: &str = "/Library/LaunchDaemons/com.github.vpn-kill-switch.portal.plist";
+
+/// Main ruleset loaded in anchor mode: `/etc/pf.conf` with a reference to
+/// [`ANCHOR`]; in a root-only directory since it is loaded as is
+const PF_MAIN_PATH: &str = "/var/run/killswitch.main.pf.conf";
+
+/// Anchor holding the rules in anchor mode.
+///
+/// It is referenced ahead of the other filter rules of the main ruleset, and
+/// its rules are all `quick`: the rules evaluated after it cannot override
+/// the kill switch.
+pub const ANCHOR: &str = "killswitch";
+
+/// Main ruleset rule evaluating [`ANCHOR`]
+const ANCHOR_RULE: &str = "anchor \"killswitch\"";
+
+/// First words of the filter rules of a pf.conf, which the anchor reference
+/// must come before
+const FILTER_STATEMENTS: &[&str] = &["anchor", "antispoof", "block", "pass"];
+
+/// Connection states to kill once the anchor rules are loaded: those on the
+/// physical interfaces, except to the destinations the rules still allow
+#[derive(Debug, Clone, Default)]
+pub struct StaleStates {
+    pub interfaces: Vec<String>,
+    pub keep: Vec<Cidr>,
+}
+
+/// `pfctl` arguments selecting the kill switch anchor
+const fn anchor_args(anchor: bool) -> &'static [&'static str] {
+    if anchor { &["-a", ANCHOR] } else { &[] }
+}
+
+pub fn apply_rules(
+    rules: &str,
+    anchor: bool,
+    stale: &StaleStates,
+    verbose: Verbosity,
+) -> Result<()> {
+    // A pending portal restore must not replace these rules
+    close_portal()?;
+
     if verbose.is_debug() {
         eprintln!("  Writing rules to {PF_RULES_PATH}");
     }
@@ -24,6 +82,10 @@ pub fn apply_rules(rules: &str, verbose: Verbosity) -> Result<()> {
 
     enable_pf(verbose)?;
 
+    if anchor {
+        return apply_anchor(stale, verbose);
+    }
+
     // Flush all rules and load killswitch rules
     let output = Command::new("pfctl")
         .args(["-Fa", "-f", PF_RULES_PATH])
@@ -42,6 +104,425 @@ pub fn apply_rules(rules: &str, verbose: Verbosity) -> Result<()> {
     Ok(())
 }
 
+/// Load the rules of a captive portal window, and have `strict` restored
+/// once `window` expires.
+///
+/// The expiry is written into the portal rules file, checked by
+/// [`expire_portal`] from every `killswitch` run, and a launchd job runs
+/// `killswitch --status` after the window, so the kill switch comes back
+/// even if the caller is interrupted.
+///
+/// Both rulesets are loaded with `pfctl -f` only: open states and the live
+/// content of the tables are kept.
+pub fn apply_portal(
+    portal: &str,
+    strict: &str,
+    window: Duration,
+    anchor: bool,
+    verbose: Verbosity,
+) -> Result<()> {
+    // A window opened again replaces the pending restore
+    close_portal()?;
+
+    let secs = window.as_secs().max(1);
+    let expires = unix_now() + secs;
+    fs::write(PF_RESTORE_PATH, strict).context("Failed to write strict rules")?;
+    fs::write(
+        PF_PORTAL_PATH,
+        format!("{PORTAL_EXPIRES}{expires}\n{portal}"),
+    )
+    .context("Failed to write portal rules")?;
+
+    open_portal_with(
+        || schedule_restore(secs),
+        || load_portal(anchor),
+        close_portal,
+    )?;
+
+    if verbose.is_verbose() {
+        eprintln!("  Captive portal rules applied");
+    }
+
+    Ok(())
+}
+
+/// Schedule the restore, then load the portal rules; on failure the pending
+/// restore is cancelled, and the strict rules are still the loaded ones.
+fn open_portal_with(
+    schedule: impl FnOnce() -> Result<()>,
+    load: impl FnOnce() -> Result<()>,
+    close: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    if let Err(err) = schedule().and_then(|()| load()) {
+        close()?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Install the launchd job running `killswitch --status` after `secs`
+fn schedule_restore(secs: u64) -> Result<()> {
+    let program = std::env::current_exe().context("Failed to locate the killswitch binary")?;
+    fs::write(
+        PORTAL_JOB_PATH,
+        launchd_job(&program.to_string_lossy(), secs),
+    )
+    .context("Failed to write the kill switch restore job")?;
+    let output = Command::new("launchctl")
+        .args(["bootstrap", "system", PORTAL_JOB_PATH])
+        .output()
+        .context("Failed to execute launchctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to schedule the kill switch restore: {stderr}");
+    }
+    Ok(())
+}
+
+/// Load the portal rules written to [`PF_PORTAL_PATH`]
+fn load_portal(anchor: bool) -> Result<()> {
+    let output = Command::new("pfctl")
+        .args(anchor_args(anchor))
+        .args(["-f", PF_PORTAL_PATH])
+        .output()
+        .context("Failed to execute pfctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to load portal rules: {stderr}");
+    }
+    Ok(())
+}
+
+/// Restore the strict rules if a captive portal window is open and has
+/// expired. Returns whether they were restored.
+///
+/// # Errors
+/// Returns an error if the strict rules cannot be loaded
+pub fn expire_portal(verbose: Verbosity) -> Result<bool> {
+    let Ok(portal) = fs::read_to_string(PF_PORTAL_PATH) else {
+        return Ok(false);
+    };
+    if !portal_expired(&portal, unix_now()) {
+        return Ok(false);
+    }
+
+    let output = Command::new("pfctl")
+        .args(anchor_args(anchor_loaded()))
+        .args(["-f", PF_RESTORE_PATH])
+        .output()
+        .context("Failed to execute pfctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to restore the kill switch rules: {stderr}");
+    }
+
+    if verbose.is_verbose() {
+        eprintln!("  Captive portal window expired, kill switch rules restored");
+    }
+
+    close_portal()?;
+    Ok(true)
+}
+
+/// Whether the window of a portal rules file has expired at `now`; a file
+/// without a readable expiry counts as expired
+fn portal_expired(portal: &str, now: u64) -> bool {
+    portal
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix(PORTAL_EXPIRES))
+        .and_then(|expires| expires.trim().parse::<u64>().ok())
+        .is_none_or(|expires| now >= expires)
+}
+
+/// Seconds since the Unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// launchd job running `program --status` every `interval` seconds, the
+/// first time after `interval`; it is removed once the rules are restored
+fn launchd_job(program: &str, interval: u64) -> String {
+    let program = program
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{PORTAL_JOB_LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{program}</string>
+        <string>--status</string>
+    </array>
+    <key>StartInterval</key>
+    <integer>{interval}</integer>
+</dict>
+</plist>
+"#
+    )
+}
+
+/// Cancel a pending captive portal restore
+fn close_portal() -> Result<()> {
+    for path in [PF_PORTAL_PATH, PF_RESTORE_PATH] {
+        if Path::new(path).exists() {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {path}"))?;
+        }
+    }
+    if Path::new(PORTAL_JOB_PATH).exists() {
+        fs::remove_file(PORTAL_JOB_PATH).context("Failed to remove the restore job")?;
+        // Last: run from the job itself, this terminates it
+        Command::new("launchctl")
+            .args(["bootout", &format!("system/{PORTAL_JOB_LABEL}")])
+            .output()
+            .context("Failed to execute launchctl")?;
+    }
+    Ok(())
+}
+
+/// Load the rules into [`ANCHOR`], referenced from the system main ruleset,
+/// keeping NAT, the other anchors and the states the rules still allow.
+fn apply_anchor(stale: &StaleStates, verbose: Verbosity) -> Result<()> {
+    let output = Command::new("pfctl")
+        .args(["-sr"])
+        .output()
+        .context("Failed to execute pfctl")?;
+    if !references_anchor(&String::from_utf8_lossy(&output.stdout)) {
+        let conf = fs::read_to_string(PF_SYSTEM_CONF).context("Failed to read pf.conf")?;
+        fs::write(PF_MAIN_PATH, with_anchor(&conf)).context("Failed to write main ruleset")?;
+        // Without -F: the states and the other anchors are kept
+        let output = Command::new("pfctl")
+            .args(["-f", PF_MAIN_PATH])
+            .output()
+            .context("Failed to execute pfctl")?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("Failed to reference anchor {ANCHOR} from the main ruleset: {stderr}");
+        }
+        if verbose.is_debug() {
+            eprintln!("  Main ruleset reloaded with `{ANCHOR_RULE}`");
+        }
+    }
+
+    let output = Command::new("pfctl")
+        .args(["-a", ANCHOR, "-f", PF_RULES_PATH])
+        .output()
+        .context("Failed to execute pfctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to load rules into anchor {ANCHOR}: {stderr}");
+    }
+
+    // Connections opened before the lockdown would survive the new rules
+    kill_states(stale, verbose)?;
+
+    if verbose.is_verbose() {
+        eprintln!("  Firewall rules applied to anchor {ANCHOR}");
+    }
+
+    Ok(())
+}
+
+/// Whether the main ruleset (`pfctl -sr`) evaluates [`ANCHOR`].
+fn references_anchor(main_rules: &str) -> bool {
+    main_rules
+        .lines()
+        .any(|line| line.trim_start().starts_with(ANCHOR_RULE))
+}
+
+/// A pf.conf with the reference to [`ANCHOR`] ahead of its filter rules
+fn with_anchor(conf: &str) -> String {
+    let mut lines: Vec<&str> = conf.lines().collect();
+    let filter = lines.iter().position(|line| {
+        line.split_whitespace()
+            .next()
+            .is_some_and(|first| FILTER_STATEMENTS.contains(&first))
+    });
+    lines.insert(filter.unwrap_or(lines.len()), ANCHOR_RULE);
+    lines.join("\n") + "\n"
+}
+
+/// Kill the connection states of the physical interfaces to destinations
+/// outside `stale.keep`, leaving the tunnel, bridges and NAT of other
+/// interfaces alone
+fn kill_states(stale: &StaleStates, verbose: Verbosity) -> Result<()> {
+    for iface in &stale.interfaces {
+        let output = Command::new("pfctl")
+            .args(["-i", iface, "-ss"])
+            .output()
+            .context("Failed to execute pfctl")?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("Failed to list the states of {iface}: {stderr}");
+        }
+        let remotes = state_remotes(&String::from_utf8_lossy(&output.stdout));
+        for remote in remotes
+            .iter()
+            .filter(|addr| !stale.keep.iter().any(|net| net.contains(addr)))
+        {
+            if verbose.is_debug() {
+                eprintln!("  Killing the states of {iface} with {remote}");
+            }
+            let any = if remote.is_ipv4() {
+                "0.0.0.0/0"
+            } else {
+                "::/0"
+            };
+            let remote = remote.to_string();
+            // From the remote, then to it
+            for hosts in [[remote.as_str(), ""], [any, remote.as_str()]] {
+                let mut command = Command::new("pfctl");
+                command.args(["-i", iface]);
+                for host in hosts.iter().filter(|host| !host.is_empty()) {
+                    command.args(["-k", host]);
+                }
+                let output = command.output().context("Failed to execute pfctl")?;
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    bail!("Failed to kill the states of {iface} with {remote}: {stderr}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remote addresses of the states listed by `pfctl -ss`, e.g.
+/// `en0 tcp 192.168.1.100:52345 -> 17.253.144.10:443 ESTABLISHED:ESTABLISHED`
+/// (IPv6 ports are printed as `2001:db8::1[443]`)
+fn state_remotes(states: &str) -> Vec<IpAddr> {
+    let mut remotes = Vec::new();
+    for line in states.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some(remote) = tokens
+            .iter()
+            .position(|t| *t == "->" || *t == "<-")
+            .and_then(|arrow| tokens.get(arrow + 1))
+        else {
+            continue;
+        };
+        let host = match remote.split_once('[') {
+            Some((host, _)) => host,
+            None => remote.split(':').next().unwrap_or(remote),
+        };
+        if let Ok(addr) = host.parse::<IpAddr>()
+            && !remotes.contains(&addr)
+        {
+            remotes.push(addr);
+        }
+    }
+    remotes
+}
+
+/// Pass rules of a loaded ruleset (`pfctl -sr`) that let IPv6 through,
+/// besides neighbor discovery, `DHCPv6` and link-local traffic on-link.
+///
+/// `table` lists the addresses of a table: rules whose tables only hold
+/// IPv4 addresses do not count.
+pub fn ipv6_passes(rules: &str, table: impl Fn(&str) -> Vec<String>) -> Vec<String> {
+    rules
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.first() != Some(&"pass") {
+                return false;
+            }
+            // pfctl adds `inet` to rules with IPv4 addresses
+            let on_link = ["inet", "icmp6-type", "546:547", "fe80::/10"];
+            if tokens.iter().any(|t| on_link.contains(t)) || line.contains(" on lo0 ") {
+                return false;
+            }
+            let tables: Vec<&str> = tokens
+                .iter()
+                .filter_map(|t| t.strip_prefix('<').and_then(|t| t.strip_suffix('>')))
+                .collect();
+            tables.is_empty()
+                || tables
+                    .iter()
+                    .any(|name| table(name).iter().any(|addr| addr.contains(':')))
+        })
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Ports a loaded ruleset (`pfctl -sr`) opens to inbound connections, e.g.
+/// `tcp/22 on en0 from 192.168.1.0/24`.
+pub fn inbound_ports(rules: &str) -> Vec<String> {
+    use std::fmt::Write;
+
+    rules
+        .lines()
+        .filter_map(|line| lint::parse(line).ok())
+        .flat_map(|parsed| parsed.ruleset.rules().cloned().collect::<Vec<_>>())
+        .filter(|rule| rule.action == Action::Pass && rule.direction == Some(Direction::In))
+        .filter_map(|rule| {
+            let port = rule.to.port.as_ref()?;
+            let protocols: Vec<String> = rule.protocols.iter().map(ToString::to_string).collect();
+            let mut open = format!("{}/{port}", protocols.join(","));
+            if let Some(iface) = &rule.interface {
+                let _ = write!(open, " on {iface}");
+            }
+            if rule.from.host != Host::Any {
+                let _ = write!(open, " from {}", rule.from.host);
+            }
+            Some(open)
+        })
+        .collect()
+}
+
+/// Whether rules are loaded in [`ANCHOR`].
+#[must_use]
+pub fn anchor_loaded() -> bool {
+    Command::new("pfctl")
+        .args(["-a", ANCHOR, "-sr"])
+        .output()
+        .is_ok_and(|output| {
+            output.status.success() && !String::from_utf8_lossy(&output.stdout).trim().is_empty()
+        })
+}
+
+/// Replace the addresses of a table in the loaded ruleset.
+pub fn replace_table(
+    table: &str,
+    addresses: &[String],
+    anchor: bool,
+    verbose: Verbosity,
+) -> Result<()> {
+    if verbose.is_debug() {
+        eprintln!("  Replacing table <{table}>: {}", addresses.join(", "));
+    }
+
+    let output = Command::new("pfctl")
+        .args(anchor_args(anchor))
+        .args(["-t", table, "-T", "replace"])
+        .args(addresses)
+        .output()
+        .context("Failed to execute pfctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to replace table <{table}>: {stderr}");
+    }
+
+    if verbose.is_verbose() {
+        eprintln!("  Table <{table}> updated");
+    }
+
+    Ok(())
+}
+
 fn enable_pf(verbose: Verbosity) -> Result<()> {
     if verbose.is_debug() {
         eprintln!("  Enabling pf");
@@ -68,6 +549,12 @@ fn enable_pf(verbose: Verbosity) -> Result<()> {
 }
 
 pub fn disable(verbose: Verbosity) -> Result<()> {
+    close_portal()?;
+
+    if anchor_loaded() {
+        return disable_anchor(verbose);
+    }
+
     if verbose.is_debug() {
         eprintln!("  Restoring system pf rules");
     }
@@ -100,6 +587,49 @@ pub fn disable(verbose: Verbosity) -> Result<()> {
     Ok(())
 }
 
+/// Flush the rules and tables of [`ANCHOR`] only.
+fn disable_anchor(verbose: Verbosity) -> Result<()> {
+    if verbose.is_debug() {
+        eprintln!("  Flushing anchor {ANCHOR}");
+    }
+
+    for flush in ["rules", "Tables"] {
+        let output = Command::new("pfctl")
+            .args(["-a", ANCHOR, "-F", flush])
+            .output()
+            .context("Failed to execute pfctl")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("Failed to flush anchor {ANCHOR}: {stderr}");
+        }
+    }
+
+    // The system main ruleset, without the anchor reference; without -F the
+    // states are kept
+    let output = Command::new("pfctl")
+        .args(["-f", PF_SYSTEM_CONF])
+        .output()
+        .context("Failed to execute pfctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to restore system rules: {stderr}");
+    }
+
+    for path in [PF_RULES_PATH, PF_MAIN_PATH] {
+        if Path::new(path).exists() {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {path}"))?;
+        }
+    }
+
+    if verbose.is_verbose() {
+        eprintln!("  Firewall rules removed from anchor {ANCHOR}");
+    }
+
+    Ok(())
+}
+
 fn cleanup_legacy_anchor(verbose: Verbosity) -> Result<()> {
     let conf = fs::read_to_string(PF_SYSTEM_CONF).context("Failed to read pf.conf")?;
     if !conf.contains("killswitch") {
@@ -121,8 +651,10 @@ fn cleanup_legacy_anchor(verbose: Verbosity) -> Result<()> {
     Ok(())
 }
 
-pub fn status() -> Result<String> {
+/// The loaded rules when the kill switch is active.
+pub fn active_rules(anchor: bool) -> Result<Option<String>> {
     let output = Command::new("pfctl")
+        .args(anchor_args(anchor))
         .args(["-sr"])
         .output()
         .context("Failed to execute pfctl")?;
@@ -140,9 +672,210 @@ pub fn status() -> Result<String> {
             .lines()
             .any(|line| !line.is_empty() && !line.contains("ALTQ"));
 
-    if has_killswitch {
-        Ok(format!("VPN kill switch: ENABLED\n\n{stdout}"))
-    } else {
-        Ok("VPN kill switch: DISABLED".to_string())
+    Ok(has_killswitch.then(|| stdout.into_owned()))
+}
+
+/// Names of the tables in the loaded ruleset.
+pub fn tables(anchor: bool) -> Result<Vec<String>> {
+    let output = Command::new("pfctl")
+        .args(anchor_args(anchor))
+        .args(["-s", "Tables"])
+        .output()
+        .context("Failed to execute pfctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to list tables: {stderr}");
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// The addresses currently in a table of the loaded ruleset.
+pub fn table_addresses(table: &str, anchor: bool) -> Result<Vec<String>> {
+    let output = Command::new("pfctl")
+        .args(anchor_args(anchor))
+        .args(["-t", table, "-T", "show"])
+        .output()
+        .context("Failed to execute pfctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to show table <{table}>: {stderr}");
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_portal_with() {
+        use std::cell::RefCell;
+
+        let run = |schedule_ok: bool, load_ok: bool| {
+            let steps = RefCell::new(Vec::new());
+            let step = |name: &'static str, ok: bool| {
+                steps.borrow_mut().push(name);
+                if ok { Ok(()) } else { bail!("{name} failed") }
+            };
+            let result = open_portal_with(
+                || step("schedule", schedule_ok),
+                || step("load", load_ok),
+                || step("close", true),
+            );
+            (result.is_ok(), steps.into_inner())
+        };
+
+        assert_eq!(run(true, true), (true, vec!["schedule", "load"]));
+        // Nothing scheduled: the portal is not opened
+        assert_eq!(run(false, true), (false, vec!["schedule", "close"]));
+        // Portal not loaded: the scheduled restore is cancelled
+        assert_eq!(run(true, false), (false, vec!["schedule", "load", "close"]));
+    }
+
+    #[test]
+    fn test_portal_expired() {
+        let portal = format!("{PORTAL_EXPIRES}1000\npass quick all\n");
+        assert!(!portal_expired(&portal, 999));
+        assert!(portal_expired(&portal, 1000));
+        assert!(portal_expired(&portal, 5000));
+        // Unreadable expiry: restore rather than stay open
+        assert!(portal_expired("pass quick all\n", 0));
+        assert!(portal_expired(&format!("{PORTAL_EXPIRES}soon\n"), 0));
+    }
+
+    #[test]
+    fn test_launchd_job() {
+        let job = launchd_job("/usr/local/bin/killswitch", 120);
+        assert!(job.contains(&format!("<string>{PORTAL_JOB_LABEL}</string>")));
+        assert!(job.contains(
+            "<string>/usr/local/bin/killswitch</string>\n        <string>--status</string>"
+        ));
+        assert!(job.contains("<key>StartInterval</key>\n    <integer>120</integer>"));
+        assert!(!job.contains("RunAtLoad"));
+        assert!(launchd_job("/opt/a&b/killswitch", 1).contains("/opt/a&amp;b/killswitch"));
+    }
+
+    #[test]
+    fn test_references_anchor() {
+        let stock =
+            "scrub-anchor \"com.apple/*\" all fragment reassemble\nanchor \"com.apple/*\" all\n";
+        assert!(!references_anchor(stock));
+        assert!(references_anchor(
+            "scrub-anchor \"com.apple/*\" all fragment reassemble\nanchor \"killswitch\" all\nanchor \"com.apple/*\" all\n"
+        ));
+    }
+
+    #[test]
+    fn test_with_anchor() {
+        let stock = "#
+# Default PF configuration file.
+#
+scrub-anchor \"com.apple/*\"
+nat-anchor \"com.apple/*\"
+rdr-anchor \"com.apple/*\"
+dummynet-anchor \"com.apple/*\"
+anchor \"com.apple/*\"
+load anchor \"com.apple\" from \"/etc/pf.anchors/com.apple\"
+";
+        let main = with_anchor(stock);
+        let killswitch = main.find("anchor \"killswitch\"\n").unwrap_or(usize::MAX);
+        let apple = main.find("\nanchor \"com.apple/*\"").unwrap_or(0);
+        let nat = main.find("nat-anchor").unwrap_or(usize::MAX);
+        assert!(nat < killswitch && killswitch < apple, "{main}");
+        assert_eq!(main.matches(ANCHOR_RULE).count(), 1);
+
+        // No filter rule: last
+        assert_eq!(
+            with_anchor("set skip on lo0\n"),
+            "set skip on lo0\nanchor \"killswitch\"\n"
+        );
+    }
+
+    #[test]
+    fn test_state_remotes() {
+        let states =
+            "en0 tcp 192.168.1.100:52345 -> 17.253.144.10:443       ESTABLISHED:ESTABLISHED
+en0 udp 192.168.1.100:51820 -> 203.0.113.1:51820       MULTIPLE:MULTIPLE
+en0 tcp 192.168.1.100:52346 -> 17.253.144.10:443       FIN_WAIT_2:FIN_WAIT_2
+en0 tcp 2001:db8::5[52400] -> 2606:4700::1111[443]       ESTABLISHED:ESTABLISHED
+en0 tcp 192.168.1.100:22 <- 192.168.1.20:50122       ESTABLISHED:ESTABLISHED
+en0 tcp 192.168.1.100:6000 (10.0.0.2:5000) -> 198.51.100.7:80       SYN_SENT:CLOSED
+No ALTQ support in kernel
+";
+        let remotes: Vec<String> = state_remotes(states)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            remotes,
+            [
+                "17.253.144.10",
+                "203.0.113.1",
+                "2606:4700::1111",
+                "192.168.1.20",
+                "198.51.100.7"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ipv6_passes() {
+        let rules = "block drop all
+pass inet from any to 255.255.255.255 flags S/SA keep state
+pass on en0 inet proto udp from any port 67:68 to any port 67:68 keep state
+pass on en0 inet6 proto ipv6-icmp all icmp6-type neighbrsol keep state
+pass on en0 inet6 proto udp from any port 546:547 to any port 546:547 keep state
+pass on en0 inet6 from fe80::/10 to ff02::/16 flags S/SA keep state
+pass on en0 proto udp from any to <vpn_peers> port = 51820 keep state
+pass on utun3 inet all flags S/SA keep state
+";
+        let tables = |name: &str| match name {
+            "vpn_peers" => vec!["203.0.113.1".to_string()],
+            _ => Vec::new(),
+        };
+        assert!(ipv6_passes(rules, tables).is_empty());
+
+        let tables = |_: &str| vec!["203.0.113.1".to_string(), "2001:db8::1".to_string()];
+        assert_eq!(
+            ipv6_passes(rules, tables),
+            ["pass on en0 proto udp from any to <vpn_peers> port = 51820 keep state"]
+        );
+
+        let rules = "block drop all\npass on utun3 all flags S/SA keep state\n";
+        assert_eq!(
+            ipv6_passes(rules, |_| Vec::new()),
+            ["pass on utun3 all flags S/SA keep state"]
+        );
+    }
+
+    #[test]
+    fn test_inbound_ports() {
+        let rules = "block drop all
+pass on en0 inet from 192.168.1.0/24 to 192.168.1.0/24 flags S/SA keep state
+block drop in on en0 proto tcp all
+pass in on en0 inet proto tcp from 192.168.1.0/24 to any port = ssh flags S/SA keep state
+pass on utun3 inet all flags S/SA keep state
+block drop in on utun3 all
+pass in on utun3 proto udp from any to any port = 51413 keep state
+";
+        assert_eq!(
+            inbound_ports(rules),
+            ["tcp/22 on en0 from 192.168.1.0/24", "udp/51413 on utun3"]
+        );
+        assert!(inbound_ports("block drop all\n").is_empty());
     }
 }
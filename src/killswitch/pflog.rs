@@ -0,0 +1,399 @@
+//! Reader for pflog captures (`tcpdump -i pflog0 -w`).
+//!
+//! A capture is a pcap file whose records start with the pflog header pf
+//! prepends to every logged packet (interface, action, direction, rule),
+//! followed by the IPv4 or IPv6 packet itself. Records are summarized into
+//! the flows the kill switch dropped.
+
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Interface pf logs the packets of `log` rules to
+pub const PFLOG_IFACE: &str = "pflog0";
+
+/// pcap link type of pflog captures (`DLT_PFLOG`)
+const LINKTYPE_PFLOG: u32 = 117;
+
+/// pcap global header and record header lengths
+const PCAP_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+/// Offsets in the pflog header (`struct pfloghdr`)
+const PFLOG_ACTION: usize = 2;
+const PFLOG_IFNAME: usize = 4;
+const IFNAMSIZ: usize = 16;
+const PFLOG_RULENR: usize = 36;
+const PFLOG_DIR: usize = 60;
+
+/// `PF_PASS`, anything else (`PF_DROP`, `PF_SCRUB`, ...) did not go through
+const PF_PASS: u8 = 0;
+
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const IPPROTO_ICMPV6: u8 = 58;
+
+/// Direction of a logged packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    In,
+    Out,
+    Unknown,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::In => write!(f, "in"),
+            Self::Out => write!(f, "out"),
+            Self::Unknown => write!(f, "?"),
+        }
+    }
+}
+
+/// A packet logged by pf
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub interface: String,
+    pub direction: Direction,
+    /// Whether the packet was dropped (any action but pass)
+    pub blocked: bool,
+    /// Number of the matching rule in the loaded ruleset
+    pub rule: u32,
+    /// IP protocol number
+    pub proto: u8,
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    /// Destination port of TCP and UDP packets
+    pub dst_port: Option<u16>,
+}
+
+/// Dropped packets to one destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flow {
+    pub interface: String,
+    pub direction: Direction,
+    pub proto: u8,
+    pub dst: IpAddr,
+    pub dst_port: Option<u16>,
+    /// Number of dropped packets
+    pub packets: usize,
+}
+
+impl fmt::Display for Flow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.dst, self.dst_port) {
+            (IpAddr::V6(dst), Some(port)) => write!(f, "[{dst}]:{port}")?,
+            (dst, Some(port)) => write!(f, "{dst}:{port}")?,
+            (dst, None) => write!(f, "{dst}")?,
+        }
+        write!(
+            f,
+            " {} {} on {}",
+            proto_name(self.proto),
+            self.direction,
+            self.interface
+        )
+    }
+}
+
+/// Name of an IP protocol number
+#[must_use]
+pub fn proto_name(proto: u8) -> String {
+    match proto {
+        IPPROTO_ICMP => "icmp".to_string(),
+        IPPROTO_TCP => "tcp".to_string(),
+        IPPROTO_UDP => "udp".to_string(),
+        IPPROTO_ICMPV6 => "icmp6".to_string(),
+        other => format!("proto {other}"),
+    }
+}
+
+/// Byte order of the pcap headers, given by the magic number
+#[derive(Clone, Copy)]
+struct Pcap {
+    big_endian: bool,
+}
+
+impl Pcap {
+    fn u32_at(self, data: &[u8], offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+}
+
+fn u16_be(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes: [u8; 2] = data.get(offset..offset + 2)?.try_into().ok()?;
+    Some(u16::from_be_bytes(bytes))
+}
+
+fn u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(u32::from_be_bytes(bytes))
+}
+
+/// Parse the records of a pflog pcap capture.
+///
+/// A truncated last record (capture interrupted while writing) is ignored,
+/// as are records that do not carry an IP packet.
+///
+/// # Errors
+/// Returns an error if the data is not a pcap capture of a pflog interface.
+pub fn parse(data: &[u8]) -> Result<Vec<Record>> {
+    let magic = data
+        .get(..4)
+        .ok_or_else(|| anyhow!("Empty or truncated pcap capture"))?;
+    let pcap = match magic {
+        // Microsecond and nanosecond timestamps
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => Pcap { big_endian: false },
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => Pcap { big_endian: true },
+        _ => bail!("Not a pcap capture (pcapng is not supported, use tcpdump -w)"),
+    };
+    let linktype = pcap
+        .u32_at(data, 20)
+        .ok_or_else(|| anyhow!("Truncated pcap header"))?;
+    if linktype != LINKTYPE_PFLOG {
+        bail!("Not a pflog capture (link type {linktype}), capture on {PFLOG_IFACE}");
+    }
+
+    let mut records = Vec::new();
+    let mut offset = PCAP_HEADER_LEN;
+    while let Some(caplen) = pcap.u32_at(data, offset + 8) {
+        let start = offset + RECORD_HEADER_LEN;
+        let Some(packet) = usize::try_from(caplen)
+            .ok()
+            .and_then(|len| data.get(start..start + len))
+        else {
+            break;
+        };
+        records.extend(parse_record(packet));
+        offset = start + packet.len();
+    }
+    Ok(records)
+}
+
+/// Parse a pflog header and the IP packet behind it
+fn parse_record(data: &[u8]) -> Option<Record> {
+    let header_len = usize::from(*data.first()?);
+    // The packet starts at the next 32-bit boundary
+    let packet = data.get(header_len.div_ceil(4) * 4..)?;
+    let ifname = data.get(PFLOG_IFNAME..PFLOG_IFNAME + IFNAMSIZ)?;
+    let interface = String::from_utf8_lossy(ifname)
+        .trim_end_matches('\0')
+        .to_string();
+    let direction = match data.get(PFLOG_DIR)? {
+        1 => Direction::In,
+        2 => Direction::Out,
+        _ => Direction::Unknown,
+    };
+    let (proto, src, dst, dst_port) = parse_ip(packet)?;
+    Some(Record {
+        interface,
+        direction,
+        blocked: *data.get(PFLOG_ACTION)? != PF_PASS,
+        rule: u32_be(data, PFLOG_RULENR)?,
+        proto,
+        src,
+        dst,
+        dst_port,
+    })
+}
+
+/// Protocol, addresses and destination port of an IPv4 or IPv6 packet
+fn parse_ip(packet: &[u8]) -> Option<(u8, IpAddr, IpAddr, Option<u16>)> {
+    let (proto, src, dst, payload) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(packet.first()? & 0x0f) * 4;
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                *packet.get(9)?,
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                packet.get(header_len..)?,
+            )
+        }
+        6 => {
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (
+                *packet.get(6)?,
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                packet.get(40..)?,
+            )
+        }
+        _ => return None,
+    };
+    let dst_port = match proto {
+        IPPROTO_TCP | IPPROTO_UDP => u16_be(payload, 2),
+        _ => None,
+    };
+    Some((proto, src, dst, dst_port))
+}
+
+/// Group the dropped packets by destination, port and interface, most
+/// dropped first.
+#[must_use]
+pub fn summarize(records: &[Record]) -> Vec<Flow> {
+    let mut flows: Vec<Flow> = Vec::new();
+    let mut index: HashMap<(&str, Direction, u8, IpAddr, Option<u16>), usize> = HashMap::new();
+    for record in records.iter().filter(|r| r.blocked) {
+        let key = (
+            record.interface.as_str(),
+            record.direction,
+            record.proto,
+            record.dst,
+            record.dst_port,
+        );
+        if let Some(flow) = index.get(&key).and_then(|&i| flows.get_mut(i)) {
+            flow.packets += 1;
+        } else {
+            index.insert(key, flows.len());
+            flows.push(Flow {
+                interface: record.interface.clone(),
+                direction: record.direction,
+                proto: record.proto,
+                dst: record.dst,
+                dst_port: record.dst_port,
+                packets: 1,
+            });
+        }
+    }
+    // Stable: flows seen first stay first on ties
+    flows.sort_by_key(|flow| std::cmp::Reverse(flow.packets));
+    flows
+}
+
+/// Capture the packets logged on `pflog0` for `duration` with tcpdump.
+///
+/// # Errors
+/// Returns an error if tcpdump cannot be run or fails.
+pub fn capture(duration: Duration) -> Result<Vec<u8>> {
+    // pflog0 only exists once created (pflogd is not running by default);
+    // creating it again fails harmlessly
+    let _ = Command::new("ifconfig")
+        .args([PFLOG_IFACE, "create"])
+        .output();
+
+    let mut child = Command::new("tcpdump")
+        .args(["-n", "-U", "-i", PFLOG_IFACE, "-w", "-"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute tcpdump")?;
+
+    let mut stdout = child
+        .stdout
+        .take()
+        .context("Failed to read tcpdump output")?;
+    // Drain the pipe while capturing so tcpdump never blocks on a full pipe
+    let reader = thread::spawn(move || {
+        let mut data = Vec::new();
+        let _ = stdout.read_to_end(&mut data);
+        data
+    });
+
+    thread::sleep(duration);
+
+    if let Some(status) = child.try_wait().context("Failed to wait for tcpdump")? {
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr);
+            }
+            bail!("Failed to capture on {PFLOG_IFACE}: {}", stderr.trim());
+        }
+    } else {
+        child.kill().context("Failed to stop tcpdump")?;
+        child.wait().context("Failed to wait for tcpdump")?;
+    }
+
+    reader
+        .join()
+        .map_err(|_| anyhow!("Failed to read tcpdump output"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCKED: &[u8] = include_bytes!("../../tests/fixtures/pflog-blocked.pcap");
+    const TRUNCATED: &[u8] = include_bytes!("../../tests/fixtures/pflog-truncated-be.pcap");
+
+    #[allow(clippy::unwrap_used)]
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_parse_records() {
+        let records = parse(BLOCKED).unwrap();
+        assert_eq!(records.len(), 6);
+        let first = records.first().unwrap();
+        assert_eq!(first.interface, "en0");
+        assert_eq!(first.direction, Direction::Out);
+        assert!(first.blocked);
+        assert_eq!(first.rule, 7);
+        assert_eq!(first.proto, IPPROTO_TCP);
+        assert_eq!(first.src, ip("192.168.1.100"));
+        assert_eq!(first.dst, ip("1.1.1.1"));
+        assert_eq!(first.dst_port, Some(443));
+
+        let v6 = records.iter().find(|r| r.dst.is_ipv6()).unwrap();
+        assert_eq!(v6.dst, ip("2001:db8::1"));
+        assert_eq!(v6.dst_port, Some(443));
+        let icmp = records.iter().find(|r| r.proto == IPPROTO_ICMP).unwrap();
+        assert_eq!(icmp.dst_port, None);
+        // The tunnel traffic was passed
+        assert!(records.iter().any(|r| !r.blocked && r.interface == "utun3"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_parse_truncated_big_endian() {
+        let records = parse(TRUNCATED).unwrap();
+        assert_eq!(records.len(), 1);
+        let first = records.first().unwrap();
+        assert_eq!(first.proto, IPPROTO_UDP);
+        assert_eq!(first.dst, ip("8.8.8.8"));
+        assert_eq!(first.dst_port, Some(53));
+        assert_eq!(first.direction, Direction::Out);
+    }
+
+    #[test]
+    fn test_parse_rejects_other_captures() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(b"not a capture at all....").is_err());
+        // Ethernet capture (link type 1)
+        let mut ethernet = BLOCKED.get(..PCAP_HEADER_LEN).unwrap_or_default().to_vec();
+        if let Some(linktype) = ethernet.get_mut(20) {
+            *linktype = 1;
+        }
+        assert!(parse(&ethernet).is_err());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_summarize() {
+        let flows = summarize(&parse(BLOCKED).unwrap());
+        assert_eq!(flows.len(), 3);
+        let top = flows.first().unwrap();
+        assert_eq!(top.packets, 2);
+        assert_eq!(top.to_string(), "1.1.1.1:443 tcp out on en0");
+        let rendered: Vec<String> = flows.iter().map(ToString::to_string).collect();
+        assert!(rendered.contains(&"[2001:db8::1]:443 tcp out on en0".to_string()));
+        assert!(rendered.contains(&"9.9.9.9 icmp out on en0".to_string()));
+    }
+}
@@ -1,116 +1,1433 @@
 use crate::cli::verbosity::Verbosity;
-use crate::killswitch::network;
-use anyhow::{Context, Result};
+use crate::killswitch::cidr::Cidr;
+use crate::killswitch::fragments::{Fragment, Position};
+use crate::killswitch::is_private_addr;
+use crate::killswitch::lint;
+use crate::killswitch::network::InterfaceInfo;
+use crate::killswitch::pf;
+use crate::killswitch::ruleset::{
+    AddressFamily, BlockPolicy, Direction, Endpoint, Host, IcmpType, Macro, Optimization, PfOption,
+    Port, Protocol, Rule, Ruleset, Statement, Table, macro_name,
+};
+use crate::killswitch::snapshot::NetworkSnapshot;
+use crate::killswitch::{BridgePolicy, Deny, Inbound, Options, PeerProto, Preset, Scope};
+use anyhow::{Context, Result, bail};
 use chrono::Local;
-use std::fmt::Write as _;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
 
-pub fn generate(vpn_peer: &str, leak: bool, local: bool, verbose: Verbosity) -> Result<String> {
-    let vpn_peer_ip: IpAddr = vpn_peer.parse().context("Invalid VPN peer IP address")?;
-    let interfaces = network::get_interfaces()?;
+const TCP_UDP: &[Protocol] = &[Protocol::Tcp, Protocol::Udp];
+
+/// pf table holding every VPN peer (server pool)
+pub const PEERS_TABLE: &str = "vpn_peers";
+
+/// pf table holding the destinations allowed outside the tunnel
+pub const ALLOW_TABLE: &str = "allow";
+
+/// pf table holding the resolved addresses of the bypassed domains
+pub const BYPASS_TABLE: &str = "bypass";
+
+/// pf table holding the DNS resolvers reachable outside the tunnel
+pub const DNS_TABLE: &str = "dns_resolvers";
+
+/// pf table holding the local subnets given to `--local`
+pub const LOCAL_TABLE: &str = "local_nets";
+
+/// pf table holding the resolvers DNS is locked to
+pub const DNS_LOCK_TABLE: &str = "dns_lock";
+
+/// pf table holding the networks denied on every interface
+pub const DENY_TABLE: &str = "deny";
+
+/// pf table holding the Tailscale control plane and DERP relays
+pub const TAILSCALE_TABLE: &str = "tailscale";
+
+/// Tailscale `MagicDNS` resolver, reached through the tunnel
+const MAGIC_DNS: &str = "100.100.100.100";
+
+/// Default UDP port of the Tailscale direct (`WireGuard`) paths
+const TAILSCALE_PORT: u16 = 41641;
+
+/// STUN, used by Tailscale to discover its public endpoint
+const STUN_PORT: u16 = 3478;
+
+/// mDNS (Bonjour) group and port
+const MDNS_GROUP: &str = "224.0.0.251";
+const MDNS_PORT: u16 = 5353;
+
+/// SSDP (`UPnP` discovery) group and port
+const SSDP_GROUP: &str = "239.255.255.250";
+const SSDP_PORT: u16 = 1900;
+
+/// IPv6 link-local unicast and link-scope multicast
+const LINK_LOCAL: &str = "fe80::/10";
+const LINK_MULTICAST: &str = "ff02::/16";
+
+/// DNS and DNS over TLS
+const DNS_PORTS: [u16; 2] = [53, 853];
+
+/// ICMP type names known to pf
+const ICMP_TYPES: &[&str] = &[
+    "echorep",
+    "unreach",
+    "squench",
+    "redir",
+    "althost",
+    "echoreq",
+    "routeradv",
+    "routersol",
+    "timex",
+    "paramprob",
+    "timereq",
+    "timerep",
+    "inforeq",
+    "inforep",
+    "maskreq",
+    "maskrep",
+    "trace",
+    "dataconv",
+    "mobredir",
+    "ipv6-where",
+    "ipv6-here",
+    "mobregreq",
+    "mobregrep",
+    "skip",
+    "photuris",
+];
+
+/// Resolved addresses the rules refer to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Targets {
+    /// VPN peers (at least one)
+    pub peers: Vec<IpAddr>,
+    /// Addresses and networks reachable outside the tunnel
+    pub allow: Vec<String>,
+    /// Addresses of the domains reachable outside the tunnel
+    pub bypass: Vec<String>,
+    /// Addresses of the Tailscale control plane and DERP relays
+    pub tailscale: Vec<String>,
+    /// User fragments spliced into the ruleset
+    pub fragments: Vec<Fragment>,
+    /// Accounts allowed outside the tunnel
+    pub exempt: Owners,
+    /// Accounts the kill switch is enforced for (every account when empty)
+    pub only: Owners,
+    /// Ports and networks blocked on every interface
+    pub deny: Vec<Deny>,
+}
+
+/// Resolved local accounts, matched by pf on the owner of the socket.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Owners {
+    pub users: Vec<u32>,
+    pub groups: Vec<u32>,
+}
+
+impl Owners {
+    /// Whether no account is listed.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.groups.is_empty()
+    }
+
+    /// One rule per non-empty list, matching its UIDs or GIDs
+    fn rules(&self, rule: &Rule) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        if !self.users.is_empty() {
+            rules.push(rule.clone().user(&self.users));
+        }
+        if !self.groups.is_empty() {
+            rules.push(rule.clone().group(&self.groups));
+        }
+        rules
+    }
+}
+
+impl Targets {
+    /// Tables of destinations reachable outside the tunnel, with the comment
+    /// of their rules; empty tables are left out
+    fn outside_tables(&self) -> Vec<(&'static str, &[String], &'static str)> {
+        [
+            (
+                ALLOW_TABLE,
+                self.allow.as_slice(),
+                "Allow destinations outside the vpn",
+            ),
+            (
+                BYPASS_TABLE,
+                self.bypass.as_slice(),
+                "Allow bypassed domains outside the vpn",
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, addrs, _)| !addrs.is_empty())
+        .collect()
+    }
+}
+
+/// Build the kill switch ruleset for the interfaces in `snapshot`.
+///
+/// Generation is pure: everything it needs comes from the snapshot, so it
+/// does not depend on the machine it runs on.
+pub fn generate(
+    snapshot: &NetworkSnapshot,
+    targets: &Targets,
+    options: &Options,
+    verbose: Verbosity,
+) -> Result<Ruleset> {
+    let peers = &targets.peers;
+    if peers.is_empty() {
+        bail!("At least one VPN peer is required");
+    }
+    let icmp = icmp_types(options)?;
+    let local = local_networks(snapshot, options)?;
+    let (interfaces, bridges) = select_interfaces(snapshot, options);
+
+    check_interfaces(options, &interfaces, &local)?;
+    check_macro_names(&interfaces, &bridges)?;
+    check_deny(snapshot, targets, options)?;
 
     if verbose.is_debug() {
-        eprintln!("  VPN gateway: {vpn_peer_ip}");
-        eprintln!("  Leak mode: {leak}");
-        eprintln!("  Local network: {local}");
+        for peer in peers {
+            eprintln!("  VPN gateway: {peer}");
+        }
+        eprintln!("  DNS outside the VPN: {:?}", options.allow_dns);
+        eprintln!("  ICMP outside the VPN: {:?}", options.allow_icmp);
+        eprintln!("  Local network: {local:?}");
     }
 
-    let sep = "-".repeat(62);
-    let mut rules = String::new();
-
-    // Header
-    writeln!(rules, "# {sep}")?;
-    writeln!(
-        rules,
-        "# {}",
-        Local::now().format("%a, %d %b %Y %H:%M:%S %z")
-    )?;
-    rules.push_str("# sudo pfctl -Fa -f /tmp/killswitch.pf.conf -e\n");
-    writeln!(rules, "# {sep}")?;
+    let mut rules = Ruleset::new();
+    push_header(&mut rules, options.anchor);
 
     // Interface macros
     for iface in &interfaces {
-        if iface.is_p2p() {
-            writeln!(rules, "vpn_{} = \"{}\"", iface.name(), iface.name())?;
-        } else {
-            writeln!(rules, "int_{} = \"{}\"", iface.name(), iface.name())?;
-        }
+        let prefix = if iface.is_p2p() { "vpn" } else { "int" };
+        rules.push(Macro::interface(prefix, iface.name()));
     }
-    writeln!(rules, "vpn_ip = \"{vpn_peer_ip}\"")?;
-    rules.push('\n');
+    for iface in &bridges {
+        rules.push(Macro::interface("br", iface.name()));
+    }
+    push_tables(&mut rules, snapshot, targets, options, &local, &interfaces)?;
+    check_fragments(&rules, &targets.fragments, options.unsafe_fragments)?;
+    rules.blank();
 
-    // Global settings
-    rules.push_str("set block-policy drop\n");
-    rules.push_str("set ruleset-optimization basic\n");
-    rules.push_str("set skip on lo0\n");
-    rules.push('\n');
+    // Global settings, ignored by pfctl inside an anchor
+    if options.anchor {
+        rules.push(Rule::pass().quick().on_interface("lo0"));
+    } else {
+        rules.push(PfOption::BlockPolicy(BlockPolicy::Drop));
+        rules.push(PfOption::RulesetOptimization(Optimization::Basic));
+        rules.push(PfOption::Skip("lo0".to_string()));
+    }
+    rules.blank();
+
+    push_fragments(&mut rules, &targets.fragments, Position::BeforeBlock);
 
     // Block all
-    rules.push_str("block all\n");
-    rules.push_str("block out inet6\n");
-    rules.push('\n');
+    rules.push(block(options.log));
+    rules.blank();
+
+    push_deny(&mut rules, &targets.deny, options.log);
 
-    // DNS
-    if leak {
-        rules.push_str("# dns\n");
-        rules.push_str("pass quick proto {tcp, udp} from any to any port 53 keep state\n");
-        rules.push('\n');
+    // Quick, ahead of the DNS lock
+    if options.portal {
+        for iface in interfaces.iter().filter(|i| !i.is_p2p()) {
+            push_portal(&mut rules, iface.name());
+        }
     }
 
-    // Broadcast
-    rules.push_str("# Allow broadcasts on internal interface\n");
-    rules.push_str("pass from any to 255.255.255.255 keep state\n");
-    rules.push_str("pass from 255.255.255.255 to any keep state\n");
-    rules.push('\n');
+    let tailscale = options.preset == Some(Preset::Tailscale);
+    if tailscale {
+        push_magic_dns(&mut rules, &interfaces);
+    }
+    push_dns(&mut rules, options);
+    if options.dns_lock {
+        push_dns_lock(&mut rules, &interfaces, options.log);
+    }
 
-    // Multicast
-    rules.push_str("# Allow multicast\n");
-    rules.push_str("pass proto udp from any to 224.0.0.0/4 keep state\n");
-    rules.push_str("pass proto udp from 224.0.0.0/4 to any keep state\n");
-    rules.push('\n');
+    let tunnel = tunnel_endpoint(snapshot, options);
 
     // Per physical interface rules
     for iface in interfaces.iter().filter(|i| !i.is_p2p()) {
-        if leak {
-            writeln!(
-                rules,
-                "# Allow ping\npass on $int_{} inet proto icmp all icmp-type 8 code 0 keep state",
-                iface.name()
-            )?;
-            rules.push('\n');
-        }
-        writeln!(
-            rules,
-            "# Allow dhcp\npass on $int_{} proto {{tcp,udp}} from any port 67:68 to any port 67:68 keep state",
-            iface.name()
-        )?;
-        rules.push('\n');
-        if local {
-            writeln!(
-                rules,
-                "pass from $int_{0}:network to $int_{0}:network",
+        push_owners(&mut rules, iface.name(), targets, options.log);
+        let icmp = icmp.as_deref().filter(|_| {
+            options.icmp_ifaces.is_empty() || options.icmp_ifaces.iter().any(|i| i == iface.name())
+        });
+        push_physical(
+            &mut rules,
+            iface.name(),
+            peers,
+            &tunnel,
+            icmp,
+            local == LocalNets::Interfaces,
+        );
+        if let LocalNets::Table(_) = local {
+            push_local_table(&mut rules, iface.name());
+        }
+        if local != LocalNets::Blocked {
+            push_link_local(&mut rules, iface.name());
+        }
+        if tailscale {
+            push_tailscale(&mut rules, iface.name());
+        }
+        for (table, _, comment) in targets.outside_tables() {
+            rules.comment(comment);
+            rules.push(
+                Rule::pass()
+                    .direction(Direction::Out)
+                    .on(&macro_name("int", iface.name()))
+                    .to(Host::Table(table.to_string()))
+                    .keep_state(),
+            );
+        }
+        push_inbound(&mut rules, iface, options, &local);
+    }
+
+    // After the inbound blocks, which would otherwise win over the replies
+    push_broadcast_multicast(&mut rules, &interfaces, options)?;
+
+    push_bridges(&mut rules, &bridges, &interfaces, options.bridges)?;
+    push_fragments(&mut rules, &targets.fragments, Position::AfterExemptions);
+
+    push_tunnel(&mut rules, snapshot, &interfaces, options);
+    push_fragments(&mut rules, &targets.fragments, Position::AfterTunnel);
+
+    if options.anchor {
+        return Ok(first_match(&rules));
+    }
+    Ok(rules)
+}
+
+/// States to kill when the rules are loaded into the anchor: those of the
+/// physical interfaces, except to the peers and the destinations allowed
+/// outside the tunnel
+#[must_use]
+pub fn stale_states(rules: &Ruleset) -> pf::StaleStates {
+    let interfaces = rules
+        .macros()
+        .filter(|m| m.name.starts_with("int_"))
+        .map(|m| m.value.clone())
+        .collect();
+    let keep = rules
+        .tables()
+        .filter(|t| t.name != DENY_TABLE && t.name != DNS_LOCK_TABLE)
+        .flat_map(|t| &t.addresses)
+        .filter_map(|addr| addr.parse::<Cidr>().ok())
+        .collect();
+    pf::StaleStates { interfaces, keep }
+}
+
+/// Make every rule `quick`, so the rules of the main ruleset evaluated after
+/// the anchor cannot override the kill switch.
+///
+/// The rules are reordered to keep the outcome of the last-match order they
+/// were generated in: the `quick` rules first, as they were, then the others
+/// in reverse order. Macros and tables of the fragments move above the rules.
+fn first_match(rules: &Ruleset) -> Ruleset {
+    let statements = rules.statements();
+    let start = statements
+        .iter()
+        .position(|s| matches!(s, Statement::Rule(_)))
+        .unwrap_or(statements.len());
+    let (head, body) = statements.split_at(start);
+    let mut definitions = Vec::new();
+    // Rules under their comments, split at blank lines
+    let mut groups: Vec<(Vec<Statement>, Vec<Rule>)> = vec![(Vec::new(), Vec::new())];
+    for statement in body {
+        let Some((comments, group)) = groups.last_mut() else {
+            continue;
+        };
+        match statement {
+            Statement::Rule(rule) => group.push(rule.clone()),
+            Statement::Comment(_) if group.is_empty() => comments.push(statement.clone()),
+            Statement::Comment(_) => groups.push((vec![statement.clone()], Vec::new())),
+            Statement::Blank if comments.is_empty() && group.is_empty() => {}
+            Statement::Blank => groups.push((Vec::new(), Vec::new())),
+            _ => definitions.push(statement.clone()),
+        }
+    }
+
+    let mut ordered = Ruleset::new();
+    for statement in head.iter().chain(&definitions) {
+        ordered.push(statement.clone());
+    }
+    if !definitions.is_empty() {
+        ordered.blank();
+    }
+    let mut push_group = |comments: &[Statement], group: Vec<Rule>| {
+        if group.is_empty() {
+            return;
+        }
+        for comment in comments {
+            ordered.push(comment.clone());
+        }
+        for mut rule in group {
+            rule.quick = true;
+            ordered.push(rule);
+        }
+        ordered.blank();
+    };
+    for (comments, group) in &groups {
+        push_group(
+            comments,
+            group.iter().filter(|r| r.quick).cloned().collect(),
+        );
+    }
+    for (comments, group) in groups.iter().rev() {
+        push_group(
+            comments,
+            group.iter().rev().filter(|r| !r.quick).cloned().collect(),
+        );
+    }
+    ordered
+}
+
+/// Reject fragments using undefined macros or `set` options, and, unless
+/// `physical`, passing traffic on the physical interfaces
+fn check_fragments(rules: &Ruleset, fragments: &[Fragment], physical: bool) -> Result<()> {
+    let mut errors = Vec::new();
+    for fragment in fragments {
+        for diagnostic in lint::check_fragment(&fragment.parsed, rules, physical) {
+            errors.push(format!("{}: {diagnostic}", fragment.name));
+        }
+    }
+    if !errors.is_empty() {
+        bail!(
+            "Invalid fragments (--unsafe-fragments allows pass rules on physical interfaces):\n  {}",
+            errors.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Splice the fragments of a position, each under a comment naming it
+fn push_fragments(rules: &mut Ruleset, fragments: &[Fragment], position: Position) {
+    for fragment in fragments.iter().filter(|f| f.position == position) {
+        rules.comment(format!("Fragment {}", fragment.name));
+        for statement in fragment.parsed.ruleset.statements() {
+            rules.push(statement.clone());
+        }
+        rules.blank();
+    }
+}
+
+/// Rules by socket owner, TCP and UDP only: with `--only-user` every other
+/// account passes and the listed ones fall back to the kill switch rules
+/// that follow; with `--exempt-user` the listed accounts pass
+fn push_owners(rules: &mut Ruleset, name: &str, targets: &Targets, log: bool) {
+    let int = macro_name("int", name);
+    let out = |rule: Rule| rule.direction(Direction::Out).on(&int).proto(TCP_UDP);
+    if !targets.only.is_empty() {
+        rules.comment("Kill switch only for the listed accounts");
+        rules.push(out(Rule::pass()).keep_state());
+        for rule in targets.only.rules(&out(block(log))) {
+            rules.push(rule);
+        }
+        rules.blank();
+    }
+    if !targets.exempt.is_empty() {
+        rules.comment("Allow exempt accounts outside the vpn");
+        for rule in targets.exempt.rules(&out(Rule::pass()).keep_state()) {
+            rules.push(rule);
+        }
+        rules.blank();
+    }
+}
+
+/// Interfaces named by `--icmp-iface` and `--inbound` must have rules, and
+/// inbound ports on a physical interface need the local network
+fn check_interfaces(
+    options: &Options,
+    interfaces: &[InterfaceInfo],
+    local: &LocalNets,
+) -> Result<()> {
+    for name in &options.icmp_ifaces {
+        if !interfaces.iter().any(|i| i.name() == name) {
+            bail!("Unknown interface for ICMP: {name}");
+        }
+    }
+    for inbound in &options.inbound {
+        let Some(name) = &inbound.iface else {
+            continue;
+        };
+        let Some(iface) = interfaces.iter().find(|i| i.name() == name) else {
+            bail!("Unknown interface for inbound port {inbound}: {name}");
+        };
+        if !iface.is_p2p() && *local == LocalNets::Blocked {
+            bail!("Inbound port {inbound} on the physical interface {name} requires --local");
+        }
+    }
+    Ok(())
+}
+
+/// Deny rules are `quick` ahead of the peer rules: one matching the peer
+/// endpoint would cut off the tunnel itself
+fn check_deny(snapshot: &NetworkSnapshot, targets: &Targets, options: &Options) -> Result<()> {
+    let peer_port = options.peer_port.or(snapshot.peer_port);
+    let peer_proto = options.peer_proto.or(snapshot.peer_proto);
+    for deny in &targets.deny {
+        match deny {
+            Deny::Port { proto, port } => {
+                let same_proto = proto.is_none() || peer_proto.is_none() || *proto == peer_proto;
+                if peer_port == Some(*port) && same_proto {
+                    bail!("--deny {deny} would block the VPN peer port {port}");
+                }
+            }
+            Deny::Network(network) => {
+                let net: Cidr = network.parse()?;
+                if let Some(peer) = targets.peers.iter().find(|peer| net.contains(peer)) {
+                    bail!("--deny {deny} would block the VPN peer {peer}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Interfaces whose names only differ by characters pf identifiers cannot
+/// hold (`vlan.2` and `vlan_2`) would share a macro, the second overriding
+/// the first
+fn check_macro_names(interfaces: &[InterfaceInfo], bridges: &[InterfaceInfo]) -> Result<()> {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    let named = interfaces
+        .iter()
+        .map(|i| (if i.is_p2p() { "vpn" } else { "int" }, i))
+        .chain(bridges.iter().map(|i| ("br", i)));
+    for (prefix, iface) in named {
+        let name = macro_name(prefix, iface.name());
+        if let Some(other) = seen.insert(name.clone(), iface.name()) {
+            bail!(
+                "Interfaces {other} and {} both map to the pf macro ${name}",
                 iface.name()
-            )?;
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Ports of `--inbound` open on an interface, every VPN interface when the
+/// port names none
+fn inbound_ports<'a>(
+    options: &'a Options,
+    iface: &'a InterfaceInfo,
+) -> impl Iterator<Item = &'a Inbound> {
+    options
+        .inbound
+        .iter()
+        .filter(move |inbound| match &inbound.iface {
+            Some(name) => name == iface.name(),
+            None => iface.is_p2p(),
+        })
+}
+
+/// The pass rule of an inbound port
+fn inbound_rule(inbound: &Inbound, int: &str, from: Host) -> Rule {
+    let proto = match inbound.proto {
+        PeerProto::Tcp => Protocol::Tcp,
+        PeerProto::Udp => Protocol::Udp,
+    };
+    Rule::pass()
+        .direction(Direction::In)
+        .on(int)
+        .proto(&[proto])
+        .from(from)
+        .to(Endpoint {
+            host: Host::Any,
+            port: Some(Port::Single(inbound.port)),
+        })
+        .keep_state()
+}
+
+/// Inbound traffic on a physical interface: blocked, except the open ports
+/// from the local network and the answers no state matches (DHCP, IPv6
+/// neighbor discovery and `DHCPv6`, mDNS with `--local`)
+fn push_inbound(rules: &mut Ruleset, iface: &InterfaceInfo, options: &Options, local: &LocalNets) {
+    let int = macro_name("int", iface.name());
+    let from = match local {
+        LocalNets::Blocked => Host::Any,
+        LocalNets::Interfaces => Host::Network(int.clone()),
+        LocalNets::Table(_) => Host::Table(LOCAL_TABLE.to_string()),
+    };
+    let port = |port| Endpoint {
+        host: Host::Any,
+        port: Some(Port::Single(port)),
+    };
+    rules.comment("Block inbound connections, except the open ports");
+    rules.push(block(options.log).direction(Direction::In).on(&int));
+    rules.push(
+        Rule::pass()
+            .direction(Direction::In)
+            .on(&int)
+            .family(AddressFamily::Inet)
+            .proto(&[Protocol::Udp])
+            .from(port(67))
+            .to(port(68))
+            .keep_state(),
+    );
+    rules.push(
+        Rule::pass()
+            .direction(Direction::In)
+            .on(&int)
+            .family(AddressFamily::Inet6)
+            .proto(&[Protocol::Icmp6])
+            .icmp_type("neighbrsol", None)
+            .icmp_type("neighbradv", None)
+            .icmp_type("routeradv", None)
+            .keep_state(),
+    );
+    rules.push(
+        Rule::pass()
+            .direction(Direction::In)
+            .on(&int)
+            .family(AddressFamily::Inet6)
+            .proto(&[Protocol::Udp])
+            .from(port(547))
+            .to(port(546))
+            .keep_state(),
+    );
+    for inbound in inbound_ports(options, iface) {
+        rules.push(inbound_rule(inbound, &int, from.clone()));
+    }
+    rules.blank();
+}
+
+/// Denied networks and ports, `quick` ahead of every pass rule so they
+/// apply through the tunnel too
+fn push_deny(rules: &mut Ruleset, deny: &[Deny], log: bool) {
+    if deny.is_empty() {
+        return;
+    }
+    rules.comment("Denied on every interface, the tunnel included");
+    let rule = || block(log).direction(Direction::Out).quick();
+    if deny.iter().any(|deny| matches!(deny, Deny::Network(_))) {
+        rules.push(rule().to(Host::Table(DENY_TABLE.to_string())));
+    }
+    let protocols = [
+        (None, TCP_UDP),
+        (Some(PeerProto::Tcp), &[Protocol::Tcp][..]),
+        (Some(PeerProto::Udp), &[Protocol::Udp][..]),
+    ];
+    for (proto, protocols) in protocols {
+        let ports: Vec<u16> = deny
+            .iter()
+            .filter_map(|deny| match deny {
+                Deny::Port { proto: p, port } if *p == proto => Some(*port),
+                _ => None,
+            })
+            .collect();
+        let port = match ports.as_slice() {
+            [] => continue,
+            [port] => Port::Single(*port),
+            _ => Port::List(ports),
+        };
+        rules.push(rule().proto(protocols).to(Endpoint {
+            host: Host::Any,
+            port: Some(port),
+        }));
+    }
+    rules.blank();
+}
+
+/// VPN interface pass-all, IPv6 only when the tunnel carries it, then
+/// inbound connections blocked except the open ports
+fn push_tunnel(
+    rules: &mut Ruleset,
+    snapshot: &NetworkSnapshot,
+    interfaces: &[InterfaceInfo],
+    options: &Options,
+) {
+    for iface in interfaces.iter().filter(|i| i.is_p2p()) {
+        let vpn = macro_name("vpn", iface.name());
+        let rule = Rule::pass().on(&vpn);
+        let ipv6 = snapshot
+            .interfaces
+            .iter()
+            .any(|i| i.name() == iface.name() && i.is_ipv6());
+        rules.push(if ipv6 {
+            rule
+        } else {
+            rule.family(AddressFamily::Inet)
+        });
+        rules.push(block(options.log).direction(Direction::In).on(&vpn));
+        for inbound in inbound_ports(options, iface) {
+            rules.push(inbound_rule(inbound, &vpn, Host::Any));
+        }
+    }
+}
+
+/// Tables of the peers, the destinations outside the tunnel and the
+/// resolvers
+fn push_tables(
+    rules: &mut Ruleset,
+    snapshot: &NetworkSnapshot,
+    targets: &Targets,
+    options: &Options,
+    local: &LocalNets,
+    interfaces: &[InterfaceInfo],
+) -> Result<()> {
+    rules.push(Table::persist(
+        PEERS_TABLE,
+        targets.peers.iter().map(ToString::to_string).collect(),
+    ));
+    for (table, addrs, _) in targets.outside_tables() {
+        rules.push(Table::persist(table, addrs.to_vec()));
+    }
+    let resolvers = dns_resolvers(options)?;
+    if !resolvers.is_empty() {
+        rules.push(Table::persist(DNS_TABLE, resolvers));
+    }
+    if let LocalNets::Table(nets) = local {
+        rules.push(Table::persist(LOCAL_TABLE, nets.clone()));
+    }
+    let denied: Vec<String> = targets
+        .deny
+        .iter()
+        .filter_map(|deny| match deny {
+            Deny::Network(network) => Some(network.clone()),
+            Deny::Port { .. } => None,
+        })
+        .collect();
+    if !denied.is_empty() {
+        rules.push(Table::persist(DENY_TABLE, denied));
+    }
+    if options.preset == Some(Preset::Tailscale) {
+        if targets.tailscale.is_empty() {
+            bail!("The tailscale preset requires the Tailscale control plane or DERP relays");
+        }
+        rules.push(Table::persist(TAILSCALE_TABLE, targets.tailscale.clone()));
+    }
+    if options.dns_lock {
+        let locked = locked_resolvers(snapshot);
+        if locked.is_empty() {
+            bail!("No DNS resolver found to lock DNS to");
+        }
+        if !interfaces.iter().any(InterfaceInfo::is_p2p) {
+            bail!("DNS lock requires a VPN interface");
+        }
+        rules.push(Table::persist(DNS_LOCK_TABLE, locked));
+    }
+    Ok(())
+}
+
+/// Virtualization bridges and Apple's peer-to-peer links (`AirDrop`)
+const BRIDGE_IFACES: &[&str] = &["bridge*", "vmnet*", "awdl*", "llw*"];
+
+/// Match an interface name against a glob pattern (`*` and `?`)
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Position after the last `*` and the name position it matched up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match (pattern.get(p), name.get(n)) {
+            (Some('*'), _) => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            (Some('?'), _) => (p, n) = (p + 1, n + 1),
+            (Some(c), Some(m)) if c == m => (p, n) = (p + 1, n + 1),
+            _ => match star {
+                Some((after, matched)) => {
+                    star = Some((after, matched + 1));
+                    (p, n) = (after, matched + 1);
+                }
+                None => return false,
+            },
         }
-        writeln!(
-            rules,
-            "# use only the vpn\npass on $int_{} proto {{tcp, udp}} from any to $vpn_ip",
-            iface.name()
-        )?;
     }
+    pattern
+        .get(p..)
+        .is_some_and(|rest| rest.iter().all(|&c| c == '*'))
+}
+
+/// Interfaces to generate rules for (physical and VPN) and the bridges,
+/// after `--only-iface` and `--ignore-iface`
+fn select_interfaces(
+    snapshot: &NetworkSnapshot,
+    options: &Options,
+) -> (Vec<InterfaceInfo>, Vec<InterfaceInfo>) {
+    let mut interfaces = snapshot.interfaces.clone();
+    // An interface with several addresses is reported once per address, not
+    // necessarily next to each other
+    let mut seen = HashSet::new();
+    interfaces.retain(|iface| seen.insert(iface.name().to_string()));
+    interfaces.retain(|iface| {
+        let matches = |patterns: &[String]| patterns.iter().any(|p| glob_match(p, iface.name()));
+        (options.only_ifaces.is_empty() || matches(&options.only_ifaces))
+            && !matches(&options.ignore_ifaces)
+    });
+    interfaces.into_iter().partition(|iface| {
+        iface.is_p2p() || !BRIDGE_IFACES.iter().any(|p| glob_match(p, iface.name()))
+    })
+}
 
-    // VPN interface pass-all
+/// Rules of the virtualization bridges for their policy
+fn push_bridges(
+    rules: &mut Ruleset,
+    bridges: &[InterfaceInfo],
+    interfaces: &[InterfaceInfo],
+    policy: BridgePolicy,
+) -> Result<()> {
+    if bridges.is_empty() || policy == BridgePolicy::Block {
+        return Ok(());
+    }
+    let vpn = interfaces.iter().find(|i| i.is_p2p());
+    for iface in bridges {
+        let br = macro_name("br", iface.name());
+        rules.comment(format!("Bridge {} ({policy})", iface.name()));
+        match policy {
+            BridgePolicy::Block => {}
+            BridgePolicy::Pass => rules.push(Rule::pass().on(&br)),
+            BridgePolicy::Tunnel => {
+                let Some(vpn) = vpn else {
+                    bail!("Routing bridges through the tunnel requires a VPN interface");
+                };
+                rules.push(
+                    Rule::pass()
+                        .direction(Direction::In)
+                        .on(&br)
+                        .route_to(&macro_name("vpn", vpn.name()))
+                        .from(Host::Network(br.clone()))
+                        .keep_state(),
+                );
+                // Last match: traffic between the host and its guests stays local
+                rules.push(
+                    Rule::pass()
+                        .on(&br)
+                        .from(Host::Network(br.clone()))
+                        .to(Host::Network(br.clone()))
+                        .keep_state(),
+                );
+            }
+        }
+        rules.blank();
+    }
+    Ok(())
+}
+
+/// A block rule, logged to `pflog0` with `--log`
+fn block(log: bool) -> Rule {
+    if log {
+        Rule::block().log()
+    } else {
+        Rule::block()
+    }
+}
+
+fn push_header(rules: &mut Ruleset, anchor: bool) {
+    let sep = "-".repeat(62);
+    rules.comment(sep.clone());
+    rules.comment(Local::now().format("%a, %d %b %Y %H:%M:%S %z").to_string());
+    if anchor {
+        rules.comment(format!(
+            "sudo pfctl -a {} -f /tmp/killswitch.pf.conf -e",
+            pf::ANCHOR
+        ));
+    } else {
+        rules.comment("sudo pfctl -Fa -f /tmp/killswitch.pf.conf -e");
+    }
+    rules.comment(sep);
+}
+
+/// Local traffic allowed by `--local`
+#[derive(Debug, PartialEq, Eq)]
+enum LocalNets {
+    Blocked,
+    /// The `:network` of each interface
+    Interfaces,
+    /// Explicit subnets (pf table entries, `!addr` excluded)
+    Table(Vec<String>),
+}
+
+/// Default gateways from the routing table
+fn default_gateways(snapshot: &NetworkSnapshot) -> Vec<IpAddr> {
+    snapshot
+        .routes
+        .iter()
+        .filter(|r| r.destination == "default")
+        .filter_map(|r| r.gateway.parse().ok())
+        .collect()
+}
+
+/// Validate the `--local` subnets against the interfaces' prefixes
+///
+/// A subnet is accepted when it overlaps the network of a physical interface
+/// or, for routed LAN segments, when it only spans private addresses.
+fn local_networks(snapshot: &NetworkSnapshot, options: &Options) -> Result<LocalNets> {
+    let Some(subnets) = &options.local else {
+        return Ok(LocalNets::Blocked);
+    };
+    if subnets.is_empty() && !options.exclude_gateway {
+        return Ok(LocalNets::Interfaces);
+    }
+
+    let prefixes: Vec<Cidr> = snapshot
+        .interfaces
+        .iter()
+        .filter(|i| !i.is_p2p())
+        .filter_map(|i| i.ip().parse::<Cidr>().ok())
+        .map(|c| c.network())
+        .collect();
+
+    let mut nets: Vec<Cidr> = Vec::new();
+    let mut excluded: Vec<IpAddr> = Vec::new();
+    for subnet in subnets {
+        if let Some(addr) = subnet.strip_prefix('!') {
+            excluded.push(
+                addr.parse()
+                    .with_context(|| format!("Invalid excluded address: {subnet}"))?,
+            );
+            continue;
+        }
+        let net: Cidr = subnet.parse()?;
+        let on_link = prefixes.iter().any(|p| p.overlaps(&net));
+        let private = is_private_addr(&net.network().addr()) && is_private_addr(&net.last());
+        if !on_link && !private {
+            bail!(
+                "{subnet} is not a local network: outside every interface prefix and not private"
+            );
+        }
+        nets.push(net.network());
+    }
+    if nets.is_empty() {
+        // Only exclusions given: the networks of the interfaces
+        nets.clone_from(&prefixes);
+    }
+    if options.exclude_gateway {
+        excluded.extend(default_gateways(snapshot));
+    }
+
+    let mut entries: Vec<String> = nets.iter().map(ToString::to_string).collect();
+    for addr in excluded {
+        let entry = format!("!{addr}");
+        if nets.iter().any(|n| n.contains(&addr)) && !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+    Ok(LocalNets::Table(entries))
+}
+
+/// Entries of the `<local_nets>` table for explicit `--local` subnets
+///
+/// # Errors
+/// Returns an error if no subnet is given or a subnet is not local.
+pub fn local_table(snapshot: &NetworkSnapshot, options: &Options) -> Result<Vec<String>> {
+    match local_networks(snapshot, options)? {
+        LocalNets::Table(entries) => Ok(entries),
+        LocalNets::Blocked | LocalNets::Interfaces => bail!("No local subnet given"),
+    }
+}
+
+fn push_local_table(rules: &mut Ruleset, name: &str) {
+    let int = macro_name("int", name);
+    let local = Host::Table(LOCAL_TABLE.to_string());
+    rules.comment("Allow local networks");
+    // Inbound connections are blocked, see push_inbound
+    rules.push(
+        Rule::pass()
+            .direction(Direction::Out)
+            .on(&int)
+            .to(local)
+            .keep_state(),
+    );
+}
+
+/// Validate the DNS resolvers allowed outside the tunnel
+fn dns_resolvers(options: &Options) -> Result<Vec<String>> {
+    let mut resolvers = Vec::new();
+    for resolver in options.allow_dns.iter().flatten() {
+        let addr: IpAddr = resolver
+            .parse()
+            .with_context(|| format!("Invalid DNS resolver: {resolver}"))?;
+        resolvers.push(addr.to_string());
+    }
+    Ok(resolvers)
+}
+
+/// Validate the ICMP types allowed outside the tunnel, echo request when
+/// none is given
+fn icmp_types(options: &Options) -> Result<Option<Vec<IcmpType>>> {
+    let Some(types) = &options.allow_icmp else {
+        return Ok(None);
+    };
+    if types.is_empty() {
+        return Ok(Some(vec![IcmpType {
+            name: "8".to_string(),
+            code: Some(0),
+        }]));
+    }
+
+    let mut icmp = Vec::new();
+    for name in types {
+        if !ICMP_TYPES.contains(&name.as_str()) && name.parse::<u8>().is_err() {
+            bail!("Unknown ICMP type: {name}");
+        }
+        icmp.push(IcmpType {
+            name: name.clone(),
+            code: None,
+        });
+    }
+    Ok(Some(icmp))
+}
+
+fn push_dns(rules: &mut Ruleset, options: &Options) {
+    let Some(resolvers) = &options.allow_dns else {
+        return;
+    };
+    let host = if resolvers.is_empty() {
+        Host::Any
+    } else {
+        Host::Table(DNS_TABLE.to_string())
+    };
+    rules.comment("dns");
+    rules.push(
+        Rule::pass()
+            .quick()
+            .proto(TCP_UDP)
+            .to(Endpoint {
+                host,
+                port: Some(Port::Single(53)),
+            })
+            .keep_state(),
+    );
+    rules.blank();
+}
+
+/// Resolvers pushed by the VPN: those scoped to a tunnel interface, or every
+/// configured resolver except loopback stubs when none is scoped
+#[must_use]
+pub fn locked_resolvers(snapshot: &NetworkSnapshot) -> Vec<String> {
+    let is_tunnel = |name: &str| {
+        snapshot
+            .interfaces
+            .iter()
+            .any(|i| i.is_p2p() && i.name() == name)
+    };
+    let usable: Vec<IpAddr> = snapshot
+        .dns
+        .iter()
+        .filter_map(|r| r.address.parse::<IpAddr>().ok())
+        .filter(|addr| !addr.is_loopback())
+        .collect();
+    let pushed: Vec<IpAddr> = snapshot
+        .dns
+        .iter()
+        .filter(|r| r.interface.as_deref().is_some_and(is_tunnel))
+        .filter_map(|r| r.address.parse::<IpAddr>().ok())
+        .collect();
+
+    let mut locked: Vec<String> = Vec::new();
+    for addr in if pushed.is_empty() { usable } else { pushed } {
+        let addr = addr.to_string();
+        if !locked.contains(&addr) {
+            locked.push(addr);
+        }
+    }
+    locked
+}
+
+fn push_dns_lock(rules: &mut Ruleset, interfaces: &[InterfaceInfo], log: bool) {
+    let dns = |host| Endpoint {
+        host,
+        port: Some(Port::List(DNS_PORTS.to_vec())),
+    };
+    rules.comment("Lock dns to the vpn resolvers");
     for iface in interfaces.iter().filter(|i| i.is_p2p()) {
-        writeln!(rules, "pass on $vpn_{} all", iface.name())?;
+        rules.push(
+            Rule::pass()
+                .quick()
+                .on(&macro_name("vpn", iface.name()))
+                .proto(TCP_UDP)
+                .to(dns(Host::Table(DNS_LOCK_TABLE.to_string())))
+                .keep_state(),
+        );
     }
+    rules.push(block(log).quick().proto(TCP_UDP).to(dns(Host::Any)));
+    rules.blank();
+}
 
-    Ok(rules)
+/// `MagicDNS` through the tunnel, quick so the DNS lock does not catch it
+fn push_magic_dns(rules: &mut Ruleset, interfaces: &[InterfaceInfo]) {
+    let vpns: Vec<&InterfaceInfo> = interfaces.iter().filter(|i| i.is_p2p()).collect();
+    if vpns.is_empty() {
+        return;
+    }
+    rules.comment("Allow tailscale magicdns");
+    for iface in vpns {
+        rules.push(
+            Rule::pass()
+                .quick()
+                .on(&macro_name("vpn", iface.name()))
+                .proto(TCP_UDP)
+                .to(Endpoint {
+                    host: Host::Address(MAGIC_DNS.to_string()),
+                    port: Some(Port::Single(53)),
+                })
+                .keep_state(),
+        );
+    }
+    rules.blank();
+}
+
+/// Tailscale outside the tunnel: the control plane and DERP relays over
+/// HTTPS, STUN to the relays and the direct `WireGuard` paths (opened from
+/// this side, inbound connections are blocked)
+fn push_tailscale(rules: &mut Ruleset, name: &str) {
+    let int = macro_name("int", name);
+    let relays = |port| Endpoint {
+        host: Host::Table(TAILSCALE_TABLE.to_string()),
+        port: Some(port),
+    };
+    let direct = Endpoint {
+        host: Host::Any,
+        port: Some(Port::Single(TAILSCALE_PORT)),
+    };
+    rules.comment("Allow tailscale control plane, derp relays and direct paths");
+    rules.push(
+        Rule::pass()
+            .direction(Direction::Out)
+            .on(&int)
+            .proto(&[Protocol::Tcp])
+            .to(relays(Port::List(vec![80, 443])))
+            .keep_state(),
+    );
+    rules.push(
+        Rule::pass()
+            .direction(Direction::Out)
+            .on(&int)
+            .proto(&[Protocol::Udp])
+            .to(relays(Port::Single(STUN_PORT)))
+            .keep_state(),
+    );
+    rules.push(
+        Rule::pass()
+            .direction(Direction::Out)
+            .on(&int)
+            .proto(&[Protocol::Udp])
+            .from(direct)
+            .keep_state(),
+    );
+    rules.blank();
+}
+
+/// Captive portal window: DNS and web to the network of the interface,
+/// where the gateway serves the login page
+fn push_portal(rules: &mut Ruleset, name: &str) {
+    let int = macro_name("int", name);
+    let network = |port| Endpoint {
+        host: Host::Network(int.clone()),
+        port: Some(port),
+    };
+    rules.comment("Captive portal window");
+    rules.push(
+        Rule::pass()
+            .direction(Direction::Out)
+            .quick()
+            .on(&int)
+            .proto(TCP_UDP)
+            .to(network(Port::Single(53)))
+            .keep_state(),
+    );
+    rules.push(
+        Rule::pass()
+            .direction(Direction::Out)
+            .quick()
+            .on(&int)
+            .proto(&[Protocol::Tcp])
+            .to(network(Port::List(vec![80, 443])))
+            .keep_state(),
+    );
+    rules.blank();
+}
+
+fn push_broadcast_multicast(
+    rules: &mut Ruleset,
+    interfaces: &[InterfaceInfo],
+    options: &Options,
+) -> Result<()> {
+    let broadcast = Host::Address("255.255.255.255".to_string());
+    let multicast = Host::Address("224.0.0.0/4".to_string());
+
+    // Broadcast
+    let on = scope_interfaces(&options.broadcast, interfaces, "--broadcast")?;
+    if let Some(on) = on {
+        rules.comment("Allow broadcasts on internal interface");
+        for iface in on {
+            rules.push(
+                scoped(Rule::pass(), iface)
+                    .to(broadcast.clone())
+                    .keep_state(),
+            );
+            rules.push(
+                scoped(Rule::pass(), iface)
+                    .from(broadcast.clone())
+                    .keep_state(),
+            );
+        }
+        rules.blank();
+    }
+
+    // Multicast
+    let on = scope_interfaces(&options.multicast, interfaces, "--multicast")?;
+    if let Some(on) = on {
+        rules.comment("Allow multicast");
+        for iface in on {
+            rules.push(
+                scoped(Rule::pass(), iface)
+                    .proto(&[Protocol::Udp])
+                    .to(multicast.clone())
+                    .keep_state(),
+            );
+            rules.push(
+                scoped(Rule::pass(), iface)
+                    .proto(&[Protocol::Udp])
+                    .from(multicast.clone())
+                    .keep_state(),
+            );
+        }
+        rules.blank();
+    }
+
+    for (name, scope, group, port) in [
+        ("mdns", &options.mdns, MDNS_GROUP, MDNS_PORT),
+        ("ssdp", &options.ssdp, SSDP_GROUP, SSDP_PORT),
+    ] {
+        let on = scope_interfaces(scope, interfaces, &format!("--{name}"))?;
+        // Covered by the multicast rules, or blocked by the block all
+        let carve_out = options.multicast != Scope::Blocked;
+        if (*scope == Scope::All && options.multicast == Scope::All) || (on.is_none() && !carve_out)
+        {
+            continue;
+        }
+        let service = Endpoint {
+            host: Host::Address(group.to_string()),
+            port: Some(Port::Single(port)),
+        };
+        rules.comment(if on.is_some() {
+            format!("Allow {name}")
+        } else {
+            format!("Block {name}")
+        });
+        // Last match: carved out of the multicast rules
+        if carve_out {
+            rules.push(
+                block(options.log)
+                    .proto(&[Protocol::Udp])
+                    .to(service.clone()),
+            );
+        }
+        for iface in on.unwrap_or_default() {
+            rules.push(
+                scoped(Rule::pass(), iface)
+                    .proto(&[Protocol::Udp])
+                    .to(service.clone())
+                    .keep_state(),
+            );
+        }
+        rules.blank();
+    }
+    Ok(())
+}
+
+/// Restrict a rule to an interface, any interface when `None`
+fn scoped(rule: Rule, iface: Option<&InterfaceInfo>) -> Rule {
+    match iface {
+        Some(iface) => {
+            let prefix = if iface.is_p2p() { "vpn" } else { "int" };
+            rule.on(&macro_name(prefix, iface.name()))
+        }
+        None => rule,
+    }
+}
+
+/// Interfaces of a broadcast or multicast scope: `None` when blocked,
+/// a single `None` entry (every interface) for `all`
+fn scope_interfaces<'a>(
+    scope: &Scope,
+    interfaces: &'a [InterfaceInfo],
+    flag: &str,
+) -> Result<Option<Vec<Option<&'a InterfaceInfo>>>> {
+    match scope {
+        Scope::All => Ok(Some(vec![None])),
+        Scope::Blocked => Ok(None),
+        Scope::Only(patterns) => {
+            for pattern in patterns {
+                if !interfaces.iter().any(|i| glob_match(pattern, i.name())) {
+                    bail!("No interface matches {flag} {pattern}");
+                }
+            }
+            Ok(Some(
+                interfaces
+                    .iter()
+                    .filter(|i| patterns.iter().any(|p| glob_match(p, i.name())))
+                    .map(Some)
+                    .collect(),
+            ))
+        }
+    }
+}
+
+/// How the tunnel reaches its peers: the given or detected protocol and port,
+/// both TCP and UDP to any port when unknown
+struct Tunnel {
+    protocols: &'static [Protocol],
+    peers: Endpoint,
+}
+
+fn tunnel_endpoint(snapshot: &NetworkSnapshot, options: &Options) -> Tunnel {
+    let protocols: &'static [Protocol] = match options.peer_proto.or(snapshot.peer_proto) {
+        Some(PeerProto::Tcp) => &[Protocol::Tcp],
+        Some(PeerProto::Udp) => &[Protocol::Udp],
+        None => TCP_UDP,
+    };
+    Tunnel {
+        protocols,
+        peers: Endpoint {
+            host: Host::Table(PEERS_TABLE.to_string()),
+            port: options.peer_port.or(snapshot.peer_port).map(Port::Single),
+        },
+    }
+}
+
+fn push_physical(
+    rules: &mut Ruleset,
+    name: &str,
+    peers: &[IpAddr],
+    tunnel: &Tunnel,
+    icmp: Option<&[IcmpType]>,
+    local: bool,
+) {
+    let int = macro_name("int", name);
+    let dhcp = Endpoint {
+        host: Host::Any,
+        port: Some(Port::Range(67, 68)),
+    };
+
+    if let Some(types) = icmp {
+        rules.comment("Allow ping");
+        let mut rule = Rule::pass()
+            .on(&int)
+            .family(AddressFamily::Inet)
+            .proto(&[Protocol::Icmp]);
+        for icmp_type in types {
+            rule = rule.icmp_type(&icmp_type.name, icmp_type.code);
+        }
+        rules.push(rule.keep_state());
+        rules.blank();
+    }
+    rules.comment("Allow dhcp");
+    rules.push(
+        Rule::pass()
+            .on(&int)
+            .family(AddressFamily::Inet)
+            .proto(TCP_UDP)
+            .from(dhcp.clone())
+            .to(dhcp)
+            .keep_state(),
+    );
+    rules.blank();
+    push_ipv6_on_link(rules, &int);
+    if local {
+        rules.push(
+            Rule::pass()
+                .from(Host::Network(int.clone()))
+                .to(Host::Network(int.clone())),
+        );
+    }
+
+    let mut peer_rule = Rule::pass()
+        .on(&int)
+        .proto(tunnel.protocols)
+        .to(tunnel.peers.clone());
+    if peers.iter().all(IpAddr::is_ipv6) {
+        peer_rule = peer_rule.family(AddressFamily::Inet6);
+    }
+    rules.comment("use only the vpn");
+    rules.push(peer_rule);
+}
+
+/// IPv6 on the link, whatever else is blocked: neighbor discovery, router
+/// advertisements and `DHCPv6`
+fn push_ipv6_on_link(rules: &mut Ruleset, int: &str) {
+    let dhcp6 = Endpoint {
+        host: Host::Any,
+        port: Some(Port::Range(546, 547)),
+    };
+    rules.comment("Allow IPv6 neighbor discovery and dhcpv6 on-link");
+    rules.push(
+        Rule::pass()
+            .on(int)
+            .family(AddressFamily::Inet6)
+            .proto(&[Protocol::Icmp6])
+            .icmp_type("neighbrsol", None)
+            .icmp_type("neighbradv", None)
+            .icmp_type("routersol", None)
+            .icmp_type("routeradv", None)
+            .keep_state(),
+    );
+    rules.push(
+        Rule::pass()
+            .on(int)
+            .family(AddressFamily::Inet6)
+            .proto(&[Protocol::Udp])
+            .from(dhcp6.clone())
+            .to(dhcp6)
+            .keep_state(),
+    );
+    rules.blank();
+}
+
+/// IPv6 link-local traffic with `--local`: unicast and link-scope multicast
+fn push_link_local(rules: &mut Ruleset, name: &str) {
+    let int = macro_name("int", name);
+    rules.comment("Allow IPv6 link-local");
+    for to in [LINK_LOCAL, LINK_MULTICAST] {
+        rules.push(
+            Rule::pass()
+                .on(&int)
+                .family(AddressFamily::Inet6)
+                .from(Host::Address(LINK_LOCAL.to_string()))
+                .to(Host::Address(to.to_string()))
+                .keep_state(),
+        );
+    }
+    rules.blank();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::killswitch::network::hex_to_cidr;
+    use crate::killswitch::network::{DnsResolver, hex_to_cidr};
+
+    #[allow(clippy::unwrap_used)]
+    fn targets(addrs: &[&str]) -> Targets {
+        Targets {
+            peers: addrs.iter().map(|a| a.parse().unwrap()).collect(),
+            ..Targets::default()
+        }
+    }
+
+    fn opts(leak: bool, local: bool) -> Options {
+        let mut options = Options {
+            local: local.then(Vec::new),
+            ..Options::default()
+        };
+        if leak {
+            options.allow_leak();
+        }
+        options
+    }
+
+    fn snapshot() -> NetworkSnapshot {
+        NetworkSnapshot {
+            interfaces: vec![
+                InterfaceInfo::new("en0", "a4:83:e7:00:00:01", "192.168.1.100/24", false),
+                InterfaceInfo::new("utun3", "", "10.8.0.2", true),
+            ],
+            ..NetworkSnapshot::default()
+        }
+    }
 
     fn extract_network(line: &str) -> Option<String> {
         let parts: Vec<&str> = line.split_whitespace().collect();
@@ -122,30 +1439,1262 @@ mod tests {
         Some(format!("{ip}/{cidr}"))
     }
 
+    #[test]
+    fn test_generate_macro_collision() {
+        use crate::cli::verbosity::Verbosity;
+        let mut snapshot = snapshot();
+        snapshot.interfaces.extend([
+            InterfaceInfo::new("vlan.2", "", "192.168.2.10/24", false),
+            InterfaceInfo::new("vlan_2", "", "192.168.3.10/24", false),
+        ]);
+        let err = generate(
+            &snapshot,
+            &targets(&["203.0.113.1"]),
+            &opts(false, false),
+            Verbosity::Normal,
+        )
+        .err()
+        .map(|e| e.to_string());
+        assert_eq!(
+            err.as_deref(),
+            Some("Interfaces vlan.2 and vlan_2 both map to the pf macro $int_vlan_2")
+        );
+    }
+
     #[allow(clippy::unwrap_used)]
     #[test]
     fn test_generate_basic() {
         use crate::cli::verbosity::Verbosity;
-        let rules = generate("203.0.113.1", false, false, Verbosity::Normal).unwrap();
-        assert!(rules.contains("vpn_ip = \"203.0.113.1\""));
+        let rules = generate(
+            &snapshot(),
+            &targets(&["203.0.113.1"]),
+            &opts(false, false),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("table <vpn_peers> persist { 203.0.113.1 }"));
         assert!(rules.contains("set block-policy drop"));
         assert!(rules.contains("set skip on lo0"));
         assert!(rules.contains("block all"));
-        assert!(rules.contains("block out inet6"));
+        assert!(!rules.contains("block out inet6"));
         assert!(rules.contains("pass from any to 255.255.255.255 keep state"));
         assert!(rules.contains("from any port 67:68 to any port 67:68 keep state"));
         assert!(!rules.contains("icmp-type 8 code 0"));
+        assert!(rules.contains("int_en0 = \"en0\""));
+        assert!(rules.contains("vpn_utun3 = \"utun3\""));
+        assert!(rules.contains("pass on $int_en0 proto {tcp, udp} from any to <vpn_peers>"));
+        // The tunnel has no IPv6 address
+        assert!(rules.contains("pass on $vpn_utun3 inet all"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_anchor() {
+        use crate::cli::verbosity::Verbosity;
+        let options = Options {
+            anchor: true,
+            ..Options::default()
+        };
+        let rules = generate(
+            &snapshot(),
+            &targets(&["203.0.113.1"]),
+            &options,
+            Verbosity::Normal,
+        )
+        .unwrap();
+        // pfctl ignores global options inside an anchor
+        assert_eq!(rules.options().count(), 0);
+        // Every rule is quick, first match wins: the catch-all block is last
+        assert!(rules.rules().all(|r| r.quick));
+        assert_eq!(
+            rules.rules().last().map(ToString::to_string).as_deref(),
+            Some("block quick all")
+        );
+        let rendered = rules.to_string();
+        assert!(rendered.contains("# sudo pfctl -a killswitch -f"));
+        let position = |rule: &str| rendered.find(rule).unwrap();
+        assert!(position("pass quick on lo0 all") < position("block in quick on $vpn_utun3 all"));
+        assert!(
+            position("block in quick on $vpn_utun3 all")
+                < position("pass quick on $vpn_utun3 inet all")
+        );
+        assert!(
+            position("pass in quick on $int_en0 inet proto udp from any port 67")
+                < position("block in quick on $int_en0 all")
+        );
+
+        let stale = stale_states(&rules);
+        assert_eq!(stale.interfaces, ["en0"]);
+        assert_eq!(
+            stale
+                .keep
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["203.0.113.1"]
+        );
     }
 
     #[allow(clippy::unwrap_used)]
     #[test]
     fn test_generate_with_leak() {
         use crate::cli::verbosity::Verbosity;
-        let rules = generate("203.0.113.1", true, false, Verbosity::Normal).unwrap();
+        let rules = generate(
+            &snapshot(),
+            &targets(&["203.0.113.1"]),
+            &opts(true, false),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
         assert!(rules.contains("pass quick proto {tcp, udp} from any to any port 53 keep state"));
         assert!(rules.contains("icmp-type 8 code 0 keep state"));
     }
 
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_structure() {
+        use crate::cli::verbosity::Verbosity;
+        use crate::killswitch::ruleset::Action;
+        let rules = generate(
+            &snapshot(),
+            &targets(&["203.0.113.1"]),
+            &opts(false, true),
+            Verbosity::Normal,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.table(PEERS_TABLE).map(|t| t.addresses.clone()),
+            Some(vec!["203.0.113.1".to_string()])
+        );
+        let first = rules.rules().next().unwrap();
+        assert_eq!(first.action, Action::Block);
+        assert!(first.interface.is_none());
+        // Nothing but DNS may be quick without leak
+        assert!(rules.rules().all(|r| !r.quick));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_ipv6_peer() {
+        use crate::cli::verbosity::Verbosity;
+        let rules = generate(
+            &snapshot(),
+            &targets(&["2001:db8::1"]),
+            &opts(false, false),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("table <vpn_peers> persist { 2001:db8::1 }"));
+        assert!(rules.contains("pass on $int_en0 inet6 proto {tcp, udp} from any to <vpn_peers>"));
+        assert!(rules.contains("icmp6-type {neighbrsol, neighbradv, routersol, routeradv}"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_lints_clean() {
+        use crate::cli::verbosity::Verbosity;
+        use crate::killswitch::{diff, lint};
+        let mut snapshot = bridged_snapshot();
+        snapshot.dns = vec![resolver("10.8.0.1", Some("utun3"))];
+        let mut targets = targets(&["203.0.113.1", "2001:db8::1"]);
+        targets.allow = vec!["198.51.100.0/24".to_string()];
+        targets.tailscale = vec!["192.200.0.101".to_string()];
+        targets.exempt = Owners {
+            users: vec![200],
+            groups: vec![20, 80],
+        };
+        targets.deny = ["25", "tcp/465", "203.0.113.128/25"]
+            .iter()
+            .map(|d| d.parse().unwrap())
+            .collect();
+        let variants = [
+            Options::default(),
+            Options {
+                anchor: true,
+                log: true,
+                dns_lock: true,
+                portal: true,
+                local: Some(vec![
+                    "192.168.1.0/24".to_string(),
+                    "!192.168.1.1".to_string(),
+                ]),
+                bridges: BridgePolicy::Tunnel,
+                preset: Some(Preset::Tailscale),
+                mdns: Scope::Only(vec!["en0".to_string()]),
+                ssdp: Scope::Blocked,
+                inbound: vec!["tcp/22@en0".parse().unwrap(), "udp/51413".parse().unwrap()],
+                ..Options::default()
+            },
+        ];
+        // The owner rules: kill switch only for some accounts, others exempt
+        let mut owners = targets.clone();
+        owners.only = Owners {
+            users: vec![501, 502],
+            groups: vec![20],
+        };
+        for (options, targets) in variants
+            .iter()
+            .flat_map(|options| [(options, &targets), (options, &owners)])
+        {
+            let rules = generate(&snapshot, targets, options, Verbosity::Normal).unwrap();
+            let parsed = lint::parse(&rules.to_string()).unwrap();
+            assert_eq!(parsed.ruleset, rules);
+            assert_eq!(lint::check(&parsed), []);
+            // Unchanged against itself once normalized
+            let tables = |name: &str| rules.table(name).map(|t| t.addresses.clone());
+            let changes = diff::compare(
+                &rules.to_string(),
+                |name| tables(name).unwrap_or_default(),
+                &rules,
+                &snapshot,
+            );
+            assert_eq!(changes.unwrap(), []);
+        }
+
+        // --leak lets DNS to any resolver out of the physical interfaces
+        let rules = generate(&snapshot, &targets, &opts(true, false), Verbosity::Normal)
+            .unwrap()
+            .to_string();
+        let diagnostics = lint::lint(&rules);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(
+            diagnostics
+                .iter()
+                .all(|d| d.severity == lint::Severity::Warning)
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_ipv6_policy() {
+        use crate::cli::verbosity::Verbosity;
+        let rules = generate(
+            &snapshot(),
+            &targets(&["203.0.113.1"]),
+            &opts(false, false),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains(
+            "pass on $int_en0 inet6 proto ipv6-icmp all icmp6-type {neighbrsol, neighbradv, routersol, routeradv} keep state"
+        ));
+        assert!(rules.contains(
+            "pass on $int_en0 inet6 proto udp from any port 546:547 to any port 546:547 keep state"
+        ));
+        assert!(!rules.contains("fe80::/10"));
+
+        // Link-local with --local, inet6 through a tunnel that carries it
+        let mut snapshot = snapshot();
+        snapshot.interfaces.extend([
+            InterfaceInfo::new("en0", "a4:83:e7:00:00:01", "2001:db8::5/64", false),
+            InterfaceInfo::new("utun3", "", "fd00::2", true),
+        ]);
+        let rules = generate(
+            &snapshot,
+            &targets(&["203.0.113.1"]),
+            &opts(false, true),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("pass on $int_en0 inet6 from fe80::/10 to fe80::/10 keep state"));
+        assert!(rules.contains("pass on $int_en0 inet6 from fe80::/10 to ff02::/16 keep state"));
+        assert!(rules.contains("pass on $vpn_utun3 all"));
+        assert_eq!(rules.matches("int_en0 = \"en0\"").count(), 1);
+        assert_eq!(rules.matches("vpn_utun3 = \"utun3\"").count(), 1);
+        assert_eq!(rules.matches("pass on $vpn_utun3 all\n").count(), 1);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_server_pool() {
+        use crate::cli::verbosity::Verbosity;
+        let rules = generate(
+            &snapshot(),
+            &targets(&["203.0.113.1", "198.51.100.7", "2001:db8::1"]),
+            &opts(false, false),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(
+            rules.contains("table <vpn_peers> persist { 203.0.113.1, 198.51.100.7, 2001:db8::1 }")
+        );
+        // Mixed families: a single rule without an address family covers both
+        assert!(rules.contains("pass on $int_en0 proto {tcp, udp} from any to <vpn_peers>"));
+        assert!(rules.contains("icmp6-type {neighbrsol, neighbradv, routersol, routeradv}"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_peer_port_and_proto() {
+        use crate::cli::verbosity::Verbosity;
+        let options = Options {
+            peer_port: Some(51820),
+            peer_proto: Some(PeerProto::Udp),
+            ..Options::default()
+        };
+        let rules = generate(
+            &snapshot(),
+            &targets(&["203.0.113.1"]),
+            &options,
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("pass on $int_en0 proto udp from any to <vpn_peers> port 51820"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_detected_peer_port() {
+        use crate::cli::verbosity::Verbosity;
+        let mut detected = snapshot();
+        detected.peer_port = Some(51820);
+        detected.peer_proto = Some(PeerProto::Udp);
+
+        let rules = generate(
+            &detected,
+            &targets(&["203.0.113.1"]),
+            &opts(false, false),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("proto udp from any to <vpn_peers> port 51820"));
+
+        // Explicit options win over detection
+        let options = Options {
+            peer_port: Some(443),
+            peer_proto: Some(PeerProto::Tcp),
+            ..Options::default()
+        };
+        let rules = generate(
+            &detected,
+            &targets(&["203.0.113.1"]),
+            &options,
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("proto tcp from any to <vpn_peers> port 443"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_allowlist() {
+        use crate::cli::verbosity::Verbosity;
+        let without = generate(
+            &snapshot(),
+            &targets(&["203.0.113.1"]),
+            &opts(false, false),
+            Verbosity::Normal,
+        )
+        .unwrap();
+        assert!(without.table(ALLOW_TABLE).is_none());
+
+        let mut allowed = targets(&["203.0.113.1"]);
+        allowed.allow = vec!["198.51.100.0/24".to_string(), "192.0.2.10".to_string()];
+        let rules = generate(
+            &snapshot(),
+            &allowed,
+            &opts(false, false),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("table <allow> persist { 198.51.100.0/24, 192.0.2.10 }"));
+        assert!(rules.contains("pass out on $int_en0 from any to <allow> keep state"));
+        assert!(!rules.contains("$vpn_utun3 from any to <allow>"));
+        assert!(!rules.contains("<bypass>"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_bypass_domains() {
+        use crate::cli::verbosity::Verbosity;
+        let mut bypassed = targets(&["203.0.113.1"]);
+        bypassed.bypass = vec!["192.0.2.10".to_string(), "2001:db8::10".to_string()];
+        let rules = generate(
+            &snapshot(),
+            &bypassed,
+            &opts(false, false),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("table <bypass> persist { 192.0.2.10, 2001:db8::10 }"));
+        assert!(rules.contains("pass out on $int_en0 from any to <bypass> keep state"));
+        assert!(!rules.contains("<allow>"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_scoped_dns_and_icmp() {
+        use crate::cli::verbosity::Verbosity;
+        let mut snapshot = snapshot();
+        snapshot.interfaces.push(InterfaceInfo::new(
+            "en1",
+            "a4:83:e7:00:00:02",
+            "10.0.0.5/24",
+            false,
+        ));
+        let options = Options {
+            allow_dns: Some(vec!["9.9.9.9".to_string(), "2620:fe::fe".to_string()]),
+            allow_icmp: Some(vec!["echoreq".to_string(), "unreach".to_string()]),
+            icmp_ifaces: vec!["en1".to_string()],
+            ..Options::default()
+        };
+        let rules = generate(
+            &snapshot,
+            &targets(&["203.0.113.1"]),
+            &options,
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("table <dns_resolvers> persist { 9.9.9.9, 2620:fe::fe }"));
+        assert!(rules.contains(
+            "pass quick proto {tcp, udp} from any to <dns_resolvers> port 53 keep state"
+        ));
+        assert!(!rules.contains("to any port 53"));
+        assert!(rules.contains(
+            "pass on $int_en1 inet proto icmp all icmp-type {echoreq, unreach} keep state"
+        ));
+        assert!(!rules.contains("pass on $int_en0 inet proto icmp"));
+    }
+
+    #[test]
+    fn test_generate_rejects_invalid_exemptions() {
+        use crate::cli::verbosity::Verbosity;
+        let peers = targets(&["203.0.113.1"]);
+        let invalid = [
+            Options {
+                allow_dns: Some(vec!["resolver.example".to_string()]),
+                ..Options::default()
+            },
+            Options {
+                allow_icmp: Some(vec!["ping".to_string()]),
+                ..Options::default()
+            },
+            Options {
+                allow_icmp: Some(Vec::new()),
+                icmp_ifaces: vec!["en9".to_string()],
+                ..Options::default()
+            },
+        ];
+        for options in &invalid {
+            assert!(generate(&snapshot(), &peers, options, Verbosity::Normal).is_err());
+        }
+    }
+
+    fn resolver(address: &str, interface: Option<&str>) -> DnsResolver {
+        DnsResolver {
+            address: address.to_string(),
+            interface: interface.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_locked_resolvers() {
+        let mut snapshot = snapshot();
+        snapshot.dns = vec![
+            resolver("192.168.1.1", Some("en0")),
+            resolver("10.8.0.1", Some("utun3")),
+            resolver("fd00::53", Some("utun3")),
+        ];
+        assert_eq!(locked_resolvers(&snapshot), vec!["10.8.0.1", "fd00::53"]);
+
+        // resolv.conf has no scope: every resolver but the loopback stub
+        snapshot.dns = vec![
+            resolver("127.0.0.53", None),
+            resolver("10.8.0.1", None),
+            resolver("bogus", None),
+        ];
+        assert_eq!(locked_resolvers(&snapshot), vec!["10.8.0.1"]);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_dns_lock() {
+        use crate::cli::verbosity::Verbosity;
+        let mut snapshot = snapshot();
+        snapshot.dns = vec![
+            resolver("192.168.1.1", Some("en0")),
+            resolver("10.8.0.1", Some("utun3")),
+        ];
+        let options = Options {
+            dns_lock: true,
+            ..Options::default()
+        };
+        let rules = generate(
+            &snapshot,
+            &targets(&["203.0.113.1"]),
+            &options,
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("table <dns_lock> persist { 10.8.0.1 }"));
+        assert!(rules.contains(
+            "pass quick on $vpn_utun3 proto {tcp, udp} from any to <dns_lock> port {53, 853} keep state"
+        ));
+        assert!(rules.contains("block quick proto {tcp, udp} from any to any port {53, 853}"));
+        let pass = rules.find("to <dns_lock>").unwrap();
+        let block = rules.find("block quick").unwrap();
+        assert!(pass < block);
+
+        // Nothing to lock to
+        snapshot.dns.clear();
+        assert!(
+            generate(
+                &snapshot,
+                &targets(&["203.0.113.1"]),
+                &options,
+                Verbosity::Normal
+            )
+            .is_err()
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_log() {
+        use crate::cli::verbosity::Verbosity;
+        let mut snapshot = snapshot();
+        snapshot.dns = vec![resolver("10.8.0.1", Some("utun3"))];
+        let options = Options {
+            log: true,
+            dns_lock: true,
+            ..Options::default()
+        };
+        let rules = generate(
+            &snapshot,
+            &targets(&["203.0.113.1"]),
+            &options,
+            Verbosity::Normal,
+        )
+        .unwrap();
+        let blocks: Vec<_> = rules
+            .rules()
+            .filter(|r| r.action == crate::killswitch::ruleset::Action::Block)
+            .collect();
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks.iter().all(|r| r.log));
+        let rendered = rules.to_string();
+        assert!(rendered.contains("block log all"));
+        // Pass rules are not logged
+        assert!(
+            rules
+                .rules()
+                .filter(|r| r.log)
+                .all(|r| r.action != crate::killswitch::ruleset::Action::Pass)
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_portal() {
+        use crate::cli::verbosity::Verbosity;
+        let mut snapshot = snapshot();
+        snapshot.dns = vec![resolver("10.8.0.1", Some("utun3"))];
+        let options = Options {
+            dns_lock: true,
+            portal: true,
+            ..Options::default()
+        };
+        let rules = generate(
+            &snapshot,
+            &targets(&["203.0.113.1"]),
+            &options,
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains(
+            "pass out quick on $int_en0 proto {tcp, udp} from any to $int_en0:network port 53 keep state"
+        ));
+        assert!(rules.contains(
+            "pass out quick on $int_en0 proto tcp from any to $int_en0:network port {80, 443} keep state"
+        ));
+        assert!(!rules.contains("$vpn_utun3:network"));
+        // The DNS lock would win otherwise
+        let portal = rules.find("port 53 keep state").unwrap();
+        let lock = rules.find("block quick").unwrap();
+        assert!(portal < lock);
+
+        let strict = generate(
+            &snapshot,
+            &targets(&["203.0.113.1"]),
+            &Options {
+                portal: false,
+                ..options
+            },
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(!strict.contains("Captive portal"));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("en0", "en0"));
+        assert!(!glob_match("en0", "en10"));
+        assert!(glob_match("bridge*", "bridge100"));
+        assert!(glob_match("bridge*", "bridge"));
+        assert!(glob_match("*tun*", "utun3"));
+        assert!(glob_match("en?", "en5"));
+        assert!(!glob_match("en?", "en10"));
+        assert!(!glob_match("vmnet*", "en0"));
+        assert!(glob_match("*", "awdl0"));
+    }
+
+    fn bridged_snapshot() -> NetworkSnapshot {
+        let mut snapshot = snapshot();
+        snapshot.interfaces.extend([
+            InterfaceInfo::new("en5", "", "172.20.10.2/28", false),
+            InterfaceInfo::new("bridge100", "", "192.168.2.1/24", false),
+            InterfaceInfo::new("awdl0", "", "", false),
+        ]);
+        snapshot
+    }
+
+    #[test]
+    fn test_select_interfaces() {
+        let names = |list: &[InterfaceInfo]| -> Vec<String> {
+            list.iter().map(|i| i.name().to_string()).collect()
+        };
+        let (interfaces, bridges) = select_interfaces(&bridged_snapshot(), &Options::default());
+        assert_eq!(names(&interfaces), ["en0", "utun3", "en5"]);
+        assert_eq!(names(&bridges), ["bridge100", "awdl0"]);
+
+        let options = Options {
+            only_ifaces: vec![
+                "en*".to_string(),
+                "utun*".to_string(),
+                "bridge*".to_string(),
+            ],
+            ignore_ifaces: vec!["en5".to_string()],
+            ..Options::default()
+        };
+        let (interfaces, bridges) = select_interfaces(&bridged_snapshot(), &options);
+        assert_eq!(names(&interfaces), ["en0", "utun3"]);
+        assert_eq!(names(&bridges), ["bridge100"]);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_bridges() {
+        use crate::cli::verbosity::Verbosity;
+        let generate_with = |bridges| {
+            let options = Options {
+                bridges,
+                ..Options::default()
+            };
+            generate(
+                &bridged_snapshot(),
+                &targets(&["203.0.113.1"]),
+                &options,
+                Verbosity::Normal,
+            )
+        };
+
+        // Blocked: no physical-interface treatment either
+        let rules = generate_with(BridgePolicy::Block).unwrap().to_string();
+        assert!(!rules.contains("int_bridge100"));
+        assert!(!rules.contains("$br_bridge100"));
+        assert!(rules.contains("pass on $int_en5 proto {tcp, udp} from any to <vpn_peers>"));
+
+        let rules = generate_with(BridgePolicy::Pass).unwrap().to_string();
+        assert!(rules.contains("br_bridge100 = \"bridge100\""));
+        assert!(rules.contains("pass on $br_bridge100 all"));
+        assert!(rules.contains("pass on $br_awdl0 all"));
+
+        let rules = generate_with(BridgePolicy::Tunnel).unwrap().to_string();
+        assert!(rules.contains(
+            "pass in on $br_bridge100 route-to $vpn_utun3 from $br_bridge100:network to any keep state"
+        ));
+        let routed = rules.find("route-to $vpn_utun3").unwrap();
+        let local = rules
+            .find("pass on $br_bridge100 from $br_bridge100:network to $br_bridge100:network")
+            .unwrap();
+        assert!(routed < local);
+
+        // Nothing to route to
+        let mut snapshot = bridged_snapshot();
+        snapshot.interfaces.retain(|i| !i.is_p2p());
+        let options = Options {
+            bridges: BridgePolicy::Tunnel,
+            ..Options::default()
+        };
+        assert!(
+            generate(
+                &snapshot,
+                &targets(&["203.0.113.1"]),
+                &options,
+                Verbosity::Normal
+            )
+            .is_err()
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_discovery() {
+        use crate::cli::verbosity::Verbosity;
+        let generate_with = |options: &Options| {
+            generate(
+                &snapshot(),
+                &targets(&["203.0.113.1"]),
+                options,
+                Verbosity::Normal,
+            )
+        };
+
+        // Default: the historical rules, on every interface
+        let rules = generate_with(&Options::default()).unwrap().to_string();
+        assert!(rules.contains("pass from any to 255.255.255.255 keep state"));
+        assert!(rules.contains("pass proto udp from any to 224.0.0.0/4 keep state"));
+        assert!(!rules.contains("5353"));
+        assert!(!rules.contains("1900"));
+
+        // Paranoid
+        let paranoid = Options {
+            broadcast: Scope::Blocked,
+            multicast: Scope::Blocked,
+            mdns: Scope::Blocked,
+            ssdp: Scope::Blocked,
+            ..Options::default()
+        };
+        let rules = generate_with(&paranoid).unwrap().to_string();
+        assert!(!rules.contains("255.255.255.255"));
+        assert!(!rules.contains("224.0.0."));
+        assert!(!rules.contains("239.255.255.250"));
+
+        // mDNS carved out of multicast, kept on en0
+        let options = Options {
+            broadcast: Scope::Only(vec!["en*".to_string()]),
+            mdns: Scope::Only(vec!["en0".to_string()]),
+            ssdp: Scope::Blocked,
+            ..Options::default()
+        };
+        let rules = generate_with(&options).unwrap().to_string();
+        assert!(rules.contains("pass on $int_en0 from any to 255.255.255.255 keep state"));
+        assert!(!rules.contains("pass from any to 255.255.255.255"));
+        let block = rules
+            .find("block proto udp from any to 224.0.0.251 port 5353")
+            .unwrap();
+        let pass = rules
+            .find("pass on $int_en0 proto udp from any to 224.0.0.251 port 5353 keep state")
+            .unwrap();
+        assert!(rules.find("from any to 224.0.0.0/4").unwrap() < block);
+        assert!(block < pass);
+        assert!(rules.contains("block proto udp from any to 239.255.255.250 port 1900"));
+        assert!(!rules.contains("pass proto udp from any to 239.255.255.250"));
+
+        // mDNS alone: nothing to carve out of
+        let options = Options {
+            mdns: Scope::All,
+            ..paranoid
+        };
+        let rules = generate_with(&options).unwrap().to_string();
+        assert!(!rules.contains("block proto udp from any to 224.0.0.251"));
+        assert!(rules.contains("pass proto udp from any to 224.0.0.251 port 5353 keep state"));
+
+        let options = Options {
+            ssdp: Scope::Only(vec!["en9".to_string()]),
+            ..Options::default()
+        };
+        assert!(generate_with(&options).is_err());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_tailscale() {
+        use crate::cli::verbosity::Verbosity;
+        let mut snapshot = snapshot();
+        snapshot.dns = vec![resolver("100.100.100.100", Some("utun3"))];
+        let options = Options {
+            preset: Some(Preset::Tailscale),
+            dns_lock: true,
+            ..Options::default()
+        };
+        let mut targets = targets(&["203.0.113.1"]);
+        targets.tailscale = vec!["192.200.0.101".to_string(), "2607:f740::1".to_string()];
+        let rules = generate(&snapshot, &targets, &options, Verbosity::Normal)
+            .unwrap()
+            .to_string();
+        assert!(rules.contains("table <tailscale> persist { 192.200.0.101, 2607:f740::1 }"));
+        assert!(rules.contains(
+            "pass out on $int_en0 proto tcp from any to <tailscale> port {80, 443} keep state"
+        ));
+        assert!(rules.contains(
+            "pass out on $int_en0 proto udp from any to <tailscale> port 3478 keep state"
+        ));
+        assert!(
+            rules.contains("pass out on $int_en0 proto udp from any port 41641 to any keep state")
+        );
+        assert!(!rules.contains("pass in on $int_en0 proto udp from any to any port 41641"));
+        // MagicDNS ahead of the DNS lock
+        let magic = rules
+            .find("pass quick on $vpn_utun3 proto {tcp, udp} from any to 100.100.100.100 port 53 keep state")
+            .unwrap();
+        let lock = rules.find("block quick").unwrap();
+        assert!(magic < lock);
+
+        // Nothing to reach the tailnet with
+        targets.tailscale.clear();
+        assert!(generate(&snapshot, &targets, &options, Verbosity::Normal).is_err());
+
+        let rules = generate(&snapshot, &targets, &Options::default(), Verbosity::Normal)
+            .unwrap()
+            .to_string();
+        assert!(!rules.contains("tailscale"));
+        assert!(!rules.contains("41641"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_owners() {
+        use crate::cli::verbosity::Verbosity;
+        let mut targets = targets(&["203.0.113.1"]);
+        targets.exempt = Owners {
+            users: vec![200],
+            groups: vec![20, 80],
+        };
+        let rules = generate(
+            &snapshot(),
+            &targets,
+            &Options::default(),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("pass out on $int_en0 proto {tcp, udp} all user 200 keep state"));
+        assert!(
+            rules.contains("pass out on $int_en0 proto {tcp, udp} all group { 20, 80 } keep state")
+        );
+        assert!(!rules.contains("$vpn_utun3 proto {tcp, udp} all user"));
+
+        // Everyone else passes, the listed accounts fall back to the kill switch
+        targets.exempt = Owners::default();
+        targets.only = Owners {
+            users: vec![501],
+            groups: Vec::new(),
+        };
+        let options = Options {
+            log: true,
+            ..Options::default()
+        };
+        let rules = generate(&snapshot(), &targets, &options, Verbosity::Normal)
+            .unwrap()
+            .to_string();
+        let pass = rules
+            .find("pass out on $int_en0 proto {tcp, udp} all keep state")
+            .unwrap();
+        let block = rules
+            .find("block out log on $int_en0 proto {tcp, udp} all user 501\n")
+            .unwrap();
+        let peers = rules
+            .find("pass on $int_en0 proto {tcp, udp} from any to <vpn_peers>")
+            .unwrap();
+        assert!(pass < block && block < peers);
+        assert!(!rules.contains("group"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_inbound_broadcast() {
+        use crate::cli::verbosity::Verbosity;
+        // Whatever --local is, the broadcast, multicast, SSDP and mDNS
+        // passes come after the inbound block, which they would lose to
+        for local in [false, true] {
+            let options = Options {
+                broadcast: Scope::Only(vec!["en0".to_string()]),
+                multicast: Scope::Only(vec!["en0".to_string()]),
+                ssdp: Scope::Only(vec!["en0".to_string()]),
+                mdns: Scope::Only(vec!["en0".to_string()]),
+                ..opts(false, local)
+            };
+            let rules = generate(
+                &snapshot(),
+                &targets(&["203.0.113.1"]),
+                &options,
+                Verbosity::Normal,
+            )
+            .unwrap()
+            .to_string();
+            let block = rules.find("block in on $int_en0 all\n").unwrap();
+            for pass in [
+                "pass on $int_en0 from any to 255.255.255.255 keep state",
+                "pass on $int_en0 from 255.255.255.255 to any keep state",
+                "pass on $int_en0 proto udp from any to 224.0.0.0/4 keep state",
+                "pass on $int_en0 proto udp from 224.0.0.0/4 to any keep state",
+                "pass on $int_en0 proto udp from any to 239.255.255.250 port 1900 keep state",
+                "pass on $int_en0 proto udp from any to 224.0.0.251 port 5353 keep state",
+            ] {
+                assert!(
+                    rules.find(pass).is_some_and(|pass| block < pass),
+                    "{pass}\n{rules}"
+                );
+            }
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_inbound() {
+        use crate::cli::verbosity::Verbosity;
+        let targets = targets(&["203.0.113.1"]);
+        let inbound = |ports: &[&str], local| Options {
+            inbound: ports.iter().map(|p| p.parse().unwrap()).collect(),
+            ..opts(false, local)
+        };
+
+        // Nothing open: inbound connections blocked on every interface
+        let rules = generate(
+            &snapshot(),
+            &targets,
+            &inbound(&[], false),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        let tunnel = rules.find("pass on $vpn_utun3 inet all\n").unwrap();
+        let block = rules.find("block in on $vpn_utun3 all\n").unwrap();
+        assert!(tunnel < block);
+        assert!(rules.contains("block in on $int_en0 all\n"));
+        assert!(!rules.contains("port 22"));
+
+        // Inbound UDP from the LAN is blocked too: after the block, only the
+        // answers no state matches pass in on the physical interface
+        let rules = generate(
+            &snapshot(),
+            &targets,
+            &inbound(&[], true),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        let local = rules
+            .find("pass from $int_en0:network to $int_en0:network\n")
+            .unwrap();
+        let block = rules.find("block in on $int_en0 all\n").unwrap();
+        assert!(local < block);
+        let passes: Vec<&str> = rules
+            .get(block..)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("pass") && line.contains("$int_en0"))
+            .collect();
+        assert_eq!(
+            passes,
+            [
+                "pass in on $int_en0 inet proto udp from any port 67 to any port 68 keep state",
+                "pass in on $int_en0 inet6 proto ipv6-icmp all icmp6-type {neighbrsol, neighbradv, routeradv} keep state",
+                "pass in on $int_en0 inet6 proto udp from any port 547 to any port 546 keep state",
+            ]
+        );
+
+        // Forwarded port on the tunnel, SSH from the local network
+        let options = inbound(&["udp/51413", "tcp/22@en0"], true);
+        let rules = generate(&snapshot(), &targets, &options, Verbosity::Normal)
+            .unwrap()
+            .to_string();
+        let block = rules.find("block in on $vpn_utun3 all\n").unwrap();
+        let open = rules
+            .find("pass in on $vpn_utun3 proto udp from any to any port 51413 keep state")
+            .unwrap();
+        assert!(block < open);
+        let block = rules.find("block in on $int_en0 all\n").unwrap();
+        let ssh = rules
+            .find("pass in on $int_en0 proto tcp from $int_en0:network to any port 22 keep state")
+            .unwrap();
+        assert!(block < ssh);
+        assert!(!rules.contains("$int_en0 proto udp from any to any port 51413"));
+
+        // Physical ports need --local and a known interface
+        let err = generate(
+            &snapshot(),
+            &targets,
+            &inbound(&["tcp/22@en0"], false),
+            Verbosity::Normal,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("requires --local"), "{err}");
+        assert!(
+            generate(
+                &snapshot(),
+                &targets,
+                &inbound(&["tcp/22@en9"], true),
+                Verbosity::Normal
+            )
+            .is_err()
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_deny() {
+        use crate::cli::verbosity::Verbosity;
+        let mut targets = targets(&["203.0.113.1"]);
+        targets.deny = ["25", "587", "tcp/465", "198.51.100.0/24", "192.0.2.7"]
+            .iter()
+            .map(|d| d.parse().unwrap())
+            .collect();
+        let options = Options {
+            log: true,
+            ..Options::default()
+        };
+        let rules = generate(&snapshot(), &targets, &options, Verbosity::Normal)
+            .unwrap()
+            .to_string();
+        assert!(rules.contains("table <deny> persist { 198.51.100.0/24, 192.0.2.7 }"));
+        let block_all = rules.find("block log all\n").unwrap();
+        let networks = rules
+            .find("block out log quick from any to <deny>\n")
+            .unwrap();
+        let ports = rules
+            .find("block out log quick proto {tcp, udp} from any to any port {25, 587}\n")
+            .unwrap();
+        let tcp = rules
+            .find("block out log quick proto tcp from any to any port 465\n")
+            .unwrap();
+        let tunnel = rules.find("pass on $vpn_utun3 inet all").unwrap();
+        assert!(block_all < networks && networks < ports && ports < tcp && tcp < tunnel);
+        assert!(!rules.contains("proto udp from any to any port"));
+
+        // Ports only: no table
+        targets.deny = vec!["udp/443".parse().unwrap()];
+        let rules = generate(
+            &snapshot(),
+            &targets,
+            &Options::default(),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(!rules.contains("<deny>"));
+        assert!(rules.contains("block out quick proto udp from any to any port 443\n"));
+
+        // Nothing denied may cut off the tunnel itself
+        let peer = Options {
+            peer_port: Some(51820),
+            peer_proto: Some(PeerProto::Udp),
+            ..Options::default()
+        };
+        for (deny, err) in [
+            (
+                "udp/51820",
+                Some("--deny udp/51820 would block the VPN peer port 51820"),
+            ),
+            (
+                "51820",
+                Some("--deny 51820 would block the VPN peer port 51820"),
+            ),
+            ("tcp/51820", None),
+            ("udp/51821", None),
+            (
+                "203.0.113.0/24",
+                Some("--deny 203.0.113.0/24 would block the VPN peer 203.0.113.1"),
+            ),
+            ("203.0.113.128/25", None),
+        ] {
+            targets.deny = vec![deny.parse().unwrap()];
+            let result = generate(&snapshot(), &targets, &peer, Verbosity::Normal);
+            assert_eq!(
+                result.err().map(|e| e.to_string()).as_deref(),
+                err,
+                "{deny}"
+            );
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_fragments() {
+        use crate::cli::verbosity::Verbosity;
+        use crate::killswitch::lint;
+        let fragment = |position, name: &str, text: &str| Fragment {
+            position,
+            name: name.to_string(),
+            parsed: lint::parse(text).unwrap(),
+        };
+        let mut targets = targets(&["203.0.113.1"]);
+        targets.fragments = vec![
+            fragment(
+                Position::BeforeBlock,
+                "before-block/smtp.conf",
+                "block out quick proto tcp to any port 25\n",
+            ),
+            fragment(
+                Position::AfterExemptions,
+                "after-exemptions/game.conf",
+                "game = \"192.168.1.50\"\npass out on $int_en0 proto udp to $game port 27015\n",
+            ),
+            fragment(
+                Position::AfterTunnel,
+                "after-tunnel/mail.conf",
+                "pass out on $vpn_utun3 proto tcp to any port 587\n",
+            ),
+        ];
+
+        // A pass on the physical interface needs the override
+        let err = generate(
+            &snapshot(),
+            &targets,
+            &Options::default(),
+            Verbosity::Normal,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("after-exemptions/game.conf: line 2: error: `pass` on the physical interface $int_en0"),
+            "{err}"
+        );
+
+        let options = Options {
+            unsafe_fragments: true,
+            ..Options::default()
+        };
+        let rules = generate(&snapshot(), &targets, &options, Verbosity::Normal)
+            .unwrap()
+            .to_string();
+        let position = |text: &str| rules.find(text).unwrap();
+        assert!(position("# Fragment before-block/smtp.conf") < position("\nblock all"));
+        assert!(
+            position("pass out on $int_en0 proto udp from any to $game port 27015")
+                > position("pass on $int_en0 proto {tcp, udp} from any to <vpn_peers>")
+        );
+        assert!(
+            position("pass out on $int_en0 proto udp from any to $game port 27015")
+                < position("pass on $vpn_utun3 inet all")
+        );
+        assert!(
+            position("pass out on $vpn_utun3 proto tcp from any to any port 587")
+                > position("pass on $vpn_utun3 inet all")
+        );
+
+        // Fragments cannot use macros the ruleset does not define
+        targets.fragments = vec![fragment(
+            Position::AfterTunnel,
+            "after-tunnel/typo.conf",
+            "pass on $vpn_utun9 all\n",
+        )];
+        assert!(generate(&snapshot(), &targets, &options, Verbosity::Normal).is_err());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_local_interfaces() {
+        use crate::cli::verbosity::Verbosity;
+        let rules = generate(
+            &snapshot(),
+            &targets(&["203.0.113.1"]),
+            &opts(false, true),
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains("pass from $int_en0:network to $int_en0:network"));
+        assert!(!rules.contains("<local_nets>"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_generate_local_subnets() {
+        use crate::cli::verbosity::Verbosity;
+        use crate::killswitch::network::Route;
+        let mut snapshot = snapshot();
+        snapshot.routes = vec![Route {
+            destination: "default".to_string(),
+            gateway: "192.168.1.1".to_string(),
+            flags: "UGScg".to_string(),
+            interface: "en0".to_string(),
+        }];
+        let options = Options {
+            // On-link LAN and a routed printer VLAN
+            local: Some(vec![
+                "192.168.1.0/24".to_string(),
+                "192.168.20.0/24".to_string(),
+            ]),
+            exclude_gateway: true,
+            ..Options::default()
+        };
+        let rules = generate(
+            &snapshot,
+            &targets(&["203.0.113.1"]),
+            &options,
+            Verbosity::Normal,
+        )
+        .unwrap()
+        .to_string();
+        assert!(rules.contains(
+            "table <local_nets> persist { 192.168.1.0/24, 192.168.20.0/24, !192.168.1.1 }"
+        ));
+        assert!(rules.contains("pass out on $int_en0 from any to <local_nets> keep state"));
+        // Inbound from the local networks only through --inbound ports
+        assert!(!rules.contains("pass in on $int_en0 from <local_nets> to any"));
+        assert!(!rules.contains(":network"));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_local_networks_validation() {
+        let snapshot = snapshot();
+        let local = |subnets: &[&str], exclude_gateway| Options {
+            local: Some(subnets.iter().map(ToString::to_string).collect()),
+            exclude_gateway,
+            ..Options::default()
+        };
+
+        // Public networks are not local
+        assert!(local_networks(&snapshot, &local(&["203.0.113.0/24"], false)).is_err());
+        // 10.0.0.0/7 spans public addresses
+        assert!(local_networks(&snapshot, &local(&["10.0.0.0/7"], false)).is_err());
+        assert!(local_networks(&snapshot, &local(&["printer"], false)).is_err());
+        assert!(local_networks(&snapshot, &local(&["!router"], false)).is_err());
+        // The interface prefix is normalized
+        assert_eq!(
+            local_networks(
+                &snapshot,
+                &local(&["192.168.1.7/24", "!192.168.1.1"], false)
+            )
+            .unwrap(),
+            LocalNets::Table(vec![
+                "192.168.1.0/24".to_string(),
+                "!192.168.1.1".to_string()
+            ])
+        );
+        // Exclusions only: the interface networks
+        assert_eq!(
+            local_networks(&snapshot, &local(&["!192.168.1.1"], false)).unwrap(),
+            LocalNets::Table(vec![
+                "192.168.1.0/24".to_string(),
+                "!192.168.1.1".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_generate_requires_peer() {
+        use crate::cli::verbosity::Verbosity;
+        assert!(
+            generate(
+                &snapshot(),
+                &targets(&[]),
+                &opts(false, false),
+                Verbosity::Normal
+            )
+            .is_err()
+        );
+    }
+
     #[test]
     fn test_hex_to_cidr() {
         assert_eq!(hex_to_cidr("0xffffff00"), Some(24));
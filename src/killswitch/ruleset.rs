@@ -0,0 +1,901 @@
+//! Typed model of a pf ruleset.
+//!
+//! The generator builds a [`Ruleset`] instead of concatenating strings, so
+//! rules can be inspected, transformed and compared structurally before
+//! being rendered to pf syntax with [`std::fmt::Display`].
+
+use std::fmt;
+
+// ============================================================================
+// Ruleset
+// ============================================================================
+
+/// An ordered list of pf statements.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ruleset {
+    statements: Vec<Statement>,
+}
+
+impl Ruleset {
+    /// Create an empty ruleset.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a statement.
+    pub fn push(&mut self, statement: impl Into<Statement>) {
+        self.statements.push(statement.into());
+    }
+
+    /// Append a `# comment` line.
+    pub fn comment(&mut self, text: impl Into<String>) {
+        self.statements.push(Statement::Comment(text.into()));
+    }
+
+    /// Append an empty line.
+    pub fn blank(&mut self) {
+        self.statements.push(Statement::Blank);
+    }
+
+    /// All statements in order, including comments and blank lines.
+    #[must_use]
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    /// Mutable access to the statements, for post-processing.
+    pub fn statements_mut(&mut self) -> &mut Vec<Statement> {
+        &mut self.statements
+    }
+
+    /// Macro definitions in order.
+    pub fn macros(&self) -> impl Iterator<Item = &Macro> {
+        self.statements.iter().filter_map(|s| match s {
+            Statement::Macro(m) => Some(m),
+            _ => None,
+        })
+    }
+
+    /// Look up a macro by name.
+    #[must_use]
+    pub fn macro_value(&self, name: &str) -> Option<&str> {
+        self.macros()
+            .find(|m| m.name == name)
+            .map(|m| m.value.as_str())
+    }
+
+    /// Global `set` options in order.
+    pub fn options(&self) -> impl Iterator<Item = &PfOption> {
+        self.statements.iter().filter_map(|s| match s {
+            Statement::Option(o) => Some(o),
+            _ => None,
+        })
+    }
+
+    /// Table definitions in order.
+    pub fn tables(&self) -> impl Iterator<Item = &Table> {
+        self.statements.iter().filter_map(|s| match s {
+            Statement::Table(t) => Some(t),
+            _ => None,
+        })
+    }
+
+    /// Look up a table by name.
+    #[must_use]
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables().find(|t| t.name == name)
+    }
+
+    /// Filter rules in evaluation order.
+    pub fn rules(&self) -> impl Iterator<Item = &Rule> {
+        self.statements.iter().filter_map(|s| match s {
+            Statement::Rule(r) => Some(r),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for Ruleset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for statement in &self.statements {
+            writeln!(f, "{statement}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A single line of a pf ruleset.
+// Rules are most of the statements, boxing them would not save anything
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Comment(String),
+    Blank,
+    Macro(Macro),
+    Option(PfOption),
+    Table(Table),
+    Rule(Rule),
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Comment(text) if text.is_empty() => write!(f, "#"),
+            Self::Comment(text) => write!(f, "# {text}"),
+            Self::Blank => Ok(()),
+            Self::Macro(m) => m.fmt(f),
+            Self::Option(o) => o.fmt(f),
+            Self::Table(t) => t.fmt(f),
+            Self::Rule(r) => r.fmt(f),
+        }
+    }
+}
+
+impl From<Macro> for Statement {
+    fn from(m: Macro) -> Self {
+        Self::Macro(m)
+    }
+}
+
+impl From<PfOption> for Statement {
+    fn from(o: PfOption) -> Self {
+        Self::Option(o)
+    }
+}
+
+impl From<Table> for Statement {
+    fn from(t: Table) -> Self {
+        Self::Table(t)
+    }
+}
+
+impl From<Rule> for Statement {
+    fn from(r: Rule) -> Self {
+        Self::Rule(r)
+    }
+}
+
+// ============================================================================
+// Macros
+// ============================================================================
+
+/// A macro definition: `name = "value"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Macro {
+    pub name: String,
+    pub value: String,
+}
+
+impl Macro {
+    /// Create a macro, sanitizing the name into a valid pf identifier.
+    #[must_use]
+    pub fn new(name: &str, value: impl Into<String>) -> Self {
+        Self {
+            name: sanitize_identifier(name),
+            value: value.into(),
+        }
+    }
+
+    /// Create an interface macro such as `int_en0 = "en0"`.
+    #[must_use]
+    pub fn interface(prefix: &str, iface: &str) -> Self {
+        Self {
+            name: macro_name(prefix, iface),
+            value: iface.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Macro {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = \"{}\"", self.name, self.value)
+    }
+}
+
+/// Build the macro name for an interface, e.g. `("int", "en0")` -> `int_en0`.
+///
+/// Interface names such as `vlan.2` or `ipsec-0` are not valid pf
+/// identifiers, so any character other than ASCII letters, digits and `_`
+/// is replaced with `_`.
+#[must_use]
+pub fn macro_name(prefix: &str, iface: &str) -> String {
+    sanitize_identifier(&format!("{prefix}_{iface}"))
+}
+
+/// Turn an arbitrary string into a valid pf macro/table identifier.
+///
+/// pf identifiers must start with a letter and contain only letters, digits
+/// and underscores.
+#[must_use]
+pub fn sanitize_identifier(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !out.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        out.insert(0, 'x');
+    }
+    out
+}
+
+// ============================================================================
+// Options
+// ============================================================================
+
+/// A global `set` option.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PfOption {
+    BlockPolicy(BlockPolicy),
+    RulesetOptimization(Optimization),
+    Skip(String),
+}
+
+impl fmt::Display for PfOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BlockPolicy(p) => write!(f, "set block-policy {p}"),
+            Self::RulesetOptimization(o) => write!(f, "set ruleset-optimization {o}"),
+            Self::Skip(iface) => write!(f, "set skip on {iface}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockPolicy {
+    Drop,
+    Return,
+}
+
+impl fmt::Display for BlockPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Drop => "drop",
+            Self::Return => "return",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Optimization {
+    None,
+    Basic,
+    Profile,
+}
+
+impl fmt::Display for Optimization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Basic => "basic",
+            Self::Profile => "profile",
+        })
+    }
+}
+
+// ============================================================================
+// Tables
+// ============================================================================
+
+/// A table definition: `table <name> persist { addr, ... }`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub name: String,
+    pub persist: bool,
+    pub addresses: Vec<String>,
+}
+
+impl Table {
+    /// Create a persistent table with the given addresses.
+    #[must_use]
+    pub fn persist(name: &str, addresses: Vec<String>) -> Self {
+        Self {
+            name: sanitize_identifier(name),
+            persist: true,
+            addresses,
+        }
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "table <{}>", self.name)?;
+        if self.persist {
+            f.write_str(" persist")?;
+        }
+        if !self.addresses.is_empty() {
+            write!(f, " {{ {} }}", self.addresses.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Filter rules
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Pass,
+    Block,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pass => "pass",
+            Self::Block => "block",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::In => "in",
+            Self::Out => "out",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    Inet,
+    Inet6,
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Inet => "inet",
+            Self::Inet6 => "inet6",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    Icmp,
+    Icmp6,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+            Self::Icmp => "icmp",
+            Self::Icmp6 => "ipv6-icmp",
+        })
+    }
+}
+
+/// The interface a rule applies to (`on ...`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Interface {
+    /// A literal interface name, e.g. `en0`.
+    Name(String),
+    /// A macro reference, e.g. `$int_en0`.
+    Macro(String),
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => f.write_str(name),
+            Self::Macro(name) => write!(f, "${name}"),
+        }
+    }
+}
+
+/// A source or destination address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    Any,
+    /// An address or CIDR network, e.g. `203.0.113.1` or `224.0.0.0/4`.
+    Address(String),
+    /// A macro reference, e.g. `$vpn_ip`.
+    Macro(String),
+    /// A table reference, e.g. `<vpn_peers>`.
+    Table(String),
+    /// The network attached to an interface macro, e.g. `$int_en0:network`.
+    Network(String),
+    /// A negated host, e.g. `! 192.168.1.1`.
+    Not(Box<Host>),
+    /// A list of hosts, e.g. `{ 10.0.0.0/8, 172.16.0.0/12 }`.
+    List(Vec<Host>),
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Any => f.write_str("any"),
+            Self::Address(addr) => f.write_str(addr),
+            Self::Macro(name) => write!(f, "${name}"),
+            Self::Table(name) => write!(f, "<{name}>"),
+            Self::Network(name) => write!(f, "${name}:network"),
+            Self::Not(host) => write!(f, "! {host}"),
+            Self::List(hosts) => {
+                let hosts: Vec<String> = hosts.iter().map(ToString::to_string).collect();
+                write!(f, "{{ {} }}", hosts.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Port {
+    Single(u16),
+    Range(u16, u16),
+    /// `{53, 853}`
+    List(Vec<u16>),
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Single(port) => write!(f, "{port}"),
+            Self::Range(low, high) => write!(f, "{low}:{high}"),
+            Self::List(ports) => {
+                let ports: Vec<String> = ports.iter().map(ToString::to_string).collect();
+                write!(f, "{{{}}}", ports.join(", "))
+            }
+        }
+    }
+}
+
+/// One side of a rule: `from <host> [port <port>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: Host,
+    pub port: Option<Port>,
+}
+
+impl Endpoint {
+    /// `any` without a port.
+    #[must_use]
+    pub const fn any() -> Self {
+        Self {
+            host: Host::Any,
+            port: None,
+        }
+    }
+
+    fn is_any(&self) -> bool {
+        self.host == Host::Any && self.port.is_none()
+    }
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self::any()
+    }
+}
+
+impl From<Host> for Endpoint {
+    fn from(host: Host) -> Self {
+        Self { host, port: None }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.host)?;
+        if let Some(port) = &self.port {
+            write!(f, " port {port}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An ICMP type match: `icmp-type <type> [code <code>]`, or `icmp6-type`
+/// for `ICMPv6` rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcmpType {
+    /// Numeric type or pf name (e.g. `8` or `echoreq`).
+    pub name: String,
+    pub code: Option<u8>,
+}
+
+impl fmt::Display for IcmpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if let Some(code) = self.code {
+            write!(f, " code {code}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A filter rule.
+///
+/// Rendered in pf order:
+/// `action [direction] [log] [quick] [on iface] [route-to iface] [af] [proto] from .. to ..
+/// [user ..] [group ..] [icmp-type ..] [keep state]`. When both endpoints are `any` without ports
+/// the rule is rendered with `all`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub direction: Option<Direction>,
+    pub log: bool,
+    pub quick: bool,
+    pub interface: Option<Interface>,
+    /// Interface the matching packets are routed to (`route-to`)
+    pub route_to: Option<Interface>,
+    pub family: Option<AddressFamily>,
+    pub protocols: Vec<Protocol>,
+    pub from: Endpoint,
+    pub to: Endpoint,
+    /// UIDs owning the socket (TCP and UDP only)
+    pub users: Vec<u32>,
+    /// GIDs owning the socket (TCP and UDP only)
+    pub groups: Vec<u32>,
+    pub icmp_types: Vec<IcmpType>,
+    pub keep_state: bool,
+}
+
+impl Rule {
+    fn new(action: Action) -> Self {
+        Self {
+            action,
+            direction: None,
+            log: false,
+            quick: false,
+            interface: None,
+            route_to: None,
+            family: None,
+            protocols: Vec::new(),
+            from: Endpoint::any(),
+            to: Endpoint::any(),
+            users: Vec::new(),
+            groups: Vec::new(),
+            icmp_types: Vec::new(),
+            keep_state: false,
+        }
+    }
+
+    /// Start a `pass` rule matching everything.
+    #[must_use]
+    pub fn pass() -> Self {
+        Self::new(Action::Pass)
+    }
+
+    /// Start a `block` rule matching everything.
+    #[must_use]
+    pub fn block() -> Self {
+        Self::new(Action::Block)
+    }
+
+    #[must_use]
+    pub const fn direction(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    #[must_use]
+    pub const fn log(mut self) -> Self {
+        self.log = true;
+        self
+    }
+
+    #[must_use]
+    pub const fn quick(mut self) -> Self {
+        self.quick = true;
+        self
+    }
+
+    /// Restrict the rule to the interface behind the given macro.
+    #[must_use]
+    pub fn on(mut self, macro_name: &str) -> Self {
+        self.interface = Some(Interface::Macro(macro_name.to_string()));
+        self
+    }
+
+    /// Restrict the rule to a literal interface name.
+    #[must_use]
+    pub fn on_interface(mut self, name: &str) -> Self {
+        self.interface = Some(Interface::Name(name.to_string()));
+        self
+    }
+
+    /// Route the matching packets to the interface behind the given macro.
+    #[must_use]
+    pub fn route_to(mut self, macro_name: &str) -> Self {
+        self.route_to = Some(Interface::Macro(macro_name.to_string()));
+        self
+    }
+
+    #[must_use]
+    pub const fn family(mut self, family: AddressFamily) -> Self {
+        self.family = Some(family);
+        self
+    }
+
+    #[must_use]
+    pub fn proto(mut self, protocols: &[Protocol]) -> Self {
+        self.protocols = protocols.to_vec();
+        self
+    }
+
+    #[must_use]
+    pub fn from(mut self, from: impl Into<Endpoint>) -> Self {
+        self.from = from.into();
+        self
+    }
+
+    #[must_use]
+    pub fn to(mut self, to: impl Into<Endpoint>) -> Self {
+        self.to = to.into();
+        self
+    }
+
+    /// Only match sockets owned by these UIDs.
+    #[must_use]
+    pub fn user(mut self, uids: &[u32]) -> Self {
+        self.users = uids.to_vec();
+        self
+    }
+
+    /// Only match sockets owned by these GIDs.
+    #[must_use]
+    pub fn group(mut self, gids: &[u32]) -> Self {
+        self.groups = gids.to_vec();
+        self
+    }
+
+    /// Add an ICMP type to match; several types render as a list.
+    #[must_use]
+    pub fn icmp_type(mut self, name: &str, code: Option<u8>) -> Self {
+        self.icmp_types.push(IcmpType {
+            name: name.to_string(),
+            code,
+        });
+        self
+    }
+
+    #[must_use]
+    pub const fn keep_state(mut self) -> Self {
+        self.keep_state = true;
+        self
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.action)?;
+        if let Some(direction) = self.direction {
+            write!(f, " {direction}")?;
+        }
+        if self.log {
+            f.write_str(" log")?;
+        }
+        if self.quick {
+            f.write_str(" quick")?;
+        }
+        if let Some(iface) = &self.interface {
+            write!(f, " on {iface}")?;
+        }
+        if let Some(iface) = &self.route_to {
+            write!(f, " route-to {iface}")?;
+        }
+        if let Some(family) = self.family {
+            write!(f, " {family}")?;
+        }
+        match self.protocols.as_slice() {
+            [] => {}
+            [proto] => write!(f, " proto {proto}")?,
+            protos => {
+                let list: Vec<String> = protos.iter().map(ToString::to_string).collect();
+                write!(f, " proto {{{}}}", list.join(", "))?;
+            }
+        }
+        if self.from.is_any() && self.to.is_any() {
+            f.write_str(" all")?;
+        } else {
+            write!(f, " from {} to {}", self.from, self.to)?;
+        }
+        for (keyword, ids) in [("user", &self.users), ("group", &self.groups)] {
+            match ids.as_slice() {
+                [] => {}
+                [id] => write!(f, " {keyword} {id}")?,
+                ids => {
+                    let list: Vec<String> = ids.iter().map(ToString::to_string).collect();
+                    write!(f, " {keyword} {{ {} }}", list.join(", "))?;
+                }
+            }
+        }
+        let keyword = if self.protocols.contains(&Protocol::Icmp6) {
+            "icmp6-type"
+        } else {
+            "icmp-type"
+        };
+        match self.icmp_types.as_slice() {
+            [] => {}
+            [icmp] => write!(f, " {keyword} {icmp}")?,
+            types => {
+                let list: Vec<String> = types.iter().map(ToString::to_string).collect();
+                write!(f, " {keyword} {{{}}}", list.join(", "))?;
+            }
+        }
+        if self.keep_state {
+            f.write_str(" keep state")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_name_sanitizes_interface() {
+        assert_eq!(macro_name("int", "en0"), "int_en0");
+        assert_eq!(macro_name("int", "vlan.2"), "int_vlan_2");
+        assert_eq!(macro_name("vpn", "ipsec-0"), "vpn_ipsec_0");
+        assert_eq!(sanitize_identifier("0bad"), "x0bad");
+    }
+
+    #[test]
+    fn test_macro_render() {
+        let m = Macro::interface("int", "vlan.2");
+        assert_eq!(m.to_string(), "int_vlan_2 = \"vlan.2\"");
+    }
+
+    #[test]
+    fn test_table_render() {
+        let t = Table::persist(
+            "vpn_peers",
+            vec!["203.0.113.1".into(), "2001:db8::1".into()],
+        );
+        assert_eq!(
+            t.to_string(),
+            "table <vpn_peers> persist { 203.0.113.1, 2001:db8::1 }"
+        );
+        let empty = Table::persist("allow", Vec::new());
+        assert_eq!(empty.to_string(), "table <allow> persist");
+    }
+
+    #[test]
+    fn test_rule_render_all() {
+        assert_eq!(Rule::block().to_string(), "block all");
+        assert_eq!(
+            Rule::block()
+                .direction(Direction::Out)
+                .family(AddressFamily::Inet6)
+                .to_string(),
+            "block out inet6 all"
+        );
+        assert_eq!(
+            Rule::pass().on("vpn_utun3").to_string(),
+            "pass on $vpn_utun3 all"
+        );
+    }
+
+    #[test]
+    fn test_rule_render_full() {
+        let rule = Rule::pass()
+            .on("int_en0")
+            .proto(&[Protocol::Tcp, Protocol::Udp])
+            .from(Endpoint {
+                host: Host::Any,
+                port: Some(Port::Range(67, 68)),
+            })
+            .to(Endpoint {
+                host: Host::Any,
+                port: Some(Port::Range(67, 68)),
+            })
+            .keep_state();
+        assert_eq!(
+            rule.to_string(),
+            "pass on $int_en0 proto {tcp, udp} from any port 67:68 to any port 67:68 keep state"
+        );
+
+        let ping = Rule::pass()
+            .on("int_en0")
+            .family(AddressFamily::Inet)
+            .proto(&[Protocol::Icmp])
+            .icmp_type("8", Some(0))
+            .keep_state();
+        assert_eq!(
+            ping.to_string(),
+            "pass on $int_en0 inet proto icmp all icmp-type 8 code 0 keep state"
+        );
+
+        let nd = Rule::pass()
+            .on("int_en0")
+            .family(AddressFamily::Inet6)
+            .proto(&[Protocol::Icmp6])
+            .icmp_type("neighbrsol", None)
+            .icmp_type("neighbradv", None);
+        assert_eq!(
+            nd.to_string(),
+            "pass on $int_en0 inet6 proto ipv6-icmp all icmp6-type {neighbrsol, neighbradv}"
+        );
+
+        // Each type of a list keeps its code
+        let unreachable = Rule::pass()
+            .family(AddressFamily::Inet)
+            .proto(&[Protocol::Icmp])
+            .icmp_type("3", Some(4))
+            .icmp_type("11", None);
+        assert_eq!(
+            unreachable.to_string(),
+            "pass inet proto icmp all icmp-type {3 code 4, 11}"
+        );
+
+        let dns = Rule::pass().quick().proto(&[Protocol::Udp]).to(Endpoint {
+            host: Host::Any,
+            port: Some(Port::Single(53)),
+        });
+        assert_eq!(
+            dns.to_string(),
+            "pass quick proto udp from any to any port 53"
+        );
+
+        let dot = Rule::block().quick().proto(&[Protocol::Tcp]).to(Endpoint {
+            host: Host::Any,
+            port: Some(Port::List(vec![53, 853])),
+        });
+        assert_eq!(
+            dot.to_string(),
+            "block quick proto tcp from any to any port {53, 853}"
+        );
+
+        let local = Rule::pass()
+            .from(Host::Network("int_en0".into()))
+            .to(Host::Table("local".into()));
+        assert_eq!(local.to_string(), "pass from $int_en0:network to <local>");
+
+        let routed = Rule::pass()
+            .direction(Direction::In)
+            .on("br_bridge100")
+            .route_to("vpn_utun3")
+            .from(Host::Network("br_bridge100".into()))
+            .keep_state();
+        assert_eq!(
+            routed.to_string(),
+            "pass in on $br_bridge100 route-to $vpn_utun3 from $br_bridge100:network to any keep state"
+        );
+
+        let owned = Rule::pass()
+            .direction(Direction::Out)
+            .on("int_en0")
+            .proto(&[Protocol::Tcp, Protocol::Udp])
+            .user(&[501, 502])
+            .group(&[20])
+            .keep_state();
+        assert_eq!(
+            owned.to_string(),
+            "pass out on $int_en0 proto {tcp, udp} all user { 501, 502 } group 20 keep state"
+        );
+    }
+
+    #[test]
+    fn test_ruleset_accessors_and_render() {
+        let mut rs = Ruleset::new();
+        rs.comment("header");
+        rs.push(Macro::interface("int", "en0"));
+        rs.blank();
+        rs.push(PfOption::BlockPolicy(BlockPolicy::Drop));
+        rs.push(PfOption::Skip("lo0".into()));
+        rs.push(Rule::block());
+        rs.push(Rule::pass().on("int_en0").to(Host::Macro("vpn_ip".into())));
+
+        assert_eq!(rs.macro_value("int_en0"), Some("en0"));
+        assert_eq!(rs.options().count(), 2);
+        assert_eq!(rs.rules().count(), 2);
+        assert_eq!(
+            rs.to_string(),
+            "# header\nint_en0 = \"en0\"\n\nset block-policy drop\nset skip on lo0\nblock all\npass on $int_en0 from any to $vpn_ip\n"
+        );
+    }
+}
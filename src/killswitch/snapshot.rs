@@ -0,0 +1,163 @@
+//! Serializable snapshot of the network state used to generate rules.
+//!
+//! A snapshot can be captured on one machine with `killswitch snapshot`,
+//! saved as JSON and fed back to the rule generator elsewhere, so rulesets
+//! can be generated and reviewed without a live `ifconfig`.
+
+use crate::cli::verbosity::Verbosity;
+use crate::killswitch::PeerProto;
+use crate::killswitch::network::{self, DnsResolver, InterfaceInfo, Route};
+use anyhow::{Context, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Network state relevant to the kill switch.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkSnapshot {
+    /// When the snapshot was captured (RFC 3339, empty for synthetic snapshots).
+    #[serde(default)]
+    pub captured_at: String,
+    pub interfaces: Vec<InterfaceInfo>,
+    /// Detected VPN peer addresses.
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Port the tunnel uses to reach the peers, when detection reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_port: Option<u16>,
+    /// Transport protocol of the tunnel, when detection reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_proto: Option<PeerProto>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(default)]
+    pub dns: Vec<DnsResolver>,
+}
+
+impl NetworkSnapshot {
+    /// Capture the current network state of this machine.
+    ///
+    /// Only interface discovery is mandatory; peers, routes and DNS are left
+    /// empty when they cannot be detected.
+    ///
+    /// # Errors
+    /// Returns an error if the interfaces cannot be read.
+    pub fn capture(verbose: Verbosity) -> Result<Self> {
+        let interfaces = network::get_interfaces()?;
+
+        let endpoints = match network::detect_vpn_peers(verbose) {
+            Ok(peers) => peers,
+            Err(e) => {
+                if verbose.is_debug() {
+                    eprintln!("  No VPN peer detected: {e}");
+                }
+                Vec::new()
+            }
+        };
+
+        let routes = network::get_routes().unwrap_or_else(|e| {
+            if verbose.is_debug() {
+                eprintln!("  Could not read routing table: {e}");
+            }
+            Vec::new()
+        });
+
+        let dns = network::get_dns_resolvers().unwrap_or_else(|e| {
+            if verbose.is_debug() {
+                eprintln!("  Could not read DNS configuration: {e}");
+            }
+            Vec::new()
+        });
+
+        // The first peer reporting a port describes the tunnel
+        let tunnel = endpoints.iter().find(|p| p.port.is_some());
+
+        Ok(Self {
+            captured_at: Local::now().to_rfc3339(),
+            interfaces,
+            peer_port: tunnel.and_then(|p| p.port),
+            peer_proto: tunnel.and_then(|p| p.proto),
+            peers: endpoints.iter().map(|p| p.address.clone()).collect(),
+            routes,
+            dns,
+        })
+    }
+
+    /// Load a snapshot from a JSON file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or is not a valid snapshot.
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        Self::from_json(&json)
+    }
+
+    /// Parse a snapshot from JSON.
+    ///
+    /// # Errors
+    /// Returns an error if the JSON is not a valid snapshot.
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("Invalid snapshot JSON")
+    }
+
+    /// Serialize the snapshot as pretty-printed JSON.
+    ///
+    /// # Errors
+    /// Returns an error if serialization fails.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize snapshot")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_snapshot_json_roundtrip() {
+        let snapshot = NetworkSnapshot {
+            captured_at: String::new(),
+            interfaces: vec![
+                InterfaceInfo::new("en0", "a4:83:e7:00:00:01", "192.168.1.100/24", false),
+                InterfaceInfo::new("utun3", "", "10.8.0.2", true),
+            ],
+            peers: vec!["203.0.113.1".to_string()],
+            peer_port: Some(51820),
+            peer_proto: Some(PeerProto::Udp),
+            routes: vec![Route {
+                destination: "203.0.113.1".to_string(),
+                gateway: "192.168.1.1".to_string(),
+                flags: "UGSH".to_string(),
+                interface: "en0".to_string(),
+            }],
+            dns: vec![DnsResolver {
+                address: "10.8.0.1".to_string(),
+                interface: Some("utun3".to_string()),
+            }],
+        };
+
+        let json = snapshot.to_json().unwrap();
+        assert!(json.contains("\"peer_proto\": \"udp\""));
+        assert_eq!(NetworkSnapshot::from_json(&json).unwrap(), snapshot);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_snapshot_optional_fields() {
+        let json =
+            r#"{"interfaces":[{"name":"en0","mac":"","ip":"192.168.1.2/24","is_p2p":false}]}"#;
+        let snapshot = NetworkSnapshot::from_json(json).unwrap();
+        assert_eq!(snapshot.interfaces.len(), 1);
+        assert!(snapshot.peers.is_empty());
+        assert_eq!(snapshot.peer_port, None);
+        assert!(snapshot.dns.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_invalid_json() {
+        assert!(NetworkSnapshot::from_json("{").is_err());
+    }
+}
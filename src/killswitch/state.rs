@@ -0,0 +1,97 @@
+//! Settings of the active kill switch.
+//!
+//! `enable` records the options it was given so later commands (`refresh`)
+//! can rebuild the same peer set without repeating the command line.
+
+use crate::killswitch::Options;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Kept in a root-only directory: `refresh` trusts its content while
+/// running as root.
+const STATE_PATH: &str = "/var/run/killswitch.json";
+
+/// What was applied by the last `enable`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    pub options: Options,
+    /// Peer addresses currently loaded in the pf table.
+    #[serde(default)]
+    pub peers: Vec<String>,
+}
+
+impl State {
+    /// Load the state of the active kill switch.
+    ///
+    /// # Errors
+    /// Returns an error if the kill switch is not enabled or the state is invalid.
+    pub fn load() -> Result<Self> {
+        Self::load_from(Path::new(STATE_PATH))
+    }
+
+    fn load_from(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).with_context(|| {
+            format!(
+                "Failed to read {}, is the kill switch enabled?",
+                path.display()
+            )
+        })?;
+        serde_json::from_str(&json).context("Invalid kill switch state")
+    }
+
+    /// Save the state of the active kill switch.
+    ///
+    /// # Errors
+    /// Returns an error if the state cannot be written.
+    pub fn save(&self) -> Result<()> {
+        self.save_to(Path::new(STATE_PATH))
+    }
+
+    fn save_to(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize state")?;
+        fs::write(path, json + "\n").with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Forget the state once the kill switch is disabled.
+    ///
+    /// # Errors
+    /// Returns an error if the state file exists but cannot be removed.
+    pub fn remove() -> Result<()> {
+        let path = Path::new(STATE_PATH);
+        if path.exists() {
+            fs::remove_file(path).context("Failed to remove kill switch state")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn test_state_roundtrip() {
+        let path =
+            std::env::temp_dir().join(format!("killswitch-state-{}.json", std::process::id()));
+        let state = State {
+            options: Options {
+                peers: vec!["nl.vpn.example".to_string()],
+                peer_port: Some(51820),
+                ..Options::default()
+            },
+            peers: vec!["203.0.113.1".to_string(), "2001:db8::1".to_string()],
+        };
+        state.save_to(&path).unwrap();
+        let loaded = State::load_from(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), state);
+    }
+
+    #[test]
+    fn test_state_missing() {
+        assert!(State::load_from(Path::new("/nonexistent/killswitch.json")).is_err());
+    }
+}